@@ -0,0 +1,7 @@
+-- Add migration script here
+CREATE TYPE user_role AS ENUM ('user', 'admin');
+
+ALTER TABLE users ADD COLUMN role user_role NOT NULL DEFAULT 'user';
+ALTER TABLE users ADD COLUMN disabled BOOLEAN NOT NULL DEFAULT FALSE;
+
+ALTER TABLE urls ADD COLUMN taken_down BOOLEAN NOT NULL DEFAULT FALSE;
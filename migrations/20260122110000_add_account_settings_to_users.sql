@@ -0,0 +1,8 @@
+-- Add migration script here
+-- Email changes wait here until the new address is confirmed
+ALTER TABLE users ADD COLUMN pending_email TEXT;
+ALTER TABLE users ADD COLUMN email_change_token TEXT UNIQUE;
+ALTER TABLE users ADD COLUMN email_change_expires_at TIMESTAMPTZ;
+
+-- Bumped to invalidate every session issued before a credential change
+ALTER TABLE users ADD COLUMN session_version INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,10 @@
+-- Add migration script here
+-- Proof that a personal data erasure happened. Holds no personal data itself,
+-- user_id has no foreign key because the user row is gone.
+CREATE TABLE account_erasures (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL,
+    links_deleted INTEGER NOT NULL,
+    links_anonymized INTEGER NOT NULL,
+    erased_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,36 @@
+-- Limits are NULL when the plan does not cap them
+CREATE TABLE plans (
+    id TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    max_active_links INTEGER,
+    monthly_redirects BIGINT,
+    max_link_ttl_days INTEGER,
+    custom_aliases BOOLEAN NOT NULL DEFAULT FALSE,
+    analytics_retention_days INTEGER,
+    position INTEGER NOT NULL DEFAULT 0
+);
+
+INSERT INTO plans (id, name, max_active_links, monthly_redirects, max_link_ttl_days, custom_aliases, analytics_retention_days, position) VALUES
+    ('free', 'Free', 50, 10000, 30, FALSE, 30, 0),
+    ('pro', 'Pro', 1000, 500000, 365, TRUE, 365, 1),
+    ('business', 'Business', NULL, NULL, NULL, TRUE, NULL, 2);
+
+ALTER TABLE users ADD COLUMN plan_id TEXT NOT NULL DEFAULT 'free' REFERENCES plans(id);
+
+-- Redirects served for each account's links, counted per calendar month
+CREATE TABLE redirect_usage (
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    month DATE NOT NULL,
+    redirects BIGINT NOT NULL DEFAULT 0,
+    PRIMARY KEY (user_id, month)
+);
+
+-- One row per redirect, kept as long as the owner's plan retains analytics
+CREATE TABLE click_events (
+    id BIGSERIAL PRIMARY KEY,
+    url_id UUID NOT NULL REFERENCES urls(id) ON DELETE CASCADE,
+    clicked_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_click_events_url_id ON click_events (url_id, clicked_at);
+CREATE INDEX idx_urls_user_id ON urls (user_id);
@@ -0,0 +1,14 @@
+CREATE TYPE usage_metric AS ENUM ('redirect', 'link_created');
+
+-- Billable usage per account and hour. A row only grows while its hour is
+-- open and is never changed afterwards, so exports of closed hours are stable.
+-- There is no foreign key: usage stays billable after the account is deleted.
+CREATE TABLE usage_hourly (
+    user_id UUID NOT NULL,
+    hour TIMESTAMPTZ NOT NULL,
+    metric usage_metric NOT NULL,
+    quantity BIGINT NOT NULL,
+    PRIMARY KEY (user_id, hour, metric)
+);
+
+CREATE INDEX idx_usage_hourly_hour ON usage_hourly (hour);
@@ -0,0 +1,24 @@
+-- Search, sort and page the link listing
+
+-- Most recent redirect of a link, for sorting by last click
+ALTER TABLE urls ADD COLUMN last_clicked_at TIMESTAMPTZ;
+
+UPDATE urls u
+SET last_clicked_at = e.last_clicked_at
+FROM (
+    SELECT url_id, MAX(clicked_at) AS last_clicked_at
+    FROM click_events
+    GROUP BY url_id
+) e
+WHERE e.url_id = u.id;
+
+-- Free text search matches substrings of the short code, site name and destination
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+CREATE INDEX idx_urls_search ON urls
+    USING GIN ((short_code || ' ' || COALESCE(site_name, '') || ' ' || long_url) gin_trgm_ops);
+
+-- Keyset pagination within a workspace, id breaks ties
+CREATE INDEX idx_urls_workspace_created ON urls (workspace_id, created_at, id);
+CREATE INDEX idx_urls_workspace_clicks ON urls (workspace_id, clicks, id);
+CREATE INDEX idx_urls_workspace_last_click ON urls (workspace_id, (COALESCE(last_clicked_at, 'epoch'::timestamptz)), id);
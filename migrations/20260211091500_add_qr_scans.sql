@@ -0,0 +1,8 @@
+-- Tell QR code scans apart from other clicks. QR codes encode the short link
+-- with `?src=qr`, redirects carrying it are recorded with the `qr` source.
+CREATE TYPE click_source AS ENUM ('link', 'qr');
+
+ALTER TABLE click_events ADD COLUMN source click_source NOT NULL DEFAULT 'link';
+
+-- Lifetime count, click events are pruned with the plan's analytics retention
+ALTER TABLE urls ADD COLUMN qr_scans INTEGER NOT NULL DEFAULT 0;
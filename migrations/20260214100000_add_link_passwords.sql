@@ -0,0 +1,2 @@
+-- Links that ask for a shared passphrase before redirecting, Argon2 hashed
+ALTER TABLE urls ADD COLUMN password_hash TEXT;
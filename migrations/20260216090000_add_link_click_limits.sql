@@ -0,0 +1,2 @@
+-- Links that stop redirecting after a number of clicks, 1 for one-time links
+ALTER TABLE urls ADD COLUMN max_clicks INT CHECK (max_clicks > 0);
@@ -0,0 +1,5 @@
+-- Links that only go live at a set time. Until then visitors see a waiting
+-- page, or are sent to the fallback url when the link has one.
+ALTER TABLE urls ADD COLUMN starts_at TIMESTAMPTZ;
+ALTER TABLE urls ADD COLUMN fallback_url TEXT;
+ALTER TABLE urls ADD CONSTRAINT urls_window_check CHECK (starts_at < expires_at);
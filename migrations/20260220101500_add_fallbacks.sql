@@ -0,0 +1,7 @@
+-- Account wide fallback for links without one of their own
+ALTER TABLE users ADD COLUMN fallback_url TEXT;
+
+-- Visits sent to the fallback are kept apart from clicks, they never count
+-- toward a click limit or the plan's redirects
+ALTER TABLE click_events ADD COLUMN fallback BOOLEAN NOT NULL DEFAULT false;
+ALTER TABLE urls ADD COLUMN fallback_hits INTEGER NOT NULL DEFAULT 0;
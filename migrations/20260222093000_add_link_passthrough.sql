@@ -0,0 +1,7 @@
+-- Forward what visitors add to a short link. `query_passthrough` merges the
+-- request's query string into the destination, `path_passthrough` appends
+-- whatever follows the short code in the path.
+CREATE TYPE query_passthrough AS ENUM ('off', 'keep', 'override', 'append');
+
+ALTER TABLE urls ADD COLUMN query_passthrough query_passthrough NOT NULL DEFAULT 'off';
+ALTER TABLE urls ADD COLUMN path_passthrough BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,21 @@
+-- UTM parameters are kept apart from the destination so campaigns can be
+-- edited without touching `long_url`, they are added to it on redirect
+ALTER TABLE urls ADD COLUMN utm_source TEXT;
+ALTER TABLE urls ADD COLUMN utm_medium TEXT;
+ALTER TABLE urls ADD COLUMN utm_campaign TEXT;
+ALTER TABLE urls ADD COLUMN utm_term TEXT;
+ALTER TABLE urls ADD COLUMN utm_content TEXT;
+
+-- Named sets of UTM parameters a user fills new links from
+CREATE TABLE utm_presets (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    utm_source TEXT,
+    utm_medium TEXT,
+    utm_campaign TEXT,
+    utm_term TEXT,
+    utm_content TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE (user_id, name)
+);
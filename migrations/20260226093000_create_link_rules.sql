@@ -0,0 +1,20 @@
+-- Targeting rules send some visitors of a link elsewhere than `long_url`.
+-- They are tried by ascending position, the first one whose conditions all
+-- match picks the destination.
+CREATE TYPE target_os AS ENUM ('ios', 'android', 'windows', 'macos', 'linux');
+CREATE TYPE device_class AS ENUM ('mobile', 'tablet', 'desktop');
+
+CREATE TABLE link_rules (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    url_id UUID NOT NULL REFERENCES urls(id) ON DELETE CASCADE,
+    position INTEGER NOT NULL,
+    destination TEXT NOT NULL,
+    os target_os,
+    device device_class,
+    language TEXT,
+    -- Times of day in UTC, a window ending before it starts runs past midnight
+    active_from TIME,
+    active_until TIME,
+    referrer_host TEXT,
+    UNIQUE (url_id, position)
+);
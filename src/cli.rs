@@ -0,0 +1,63 @@
+use std::io::Write;
+
+use anyhow::Context;
+use chrono::NaiveDate;
+use sqlx::postgres::PgPoolOptions;
+
+use crate::{
+    configuration::get_configuration,
+    services::usage::{UsageService, usage_csv},
+    store::usage::UsageRepository,
+};
+
+const USAGE_EXPORT_HELP: &str =
+    "usage: shorty usage-export --from YYYY-MM-DD --to YYYY-MM-DD [--format csv|json]";
+
+/// `shorty usage-export`: write metered usage for a billing period to stdout
+pub async fn usage_export(args: &[String]) -> anyhow::Result<()> {
+    let mut from = None;
+    let mut to = None;
+    let mut format = "csv".to_string();
+
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        let value = args
+            .next()
+            .with_context(|| format!("{} needs a value\n{}", flag, USAGE_EXPORT_HELP))?;
+        match flag.as_str() {
+            "--from" => from = Some(parse_date(value)?),
+            "--to" => to = Some(parse_date(value)?),
+            "--format" => format = value.clone(),
+            _ => anyhow::bail!("unknown option {}\n{}", flag, USAGE_EXPORT_HELP),
+        }
+    }
+    let (Some(from), Some(to)) = (from, to) else {
+        anyhow::bail!(USAGE_EXPORT_HELP);
+    };
+
+    let cfg = get_configuration().context("could not get config")?;
+    let pg_pool = PgPoolOptions::new()
+        .acquire_timeout(std::time::Duration::from_secs(2))
+        .connect_lazy_with(cfg.database.with_db());
+    let export = UsageService::new(UsageRepository::new(pg_pool))
+        .export(from, to)
+        .await?;
+
+    let body = match format.as_str() {
+        "csv" => usage_csv(&export.records)?,
+        "json" => serde_json::to_vec_pretty(&export)?,
+        other => anyhow::bail!("unknown format {}, use csv or json", other),
+    };
+    std::io::stdout().write_all(&body)?;
+    eprintln!(
+        "{} records, complete through {}",
+        export.records.len(),
+        export.complete_through
+    );
+    Ok(())
+}
+
+fn parse_date(value: &str) -> anyhow::Result<NaiveDate> {
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .with_context(|| format!("{} is not a YYYY-MM-DD date", value))
+}
@@ -9,6 +9,12 @@ use sqlx::postgres::{PgConnectOptions, PgSslMode};
 pub struct Settings {
     pub database: DatabaseSettings,
     pub application: ApplicationSettings,
+    pub auth: AuthSettings,
+    pub oidc: Option<OidcSettings>,
+    /// How account emails are delivered, email changes are refused without one
+    pub mailer: Option<MailerSettings>,
+    #[serde(default)]
+    pub rate_limit: RateLimitSettings,
 }
 
 #[derive(serde::Deserialize)]
@@ -17,6 +23,218 @@ pub struct ApplicationSettings {
 
     #[serde(deserialize_with = "deserialize_number_from_string")]
     pub port: u16,
+
+    /// Secret used to sign cookies, must be at least 64 bytes long
+    pub cookie_secret: SecretString,
+
+    /// Address the deployment is reached at, e.g. `https://sho.rt`, written into
+    /// QR codes. Required in production, local runs fall back to `host` and `port`.
+    pub public_url: Option<String>,
+}
+
+impl ApplicationSettings {
+    pub fn public_url(&self) -> String {
+        match &self.public_url {
+            Some(url) => url.trim_end_matches('/').to_string(),
+            None => format!("http://{}:{}", self.host, self.port),
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct AuthSettings {
+    /// Value of the `iss` claim, other services should pin it when verifying
+    pub issuer: String,
+
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub token_ttl_hours: i64,
+
+    /// Signing keys, rotated by giving each one an activation and retirement time
+    pub keys: Vec<JwtKeySettings>,
+
+    /// Turn off email/password login, e.g. when everyone signs in through OIDC
+    #[serde(default = "default_true")]
+    pub password_login_enabled: bool,
+
+    #[serde(default)]
+    pub password_policy: PasswordPolicySettings,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct PasswordPolicySettings {
+    #[serde(default = "default_min_password_length")]
+    pub min_length: usize,
+
+    /// Minimum estimated entropy, based on the character classes used and the length
+    #[serde(default = "default_min_entropy_bits")]
+    pub min_entropy_bits: f64,
+
+    /// Directory of SHA-1 range files (`<first five hex digits>` holding `SUFFIX:COUNT` lines)
+    /// to check passwords against. A small list of common passwords is used when unset.
+    pub breached_hashes_dir: Option<String>,
+}
+
+impl Default for PasswordPolicySettings {
+    fn default() -> Self {
+        Self {
+            min_length: default_min_password_length(),
+            min_entropy_bits: default_min_entropy_bits(),
+            breached_hashes_dir: None,
+        }
+    }
+}
+
+fn default_min_password_length() -> usize {
+    10
+}
+
+fn default_min_entropy_bits() -> f64 {
+    40.0
+}
+
+#[derive(serde::Deserialize, Clone)]
+pub struct OidcSettings {
+    /// Issuer URL, discovery is read from `{issuer_url}/.well-known/openid-configuration`
+    pub issuer_url: String,
+    pub client_id: String,
+    pub client_secret: Option<SecretString>,
+    /// Must point at `/auth/oidc/callback` on this deployment
+    pub redirect_url: String,
+    /// Label for the login button
+    #[serde(default = "default_oidc_display_name")]
+    pub display_name: String,
+    #[serde(default = "default_oidc_scopes")]
+    pub scopes: Vec<String>,
+}
+
+fn default_oidc_display_name() -> String {
+    "Single sign-on".to_string()
+}
+
+fn default_oidc_scopes() -> Vec<String> {
+    vec!["openid".into(), "email".into(), "profile".into()]
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum MailerSettings {
+    /// Write emails to the log, local development only
+    Log,
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct RateLimitSettings {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+
+    /// Take the client address from the right-most `X-Forwarded-For` entry.
+    /// Only enable behind a single proxy that appends to the header.
+    #[serde(default)]
+    pub trust_forwarded_for: bool,
+
+    /// Short link redirects on `/url/{key}`
+    #[serde(default = "default_redirect_limit")]
+    pub redirect: RateLimitPolicySettings,
+    /// Link creation on `/url/shorten` and `/shorten`
+    #[serde(default = "default_shorten_limit")]
+    pub shorten: RateLimitPolicySettings,
+    /// Login, signup, registration and token endpoints
+    #[serde(default = "default_auth_limit")]
+    pub auth: RateLimitPolicySettings,
+}
+
+impl Default for RateLimitSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            trust_forwarded_for: false,
+            redirect: default_redirect_limit(),
+            shorten: default_shorten_limit(),
+            auth: default_auth_limit(),
+        }
+    }
+}
+
+/// What requests are counted together
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitKey {
+    /// The client address
+    Ip,
+    /// The signed-in user, anonymous requests fall back to the address
+    User,
+}
+
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct RateLimitPolicySettings {
+    /// Requests allowed per window
+    pub limit: u32,
+    pub window_secs: u64,
+    pub key: RateLimitKey,
+}
+
+fn default_redirect_limit() -> RateLimitPolicySettings {
+    RateLimitPolicySettings {
+        limit: 300,
+        window_secs: 60,
+        key: RateLimitKey::Ip,
+    }
+}
+
+fn default_shorten_limit() -> RateLimitPolicySettings {
+    RateLimitPolicySettings {
+        limit: 60,
+        window_secs: 60,
+        key: RateLimitKey::User,
+    }
+}
+
+fn default_auth_limit() -> RateLimitPolicySettings {
+    RateLimitPolicySettings {
+        limit: 10,
+        window_secs: 60,
+        key: RateLimitKey::Ip,
+    }
+}
+
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JwtAlgorithm {
+    RS256,
+    EdDSA,
+}
+
+#[derive(serde::Deserialize)]
+pub struct JwtKeySettings {
+    pub kid: String,
+    pub algorithm: JwtAlgorithm,
+
+    /// PKCS#8 PEM private key, either inline or read from a file
+    pub private_key: Option<SecretString>,
+    pub private_key_path: Option<String>,
+
+    /// Start signing with this key from this instant (defaults to immediately)
+    pub active_from: Option<chrono::DateTime<chrono::Utc>>,
+    /// Stop accepting and publishing this key from this instant (defaults to never)
+    pub retire_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl JwtKeySettings {
+    pub fn private_key_pem(&self) -> anyhow::Result<String> {
+        match (&self.private_key, &self.private_key_path) {
+            (Some(pem), _) => Ok(pem.expose_secret().to_string()),
+            (None, Some(path)) => std::fs::read_to_string(path).map_err(|e| {
+                anyhow::anyhow!("could not read key `{}` from {}: {}", self.kid, path, e)
+            }),
+            (None, None) => Err(anyhow::anyhow!(
+                "key `{}` needs either `private_key` or `private_key_path`",
+                self.kid
+            )),
+        }
+    }
 }
 
 #[derive(serde::Deserialize)]
@@ -101,5 +319,20 @@ pub fn get_configuration() -> Result<Settings, config::ConfigError> {
                 .separator("__"), // Use double underscore to represent nested struct fields (e.g., APP_DATABASE__USERNAME)
         );
 
-    settings.build()?.try_deserialize()
+    let settings: Settings = settings.build()?.try_deserialize()?;
+    // Never guess the address from requests, their `Host` is whatever the client sent
+    if matches!(environment, Environment::Production) && settings.application.public_url.is_none() {
+        return Err(config::ConfigError::Message(
+            "application.public_url must be set in production".to_string(),
+        ));
+    }
+    // Confirmation links must not end up in production logs
+    if matches!(environment, Environment::Production)
+        && matches!(settings.mailer, Some(MailerSettings::Log))
+    {
+        return Err(config::ConfigError::Message(
+            "the log mailer is for local development only".to_string(),
+        ));
+    }
+    Ok(settings)
 }
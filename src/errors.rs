@@ -4,8 +4,12 @@ use axum::{
     response::{IntoResponse, Response},
 };
 use serde_json::json;
+use std::collections::BTreeMap;
 use thiserror::Error; // Recommended for clean error definitions
 
+/// Messages for individual form fields, keyed by field name
+pub type FieldErrors = BTreeMap<&'static str, Vec<String>>;
+
 #[derive(Debug, Error)]
 pub enum AuthError {
     #[error("User already exists")]
@@ -23,13 +27,50 @@ pub enum AuthError {
     #[error("Invalid token")]
     InvalidToken,
 
+    #[error("Forbidden")]
+    Forbidden,
+
+    #[error("Not found")]
+    NotFound,
+
+    #[error("Account disabled")]
+    AccountDisabled,
+
+    #[error("Password login disabled")]
+    PasswordLoginDisabled,
+
+    #[error("Email not verified")]
+    EmailNotVerified,
+
+    #[error("Account linked to another OIDC identity")]
+    OidcIdentityConflict,
+
+    #[error("Invalid CSRF token")]
+    InvalidCsrfToken,
+
+    #[error("Sole owner of a shared workspace")]
+    SoleWorkspaceOwner,
+
+    #[error("Rate limited")]
+    RateLimited,
+
+    #[error("Plan limit reached")]
+    PlanLimitReached,
+
+    #[error("No mailer configured")]
+    MailerUnavailable,
+
+    #[error("Validation failed")]
+    Validation(FieldErrors),
+
     #[error("Internal server error")]
     Internal,
 }
 
-impl IntoResponse for AuthError {
-    fn into_response(self) -> Response {
-        let (status, error_message) = match self {
+impl AuthError {
+    /// Status code and the message shown to users, in JSON bodies and flash messages
+    pub fn status_and_message(&self) -> (StatusCode, &'static str) {
+        match self {
             AuthError::UserAlreadyExists => (
                 StatusCode::CONFLICT,
                 "A user with this email already exists",
@@ -43,15 +84,72 @@ impl IntoResponse for AuthError {
                 "Failed to generate session",
             ),
             AuthError::InvalidToken => (StatusCode::UNAUTHORIZED, "Invalid or expired session"),
+            AuthError::Forbidden => (
+                StatusCode::FORBIDDEN,
+                "You do not have permission to access this resource",
+            ),
+            AuthError::NotFound => (StatusCode::NOT_FOUND, "Resource not found"),
+            AuthError::AccountDisabled => (StatusCode::FORBIDDEN, "This account has been disabled"),
+            AuthError::PasswordLoginDisabled => (
+                StatusCode::FORBIDDEN,
+                "Password login is disabled, use single sign-on",
+            ),
+            AuthError::EmailNotVerified => (
+                StatusCode::FORBIDDEN,
+                "Your identity provider has not verified this email address",
+            ),
+            AuthError::OidcIdentityConflict => (
+                StatusCode::CONFLICT,
+                "This account is already linked to another single sign-on identity",
+            ),
+            AuthError::InvalidCsrfToken => (
+                StatusCode::FORBIDDEN,
+                "Invalid or missing CSRF token, reload the page and try again",
+            ),
+            AuthError::SoleWorkspaceOwner => (
+                StatusCode::CONFLICT,
+                "Transfer ownership of your shared workspaces before deleting your account",
+            ),
+            AuthError::RateLimited => (
+                StatusCode::TOO_MANY_REQUESTS,
+                "Too many requests, slow down and try again later",
+            ),
+            AuthError::PlanLimitReached => (
+                StatusCode::FORBIDDEN,
+                "Your plan's active link limit is reached, remove links or upgrade to create more",
+            ),
+            AuthError::MailerUnavailable => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "This deployment cannot send email, ask an administrator to change your address",
+            ),
+            AuthError::Validation(_) => {
+                (StatusCode::UNPROCESSABLE_ENTITY, "Some fields are invalid")
+            }
             AuthError::Internal => (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 "An unexpected error occurred",
             ),
+        }
+    }
+}
+
+impl IntoResponse for AuthError {
+    fn into_response(self) -> Response {
+        let (status, error_message) = self.status_and_message();
+        let fields = match &self {
+            AuthError::Validation(fields) => Some(json!(fields)),
+            _ => None,
         };
 
-        let body = Json(json!({
-            "error": error_message,
-        }));
+        let body = match fields {
+            Some(fields) => Json(json!({
+                "error": error_message,
+                "fields": fields,
+            })),
+            None => Json(json!({
+                "error": error_message,
+            })),
+        };
 
         (status, body).into_response()
     }
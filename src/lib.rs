@@ -1,5 +1,7 @@
+pub mod cli;
 pub mod configuration;
 pub mod errors;
+pub mod middleware;
 pub mod models;
 pub mod routes;
 pub mod services;
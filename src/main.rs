@@ -1,10 +1,22 @@
 use shorty::{
-    startup,
+    cli, startup,
     telementry::{get_subscriber, init_subscriber},
 };
 
 #[tokio::main]
 async fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("usage-export") {
+        // stdout carries the export, logs go to stderr
+        let subscriber = get_subscriber("shorty".into(), "warn".into(), std::io::stderr);
+        init_subscriber(subscriber);
+        if let Err(e) = cli::usage_export(&args[1..]).await {
+            eprintln!("{:#}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let subscriber = get_subscriber("shorty".into(), "info".into(), std::io::stdout);
     init_subscriber(subscriber);
     opentelemetry::global::shutdown_tracer_provider();
@@ -0,0 +1,264 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{HeaderMap, HeaderName, HeaderValue, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use axum_extra::extract::CookieJar;
+use serde::Deserialize;
+
+use crate::{
+    configuration::{RateLimitKey, RateLimitPolicySettings, RateLimitSettings},
+    errors::AuthError,
+    services::jwt::JwtService,
+    startup::ConnectionPool,
+};
+
+/// Redis calls slower than this fall back to the in-memory counters
+const REDIS_TIMEOUT: Duration = Duration::from_millis(250);
+/// The in-memory fallback drops expired windows once it holds this many
+const MEMORY_PRUNE_THRESHOLD: usize = 10_000;
+
+/// Sliding window rate limiter shared by every policy.
+///
+/// Counters live in Redis so all instances see the same numbers. When Redis
+/// cannot be reached each instance keeps counting on its own.
+#[derive(Clone)]
+pub struct RateLimiter {
+    enabled: bool,
+    pool: ConnectionPool,
+    jwt: JwtService,
+    trust_forwarded_for: bool,
+    memory: Arc<Mutex<HashMap<String, (u64, u64)>>>,
+}
+
+impl std::fmt::Debug for RateLimiter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RateLimiter")
+            .field("enabled", &self.enabled)
+            .field("trust_forwarded_for", &self.trust_forwarded_for)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A named policy, used as the state of the [`rate_limit`] middleware
+#[derive(Clone, Debug)]
+pub struct RateLimit {
+    limiter: RateLimiter,
+    name: &'static str,
+    policy: RateLimitPolicySettings,
+}
+
+struct Decision {
+    allowed: bool,
+    remaining: u32,
+    /// Seconds until the current window ends
+    reset: u64,
+}
+
+#[derive(Deserialize)]
+struct Subject {
+    sub: String,
+}
+
+impl RateLimiter {
+    pub fn new(settings: &RateLimitSettings, pool: ConnectionPool, jwt: JwtService) -> Self {
+        Self {
+            enabled: settings.enabled,
+            trust_forwarded_for: settings.trust_forwarded_for,
+            pool,
+            jwt,
+            memory: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn policy(&self, name: &'static str, policy: &RateLimitPolicySettings) -> RateLimit {
+        RateLimit {
+            limiter: self.clone(),
+            name,
+            policy: policy.clone(),
+        }
+    }
+
+    /// Who a request is counted against
+    fn client_key(&self, request: &Request, key: RateLimitKey) -> String {
+        // Only a verified session token picks the bucket, anything a client
+        // can make up per request would hand it a fresh one each time
+        if key == RateLimitKey::User
+            && let Some(user_id) = self.session_subject(request.headers())
+        {
+            return format!("user:{}", user_id);
+        }
+        format!("ip:{}", self.client_ip(request))
+    }
+
+    fn session_subject(&self, headers: &HeaderMap) -> Option<String> {
+        let token = CookieJar::from_headers(headers)
+            .get("jwt")
+            .map(|c| c.value().to_string())
+            .or_else(|| {
+                headers
+                    .get(header::AUTHORIZATION)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|v| v.strip_prefix("Bearer "))
+                    .map(str::to_string)
+            })?;
+        self.jwt.decode::<Subject>(&token).ok().map(|s| s.sub)
+    }
+
+    fn client_ip(&self, request: &Request) -> String {
+        if self.trust_forwarded_for {
+            // Clients can send their own header, only the entry appended by
+            // the proxy in front of us is trustworthy
+            let forwarded = request
+                .headers()
+                .get("x-forwarded-for")
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.rsplit(',').next())
+                .map(str::trim)
+                .filter(|ip| !ip.is_empty());
+            if let Some(ip) = forwarded {
+                return ip.to_string();
+            }
+        }
+        request
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip().to_string())
+            .unwrap_or_else(|| "unknown".to_string())
+    }
+
+    /// Count a request with the sliding window approximation: the previous
+    /// window's count is weighted by how much of it still overlaps the last
+    /// `window_secs` seconds
+    async fn hit(&self, name: &str, client: &str, policy: &RateLimitPolicySettings) -> Decision {
+        let window_ms = policy.window_secs.max(1) * 1000;
+        let now_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let window = now_ms / window_ms;
+        let elapsed = now_ms % window_ms;
+
+        let current_key = format!("ratelimit:{}:{}:{}", name, client, window);
+        let previous_key = format!("ratelimit:{}:{}:{}", name, client, window - 1);
+
+        let (current, previous) = match tokio::time::timeout(
+            REDIS_TIMEOUT,
+            self.count_in_redis(&current_key, &previous_key, window_ms),
+        )
+        .await
+        {
+            Ok(Ok(counts)) => counts,
+            Ok(Err(e)) => {
+                tracing::warn!("Rate limiter falling back to memory: {:?}", e);
+                self.count_in_memory(&current_key, &previous_key, now_ms, window_ms)
+            }
+            Err(_) => {
+                tracing::warn!("Rate limiter falling back to memory: Redis timed out");
+                self.count_in_memory(&current_key, &previous_key, now_ms, window_ms)
+            }
+        };
+
+        let overlap = (window_ms - elapsed) as f64 / window_ms as f64;
+        let used = current as f64 + previous as f64 * overlap;
+        let limit = f64::from(policy.limit);
+        Decision {
+            allowed: used <= limit,
+            remaining: (limit - used).max(0.0).floor() as u32,
+            reset: (window_ms - elapsed).div_ceil(1000),
+        }
+    }
+
+    async fn count_in_redis(
+        &self,
+        current_key: &str,
+        previous_key: &str,
+        window_ms: u64,
+    ) -> anyhow::Result<(u64, u64)> {
+        let mut conn = self.pool.get().await?;
+        let (current, previous): (u64, Option<u64>) = redis::pipe()
+            .incr(current_key, 1)
+            .pexpire(current_key, (window_ms * 2) as i64)
+            .ignore()
+            .get(previous_key)
+            .query_async(&mut *conn)
+            .await?;
+        Ok((current, previous.unwrap_or(0)))
+    }
+
+    fn count_in_memory(
+        &self,
+        current_key: &str,
+        previous_key: &str,
+        now_ms: u64,
+        window_ms: u64,
+    ) -> (u64, u64) {
+        let mut memory = self.memory.lock().unwrap_or_else(|e| e.into_inner());
+        if memory.len() > MEMORY_PRUNE_THRESHOLD {
+            memory.retain(|_, (_, expires_at)| *expires_at > now_ms);
+        }
+
+        let entry = memory
+            .entry(current_key.to_string())
+            .or_insert((0, now_ms + window_ms * 2));
+        entry.0 += 1;
+        let current = entry.0;
+        let previous = memory
+            .get(previous_key)
+            .filter(|(_, expires_at)| *expires_at > now_ms)
+            .map_or(0, |(count, _)| *count);
+        (current, previous)
+    }
+}
+
+/// Throttle requests according to a [`RateLimit`] policy.
+///
+/// Responses carry `RateLimit-Limit`, `RateLimit-Remaining`, `RateLimit-Reset`
+/// and `RateLimit-Policy`; rejected requests get a 429 with `Retry-After`.
+pub async fn rate_limit(State(limit): State<RateLimit>, request: Request, next: Next) -> Response {
+    if !limit.limiter.enabled {
+        return next.run(request).await;
+    }
+    let client = limit.limiter.client_key(&request, limit.policy.key);
+    let decision = limit.limiter.hit(limit.name, &client, &limit.policy).await;
+
+    let mut response = if decision.allowed {
+        next.run(request).await
+    } else {
+        tracing::warn!(policy = limit.name, %client, "Rate limit exceeded");
+        let mut response = AuthError::RateLimited.into_response();
+        response
+            .headers_mut()
+            .insert(header::RETRY_AFTER, HeaderValue::from(decision.reset));
+        response
+    };
+
+    let headers = response.headers_mut();
+    headers.insert(
+        HeaderName::from_static("ratelimit-limit"),
+        HeaderValue::from(limit.policy.limit),
+    );
+    headers.insert(
+        HeaderName::from_static("ratelimit-remaining"),
+        HeaderValue::from(decision.remaining),
+    );
+    headers.insert(
+        HeaderName::from_static("ratelimit-reset"),
+        HeaderValue::from(decision.reset),
+    );
+    if let Ok(policy) = HeaderValue::from_str(&format!(
+        "{};w={}",
+        limit.policy.limit, limit.policy.window_secs
+    )) {
+        headers.insert(HeaderName::from_static("ratelimit-policy"), policy);
+    }
+    response
+}
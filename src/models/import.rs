@@ -0,0 +1,52 @@
+use serde::{Deserialize, Serialize};
+
+/// How a bulk import treats rows that cannot be created
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportMode {
+    /// Every row is created or none is
+    #[default]
+    Atomic,
+    /// Valid rows are created, the others are reported
+    Partial,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportStatus {
+    Created,
+    Failed,
+    /// Valid, but left out because another row failed an atomic import
+    Skipped,
+}
+
+/// Outcome of one row of an upload
+#[derive(Debug, Serialize)]
+pub struct ImportResult {
+    /// Line of the uploaded file the row was read from
+    pub line: usize,
+    pub status: ImportStatus,
+    pub long_url: String,
+    pub short_code: Option<String>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportReport {
+    pub mode: ImportMode,
+    pub created: usize,
+    pub failed: usize,
+    pub results: Vec<ImportResult>,
+}
+
+impl ImportReport {
+    pub fn new(mode: ImportMode, results: Vec<ImportResult>) -> Self {
+        let count = |status| results.iter().filter(|r| r.status == status).count();
+        Self {
+            mode,
+            created: count(ImportStatus::Created),
+            failed: count(ImportStatus::Failed),
+            results,
+        }
+    }
+}
@@ -1,2 +1,10 @@
+pub mod import;
+pub mod plan;
+pub mod privacy;
+pub mod tag;
+pub mod targeting;
 pub mod url;
+pub mod usage;
 pub mod user;
+pub mod utm;
+pub mod workspace;
@@ -0,0 +1,50 @@
+use serde::{Deserialize, Serialize};
+use sqlx::prelude::FromRow;
+
+/// What an account may do. A `None` limit means the plan does not cap it.
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct PlanModel {
+    pub id: String,
+    pub name: String,
+    pub max_active_links: Option<i32>,
+    pub monthly_redirects: Option<i64>,
+    pub max_link_ttl_days: Option<i32>,
+    pub custom_aliases: bool,
+    pub analytics_retention_days: Option<i32>,
+}
+
+/// A user's plan next to what they currently use of it
+#[derive(Debug, Clone, Serialize)]
+pub struct PlanUsage {
+    pub plan: PlanModel,
+    pub active_links: i64,
+    pub redirects_this_month: i64,
+}
+
+impl PlanUsage {
+    pub fn links_limit(&self) -> String {
+        limit_label(self.plan.max_active_links.map(i64::from))
+    }
+
+    pub fn redirects_limit(&self) -> String {
+        limit_label(self.plan.monthly_redirects)
+    }
+
+    pub fn link_lifetime(&self) -> String {
+        match self.plan.max_link_ttl_days {
+            Some(days) => format!("{} days", days),
+            None => "No expiry".to_string(),
+        }
+    }
+
+    pub fn analytics_retention(&self) -> String {
+        match self.plan.analytics_retention_days {
+            Some(days) => format!("{} days", days),
+            None => "Forever".to_string(),
+        }
+    }
+}
+
+fn limit_label(limit: Option<i64>) -> String {
+    limit.map_or_else(|| "unlimited".to_string(), |limit| limit.to_string())
+}
@@ -0,0 +1,57 @@
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::models::{
+    url::UrlModel,
+    user::{Role, UserModel},
+    utm::UtmPreset,
+    workspace::WorkspaceMembership,
+};
+
+/// Everything stored about a user, as handed out by a data export
+#[derive(Debug, Serialize)]
+pub struct DataExport {
+    pub exported_at: chrono::DateTime<chrono::Utc>,
+    pub profile: ProfileExport,
+    pub workspaces: Vec<WorkspaceMembership>,
+    pub links: Vec<UrlModel>,
+    pub utm_presets: Vec<UtmPreset>,
+}
+
+/// The user row without credentials
+#[derive(Debug, Serialize)]
+pub struct ProfileExport {
+    pub id: Uuid,
+    pub email: String,
+    pub pending_email: Option<String>,
+    pub display_name: Option<String>,
+    pub role: Role,
+    pub disabled: bool,
+    pub has_password: bool,
+    pub fallback_url: Option<String>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl From<UserModel> for ProfileExport {
+    fn from(user: UserModel) -> Self {
+        Self {
+            id: user.id,
+            email: user.email,
+            pending_email: user.pending_email,
+            display_name: user.display_name,
+            role: user.role,
+            disabled: user.disabled,
+            has_password: user.password_hash.is_some(),
+            fallback_url: user.fallback_url,
+            created_at: user.created_at,
+        }
+    }
+}
+
+/// What an erasure removed, mirrored in the `account_erasures` audit table
+#[derive(Debug)]
+pub struct ErasureSummary {
+    pub links_deleted: i32,
+    pub links_anonymized: i32,
+    pub deleted_short_codes: Vec<String>,
+}
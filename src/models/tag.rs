@@ -0,0 +1,19 @@
+use serde::Serialize;
+use sqlx::prelude::FromRow;
+use uuid::Uuid;
+
+/// A workspace tag and how many links carry it
+#[derive(Debug, Clone, FromRow, Serialize)]
+pub struct TagModel {
+    pub id: Uuid,
+    pub name: String,
+    pub links: i64,
+}
+
+/// A workspace folder and how many links it holds
+#[derive(Debug, Clone, FromRow, Serialize)]
+pub struct FolderModel {
+    pub id: Uuid,
+    pub name: String,
+    pub links: i64,
+}
@@ -0,0 +1,148 @@
+use std::str::FromStr;
+
+use chrono::NaiveTime;
+use serde::{Deserialize, Serialize};
+use sqlx::prelude::FromRow;
+use uuid::Uuid;
+
+/// Operating system a rule can target, read from the visitor's user agent
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type, Serialize, Deserialize)]
+#[sqlx(type_name = "target_os", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum TargetOs {
+    Ios,
+    Android,
+    Windows,
+    Macos,
+    Linux,
+}
+
+impl TargetOs {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Ios => "ios",
+            Self::Android => "android",
+            Self::Windows => "windows",
+            Self::Macos => "macos",
+            Self::Linux => "linux",
+        }
+    }
+}
+
+impl FromStr for TargetOs {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ios" => Ok(Self::Ios),
+            "android" => Ok(Self::Android),
+            "windows" => Ok(Self::Windows),
+            "macos" => Ok(Self::Macos),
+            "linux" => Ok(Self::Linux),
+            other => Err(format!("unknown operating system `{}`", other)),
+        }
+    }
+}
+
+/// Kind of device a rule can target, read from the visitor's user agent
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type, Serialize, Deserialize)]
+#[sqlx(type_name = "device_class", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceClass {
+    Mobile,
+    Tablet,
+    Desktop,
+}
+
+impl DeviceClass {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Mobile => "mobile",
+            Self::Tablet => "tablet",
+            Self::Desktop => "desktop",
+        }
+    }
+}
+
+impl FromStr for DeviceClass {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mobile" => Ok(Self::Mobile),
+            "tablet" => Ok(Self::Tablet),
+            "desktop" => Ok(Self::Desktop),
+            other => Err(format!("unknown device class `{}`", other)),
+        }
+    }
+}
+
+/// Sends the visitors matching every condition it sets to `destination`.
+/// A link's rules are tried in order, the first match wins.
+#[derive(Debug, Clone, PartialEq, FromRow, Serialize, Deserialize)]
+pub struct TargetingRule {
+    /// Assigned when the rule is stored, ignored when rules are replaced
+    #[serde(default)]
+    pub id: Uuid,
+    #[serde(skip)]
+    pub url_id: Uuid,
+    pub destination: String,
+    #[serde(default)]
+    pub os: Option<TargetOs>,
+    #[serde(default)]
+    pub device: Option<DeviceClass>,
+    /// Lowercase language tag such as `de` or `pt-br`, matched against the
+    /// visitor's preferred language and its regional variants
+    #[serde(default)]
+    pub language: Option<String>,
+    /// UTC time of day the rule starts to apply, midnight when not set
+    #[serde(default)]
+    pub active_from: Option<NaiveTime>,
+    /// UTC time of day the rule stops applying, midnight when not set. Before
+    /// `active_from` the window runs past midnight.
+    #[serde(default)]
+    pub active_until: Option<NaiveTime>,
+    /// Lowercase host the visitor came from, its subdomains match as well
+    #[serde(default)]
+    pub referrer_host: Option<String>,
+}
+
+impl TargetingRule {
+    /// The rule's conditions in a few words, for listings
+    pub fn conditions(&self) -> String {
+        let mut conditions = Vec::new();
+        if let Some(os) = self.os {
+            conditions.push(os.as_str().to_string());
+        }
+        if let Some(device) = self.device {
+            conditions.push(device.as_str().to_string());
+        }
+        if let Some(language) = &self.language {
+            conditions.push(format!("language {}", language));
+        }
+        if self.active_from.is_some() || self.active_until.is_some() {
+            let format = |time: Option<NaiveTime>| {
+                time.unwrap_or(NaiveTime::MIN).format("%H:%M").to_string()
+            };
+            conditions.push(format!(
+                "{}-{} UTC",
+                format(self.active_from),
+                format(self.active_until)
+            ));
+        }
+        if let Some(host) = &self.referrer_host {
+            conditions.push(format!("from {}", host));
+        }
+        conditions.join(", ")
+    }
+}
+
+/// Outcome of changing a single rule of a link
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuleEdit {
+    Applied,
+    /// The link is not in the workspace, or the rule is not one of its own
+    NotFound,
+    /// The link already has as many rules as it may have
+    Full,
+}
@@ -1,12 +1,570 @@
-use serde::{Deserialize, Serialize};
+use std::{fmt::Display, str::FromStr};
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use serde::{Deserialize, Deserializer, Serialize, de};
 use sqlx::prelude::FromRow;
 
+use crate::models::{targeting::TargetingRule, utm::UtmParams};
+
+/// Links per page when the listing does not ask for a size
+pub const DEFAULT_PAGE_SIZE: u32 = 25;
+/// Largest page a listing will return
+pub const MAX_PAGE_SIZE: u32 = 100;
+
 #[derive(Debug, FromRow, Serialize, Deserialize)]
 pub struct UrlModel {
+    pub id: uuid::Uuid,
     pub short_code: String,
     pub long_url: String,
     pub user_id: Option<uuid::Uuid>,
+    pub workspace_id: Option<uuid::Uuid>,
     pub clicks: i32,
+    /// Clicks that came from scanning the link's QR code, part of `clicks`
+    pub qr_scans: i32,
+    /// Visits sent to the fallback url, not part of `clicks`
+    pub fallback_hits: i32,
+    /// Clicks after which the link stops redirecting, 1 for a one-time link
+    pub max_clicks: Option<i32>,
     pub site_name: String,
+    pub taken_down: bool,
     pub created_at: chrono::DateTime<chrono::Utc>,
+    /// Before this the link is scheduled and does not redirect yet
+    pub starts_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// Where visitors go while the link does not redirect to `long_url`
+    pub fallback_url: Option<String>,
+    pub query_passthrough: QueryPassthrough,
+    /// Whatever follows the short code in the path is added to the destination's
+    pub path_passthrough: bool,
+    /// Added to `long_url` on redirect
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub utm: UtmParams,
+    /// Loaded apart from the link, in the order they are tried
+    #[sqlx(skip)]
+    #[serde(default)]
+    pub rules: Vec<TargetingRule>,
+    pub last_clicked_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// Visitors have to enter the link's password before being redirected
+    pub protected: bool,
+    pub folder_id: Option<uuid::Uuid>,
+    pub folder: Option<String>,
+    pub tags: Vec<String>,
+}
+
+impl UrlModel {
+    /// Clicks the link still has before it stops redirecting, none when unlimited
+    pub fn clicks_left(&self) -> Option<i32> {
+        self.max_clicks.map(|max| (max - self.clicks).max(0))
+    }
+
+    /// Where the link stands right now, as filtered by [`LinkFilter::status`]
+    pub fn status(&self) -> LinkStatus {
+        let now = chrono::Utc::now();
+        if self.taken_down {
+            LinkStatus::TakenDown
+        } else if self.expires_at.is_some_and(|at| at <= now) || self.clicks_left() == Some(0) {
+            LinkStatus::Expired
+        } else if self.starts_at.is_some_and(|at| at > now) {
+            LinkStatus::Scheduled
+        } else {
+            LinkStatus::Active
+        }
+    }
+}
+
+/// A link flattened into one CSV row, tags are joined with `;`
+#[derive(Debug, Serialize)]
+pub struct UrlCsvRow<'a> {
+    pub short_code: &'a str,
+    pub long_url: &'a str,
+    pub user_id: Option<uuid::Uuid>,
+    pub workspace_id: Option<uuid::Uuid>,
+    pub clicks: i32,
+    pub qr_scans: i32,
+    pub fallback_hits: i32,
+    pub max_clicks: Option<i32>,
+    pub site_name: &'a str,
+    pub taken_down: bool,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub starts_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub fallback_url: Option<&'a str>,
+    pub query_passthrough: QueryPassthrough,
+    pub path_passthrough: bool,
+    pub utm_source: Option<&'a str>,
+    pub utm_medium: Option<&'a str>,
+    pub utm_campaign: Option<&'a str>,
+    pub utm_term: Option<&'a str>,
+    pub utm_content: Option<&'a str>,
+    pub folder: Option<&'a str>,
+    pub tags: String,
+}
+
+impl UrlCsvRow<'_> {
+    pub const HEADER: [&'static str; 23] = [
+        "short_code",
+        "long_url",
+        "user_id",
+        "workspace_id",
+        "clicks",
+        "qr_scans",
+        "fallback_hits",
+        "max_clicks",
+        "site_name",
+        "taken_down",
+        "created_at",
+        "starts_at",
+        "expires_at",
+        "fallback_url",
+        "query_passthrough",
+        "path_passthrough",
+        "utm_source",
+        "utm_medium",
+        "utm_campaign",
+        "utm_term",
+        "utm_content",
+        "folder",
+        "tags",
+    ];
+}
+
+impl<'a> From<&'a UrlModel> for UrlCsvRow<'a> {
+    fn from(url: &'a UrlModel) -> Self {
+        Self {
+            short_code: &url.short_code,
+            long_url: &url.long_url,
+            user_id: url.user_id,
+            workspace_id: url.workspace_id,
+            clicks: url.clicks,
+            qr_scans: url.qr_scans,
+            fallback_hits: url.fallback_hits,
+            max_clicks: url.max_clicks,
+            site_name: &url.site_name,
+            taken_down: url.taken_down,
+            created_at: url.created_at,
+            starts_at: url.starts_at,
+            expires_at: url.expires_at,
+            fallback_url: url.fallback_url.as_deref(),
+            query_passthrough: url.query_passthrough,
+            path_passthrough: url.path_passthrough,
+            utm_source: url.utm.utm_source.as_deref(),
+            utm_medium: url.utm.utm_medium.as_deref(),
+            utm_campaign: url.utm.utm_campaign.as_deref(),
+            utm_term: url.utm.utm_term.as_deref(),
+            utm_content: url.utm.utm_content.as_deref(),
+            folder: url.folder.as_deref(),
+            tags: url.tags.join(";"),
+        }
+    }
+}
+
+/// Clicks of a link on one UTC day
+#[derive(Debug, Clone, FromRow, Serialize)]
+pub struct DailyClicks {
+    #[serde(skip)]
+    pub url_id: uuid::Uuid,
+    pub day: chrono::NaiveDate,
+    pub clicks: i64,
+    pub qr_scans: i64,
+    pub fallbacks: i64,
+}
+
+/// A link as exported, with its clicks per day when they were asked for.
+/// Days only go back as far as the plan keeps analytics.
+#[derive(Debug, Serialize)]
+pub struct ExportedLink {
+    #[serde(flatten)]
+    pub link: UrlModel,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub daily_clicks: Option<Vec<DailyClicks>>,
+}
+
+/// A link about to be stored
+#[derive(Debug)]
+pub struct NewUrl<'a> {
+    pub short_code: &'a str,
+    pub long_url: &'a str,
+    pub site_name: &'a str,
+    pub user_id: uuid::Uuid,
+    pub workspace_id: uuid::Uuid,
+    pub starts_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub fallback_url: Option<&'a str>,
+    pub query_passthrough: QueryPassthrough,
+    pub path_passthrough: bool,
+    pub utm: UtmParams,
+    /// Ignored unless the folder belongs to the same workspace
+    pub folder_id: Option<uuid::Uuid>,
+    /// Lowercase tag names, created in the workspace when missing
+    pub tags: &'a [String],
+    pub password_hash: Option<&'a str>,
+    pub max_clicks: Option<i32>,
+}
+
+/// Narrows, orders and pages a link listing, shared by the dashboard and the API.
+/// Blank values, as sent by an untouched form field, are ignored.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LinkFilter {
+    /// Free text matched against the short code, site name and destination
+    #[serde(
+        default,
+        deserialize_with = "blank_as_none",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub q: Option<String>,
+    /// Tag name
+    #[serde(
+        default,
+        deserialize_with = "blank_as_none",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub tag: Option<String>,
+    #[serde(
+        default,
+        deserialize_with = "blank_as_none",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub folder: Option<uuid::Uuid>,
+    #[serde(
+        default,
+        deserialize_with = "blank_as_none",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub status: Option<LinkStatus>,
+    /// First day of creation, UTC
+    #[serde(
+        default,
+        deserialize_with = "blank_as_none",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub from: Option<chrono::NaiveDate>,
+    /// Last day of creation, UTC, inclusive
+    #[serde(
+        default,
+        deserialize_with = "blank_as_none",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub to: Option<chrono::NaiveDate>,
+    #[serde(default)]
+    pub sort: LinkSort,
+    #[serde(default)]
+    pub order: SortOrder,
+    /// Cursor returned with the previous page
+    #[serde(
+        default,
+        deserialize_with = "blank_as_none",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub after: Option<String>,
+    #[serde(
+        default,
+        deserialize_with = "blank_as_none",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub limit: Option<u32>,
+}
+
+impl LinkFilter {
+    /// True when no tag or folder is picked
+    pub fn is_empty(&self) -> bool {
+        self.tag.is_none() && self.folder.is_none()
+    }
+
+    /// True when the listing is narrowed by more than a tag or folder
+    pub fn is_searching(&self) -> bool {
+        self.q.is_some() || self.status.is_some() || self.from.is_some() || self.to.is_some()
+    }
+
+    pub fn page_size(&self) -> u32 {
+        self.limit
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE)
+    }
+
+    /// The query string of this listing, without its cursor
+    pub fn query_string(&self) -> String {
+        self.query_string_after(None)
+    }
+
+    /// The query string of the page following `cursor`
+    pub fn query_string_after(&self, cursor: Option<&str>) -> String {
+        let page = Self {
+            after: cursor.map(str::to_string),
+            ..self.clone()
+        };
+        serde_urlencoded::to_string(&page).unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkStatus {
+    /// Waiting for its start time
+    Scheduled,
+    /// Live, neither scheduled, expired, used up nor taken down
+    Active,
+    /// Past its expiry date or its click limit
+    Expired,
+    TakenDown,
+}
+
+impl FromStr for LinkStatus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "scheduled" => Ok(Self::Scheduled),
+            "active" => Ok(Self::Active),
+            "expired" => Ok(Self::Expired),
+            "taken_down" => Ok(Self::TakenDown),
+            other => Err(format!("unknown link status `{}`", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkSort {
+    #[default]
+    Created,
+    Clicks,
+    LastClick,
+}
+
+impl LinkSort {
+    /// The value a link is ordered by, as written in a cursor
+    fn key(&self, url: &UrlModel) -> String {
+        match self {
+            Self::Created => url.created_at.to_rfc3339(),
+            Self::Clicks => url.clicks.to_string(),
+            // Links never clicked sort as if clicked at the epoch
+            Self::LastClick => url.last_clicked_at.unwrap_or_default().to_rfc3339(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    #[default]
+    Desc,
+    Asc,
+}
+
+/// Position in a listing: the sort value and id of the last link of a page.
+/// Opaque to clients, who only hand it back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkCursor {
+    pub key: String,
+    pub id: uuid::Uuid,
+}
+
+impl LinkCursor {
+    pub fn after(sort: LinkSort, url: &UrlModel) -> Self {
+        Self {
+            key: sort.key(url),
+            id: url.id,
+        }
+    }
+
+    pub fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(format!("{}|{}", self.key, self.id))
+    }
+
+    /// Read a cursor handed back by a client, none when it was not written
+    /// for a listing sorted by `sort`
+    pub fn decode(sort: LinkSort, cursor: &str) -> Option<Self> {
+        let raw = String::from_utf8(URL_SAFE_NO_PAD.decode(cursor).ok()?).ok()?;
+        let (key, id) = raw.rsplit_once('|')?;
+        let valid = match sort {
+            LinkSort::Created | LinkSort::LastClick => {
+                chrono::DateTime::parse_from_rfc3339(key).is_ok()
+            }
+            LinkSort::Clicks => key.parse::<i32>().is_ok(),
+        };
+        if !valid {
+            return None;
+        }
+        Some(Self {
+            key: key.to_string(),
+            id: id.parse().ok()?,
+        })
+    }
+}
+
+/// One page of a link listing
+#[derive(Debug, Serialize)]
+pub struct LinkPage {
+    pub links: Vec<UrlModel>,
+    /// Cursor of the next page, none on the last page
+    pub next: Option<String>,
+}
+
+/// Treat a missing or blank query value as absent and parse anything else
+fn blank_as_none<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    match Option::<String>::deserialize(deserializer)? {
+        Some(value) if !value.trim().is_empty() => {
+            value.trim().parse().map(Some).map_err(de::Error::custom)
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Where a click came from, QR codes mark the link they encode
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, sqlx::Type, Serialize, Deserialize)]
+#[sqlx(type_name = "click_source", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum ClickSource {
+    #[default]
+    Link,
+    Qr,
+}
+
+/// Outcome of claiming a click on a link with a click limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitedClick {
+    Counted,
+    /// The link has used up its clicks
+    Exhausted,
+    /// The owner has used up their monthly redirects
+    QuotaExceeded,
+}
+
+/// How the query string of a visit is merged into the destination
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, sqlx::Type, Serialize, Deserialize)]
+#[sqlx(type_name = "query_passthrough", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum QueryPassthrough {
+    /// The destination is used as stored
+    #[default]
+    Off,
+    /// Parameters the destination already has keep their value
+    Keep,
+    /// Parameters of the visit replace those of the destination
+    Override,
+    /// Both are kept, the visit's after the destination's
+    Append,
+}
+
+impl FromStr for QueryPassthrough {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "off" => Ok(Self::Off),
+            "keep" => Ok(Self::Keep),
+            "override" => Ok(Self::Override),
+            "append" => Ok(Self::Append),
+            other => Err(format!("unknown query passthrough `{}`", other)),
+        }
+    }
+}
+
+/// What the redirect path needs to know about a link, cached under its short code
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct LinkTarget {
+    pub long_url: String,
+    pub user_id: Option<uuid::Uuid>,
+    #[serde(default)]
+    pub starts_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
+    #[serde(default)]
+    pub fallback_url: Option<String>,
+    #[serde(default)]
+    pub query_passthrough: QueryPassthrough,
+    #[serde(default)]
+    pub path_passthrough: bool,
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub utm: UtmParams,
+    /// Visitors matching one of them go to its destination instead of `long_url`
+    #[sqlx(skip)]
+    #[serde(default)]
+    pub rules: Vec<TargetingRule>,
+    /// Only whether there is a password, the hash stays in the database
+    #[serde(default)]
+    pub protected: bool,
+    /// A limited link is never redirected from the cache alone, every click
+    /// is claimed against the database first
+    #[serde(default)]
+    pub max_clicks: Option<i32>,
+}
+
+impl LinkTarget {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|at| at <= chrono::Utc::now())
+    }
+
+    /// The start time, while it is still ahead
+    pub fn pending_start(&self) -> Option<chrono::DateTime<chrono::Utc>> {
+        self.starts_at.filter(|at| *at > chrono::Utc::now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cursor(key: &str) -> LinkCursor {
+        LinkCursor {
+            key: key.to_string(),
+            id: uuid::Uuid::from_u128(42),
+        }
+    }
+
+    #[test]
+    fn cursors_round_trip() {
+        let created = cursor("2024-05-01T12:30:00+00:00");
+        let decoded = LinkCursor::decode(LinkSort::Created, &created.encode());
+        assert_eq!(decoded, Some(created));
+
+        let clicks = cursor("17");
+        assert_eq!(
+            LinkCursor::decode(LinkSort::Clicks, &clicks.encode()),
+            Some(clicks)
+        );
+
+        // Never clicked links are keyed by the epoch
+        let never = cursor(&chrono::DateTime::<chrono::Utc>::default().to_rfc3339());
+        let decoded = LinkCursor::decode(LinkSort::LastClick, &never.encode());
+        assert_eq!(decoded, Some(never));
+    }
+
+    #[test]
+    fn cursors_are_url_safe() {
+        let encoded = cursor("2024-05-01T12:30:00+00:00").encode();
+        assert!(
+            encoded
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        );
+    }
+
+    #[test]
+    fn cursors_of_another_sort_are_refused() {
+        let created = cursor("2024-05-01T12:30:00+00:00").encode();
+        assert_eq!(LinkCursor::decode(LinkSort::Clicks, &created), None);
+        let clicks = cursor("17").encode();
+        assert_eq!(LinkCursor::decode(LinkSort::Created, &clicks), None);
+        assert_eq!(LinkCursor::decode(LinkSort::LastClick, &clicks), None);
+    }
+
+    #[test]
+    fn malformed_cursors_are_refused() {
+        let encode = |raw: &str| URL_SAFE_NO_PAD.encode(raw);
+        for raw in [
+            "not base64!",
+            &encode("17"),
+            &encode("17|not-a-uuid"),
+            &URL_SAFE_NO_PAD.encode([0xff, 0xfe, b'|']),
+        ] {
+            assert_eq!(LinkCursor::decode(LinkSort::Clicks, raw), None, "{}", raw);
+        }
+    }
 }
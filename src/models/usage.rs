@@ -0,0 +1,36 @@
+use serde::{Deserialize, Serialize};
+use sqlx::prelude::FromRow;
+use uuid::Uuid;
+
+/// A billable kind of usage
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type, Serialize, Deserialize)]
+#[sqlx(type_name = "usage_metric", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum UsageMetric {
+    Redirect,
+    LinkCreated,
+}
+
+/// Usage of one account in one hour.
+///
+/// `id` is derived from the account, hour and metric, so a billing system can
+/// import the same record twice without counting it twice.
+#[derive(Debug, Clone, FromRow, Serialize)]
+pub struct UsageRecord {
+    pub id: String,
+    pub user_id: Uuid,
+    pub hour: chrono::DateTime<chrono::Utc>,
+    pub metric: UsageMetric,
+    pub quantity: i64,
+}
+
+/// Usage for a billing period. Only hours that have ended are included,
+/// `complete_through` marks where the data stops.
+#[derive(Debug, Serialize)]
+pub struct UsageExport {
+    pub period_start: chrono::DateTime<chrono::Utc>,
+    pub period_end: chrono::DateTime<chrono::Utc>,
+    pub complete_through: chrono::DateTime<chrono::Utc>,
+    pub generated_at: chrono::DateTime<chrono::Utc>,
+    pub records: Vec<UsageRecord>,
+}
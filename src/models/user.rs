@@ -2,10 +2,67 @@ use serde::{Deserialize, Serialize};
 use sqlx::prelude::FromRow;
 use uuid::Uuid;
 
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, sqlx::Type, Serialize, Deserialize)]
+#[sqlx(type_name = "user_role", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    #[default]
+    User,
+    Admin,
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Role::User => write!(f, "user"),
+            Role::Admin => write!(f, "admin"),
+        }
+    }
+}
+
 #[derive(Debug, FromRow, Serialize, Deserialize)]
 pub struct UserModel {
     pub id: Uuid,
     pub email: String,
-    pub password_hash: String,
+    /// New address waiting for confirmation before it replaces `email`
+    pub pending_email: Option<String>,
+    pub display_name: Option<String>,
+    pub password_hash: Option<String>,
+    pub role: Role,
+    pub disabled: bool,
+    /// Sessions carrying an older version were issued before a credential change
+    pub session_version: i32,
+    pub plan_id: String,
+    /// Where unavailable links go when they have no fallback of their own
+    pub fallback_url: Option<String>,
     pub created_at: chrono::DateTime<chrono::Utc>,
 }
+
+impl UserModel {
+    /// The display name when set, otherwise the email address
+    pub fn name(&self) -> &str {
+        self.display_name.as_deref().unwrap_or(&self.email)
+    }
+
+    /// Up to two letters for the avatar, taken from the display name
+    /// words or the local part of the email
+    pub fn initials(&self) -> String {
+        let initials: String = match self.display_name.as_deref() {
+            Some(name) => name
+                .split_whitespace()
+                .filter_map(|word| word.chars().next())
+                .take(2)
+                .collect(),
+            None => self
+                .email
+                .split('@')
+                .next()
+                .unwrap_or_default()
+                .chars()
+                .filter(|c| c.is_alphanumeric())
+                .take(2)
+                .collect(),
+        };
+        initials.to_uppercase()
+    }
+}
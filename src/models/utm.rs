@@ -0,0 +1,60 @@
+use serde::{Deserialize, Serialize};
+use sqlx::prelude::FromRow;
+use uuid::Uuid;
+
+/// Campaign parameters added to a destination on redirect, named after the
+/// query parameters they become
+#[derive(Debug, Clone, Default, PartialEq, Eq, FromRow, Serialize, Deserialize)]
+pub struct UtmParams {
+    #[serde(default)]
+    pub utm_source: Option<String>,
+    #[serde(default)]
+    pub utm_medium: Option<String>,
+    #[serde(default)]
+    pub utm_campaign: Option<String>,
+    #[serde(default)]
+    pub utm_term: Option<String>,
+    #[serde(default)]
+    pub utm_content: Option<String>,
+}
+
+impl UtmParams {
+    /// The parameters that are set, as query pairs in their usual order
+    pub fn pairs(&self) -> impl Iterator<Item = (&'static str, &str)> {
+        [
+            ("utm_source", &self.utm_source),
+            ("utm_medium", &self.utm_medium),
+            ("utm_campaign", &self.utm_campaign),
+            ("utm_term", &self.utm_term),
+            ("utm_content", &self.utm_content),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| value.as_deref().map(|value| (key, value)))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs().next().is_none()
+    }
+
+    /// Fill the parameters that are not set from `preset`
+    pub fn or(self, preset: &UtmParams) -> UtmParams {
+        UtmParams {
+            utm_source: self.utm_source.or_else(|| preset.utm_source.clone()),
+            utm_medium: self.utm_medium.or_else(|| preset.utm_medium.clone()),
+            utm_campaign: self.utm_campaign.or_else(|| preset.utm_campaign.clone()),
+            utm_term: self.utm_term.or_else(|| preset.utm_term.clone()),
+            utm_content: self.utm_content.or_else(|| preset.utm_content.clone()),
+        }
+    }
+}
+
+/// A named set of UTM parameters saved by a user
+#[derive(Debug, Clone, FromRow, Serialize)]
+pub struct UtmPreset {
+    pub id: Uuid,
+    pub name: String,
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub params: UtmParams,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
@@ -0,0 +1,63 @@
+use serde::{Deserialize, Serialize};
+use sqlx::prelude::FromRow;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type, Serialize, Deserialize)]
+#[sqlx(type_name = "workspace_role", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum WorkspaceRole {
+    Owner,
+    Editor,
+    Viewer,
+}
+
+impl WorkspaceRole {
+    /// Owners and editors may create and change links
+    pub fn can_edit(&self) -> bool {
+        matches!(self, WorkspaceRole::Owner | WorkspaceRole::Editor)
+    }
+
+    /// Only owners may invite people and manage members
+    pub fn can_manage(&self) -> bool {
+        matches!(self, WorkspaceRole::Owner)
+    }
+}
+
+impl std::fmt::Display for WorkspaceRole {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WorkspaceRole::Owner => write!(f, "owner"),
+            WorkspaceRole::Editor => write!(f, "editor"),
+            WorkspaceRole::Viewer => write!(f, "viewer"),
+        }
+    }
+}
+
+/// A workspace as seen by one of its members
+#[derive(Debug, Clone, FromRow, Serialize, Deserialize)]
+pub struct WorkspaceMembership {
+    pub id: Uuid,
+    pub name: String,
+    pub personal: bool,
+    pub role: WorkspaceRole,
+}
+
+#[derive(Debug, FromRow, Serialize, Deserialize)]
+pub struct MemberModel {
+    pub user_id: Uuid,
+    pub email: String,
+    pub role: WorkspaceRole,
+    pub joined_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, FromRow, Serialize, Deserialize)]
+pub struct InvitationModel {
+    pub id: Uuid,
+    pub workspace_id: Uuid,
+    pub workspace_name: String,
+    pub email: String,
+    pub role: WorkspaceRole,
+    pub token: String,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub accepted_at: Option<chrono::DateTime<chrono::Utc>>,
+}
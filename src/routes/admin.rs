@@ -0,0 +1,285 @@
+use askama::Template;
+use axum::{
+    Form,
+    extract::{Path, Query, State},
+    http::header,
+    response::{Html, IntoResponse, Redirect},
+};
+use chrono::NaiveDate;
+use serde::Deserialize;
+use tracing::instrument;
+use uuid::Uuid;
+
+use crate::{
+    errors::{AuthError, FieldErrors},
+    middleware::csrf::CsrfToken,
+    models::{
+        plan::PlanModel,
+        url::UrlModel,
+        user::{Role, UserModel},
+    },
+    routes::{
+        auth::{AdminClaims, Page},
+        profile::ExportFormat,
+    },
+    services::usage::usage_csv,
+    startup::AppState,
+};
+
+#[derive(Template)]
+#[template(path = "admin.html")]
+struct AdminTemplate {
+    csrf_token: String,
+    query: String,
+    users: Vec<UserModel>,
+    urls: Vec<UrlModel>,
+    plans: Vec<PlanModel>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AdminSearch {
+    #[serde(default)]
+    pub q: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RoleForm {
+    pub role: Role,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PlanForm {
+    pub plan_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UsageExportQuery {
+    pub from: NaiveDate,
+    /// First day after the billing period
+    pub to: NaiveDate,
+    #[serde(default)]
+    pub format: ExportFormat,
+}
+
+#[instrument(name = "Admin: Dashboard", skip(state, _admin))]
+pub async fn admin_handler(
+    State(state): State<AppState>,
+    Page(AdminClaims(_admin)): Page<AdminClaims>,
+    Query(search): Query<AdminSearch>,
+    CsrfToken(csrf_token): CsrfToken,
+) -> Result<impl IntoResponse, AuthError> {
+    let query = search.q.trim().to_string();
+
+    let users = state.auth_service.search_users(&query).await.map_err(|e| {
+        tracing::error!("Failed to list users: {:?}", e);
+        AuthError::Internal
+    })?;
+    let urls = state.url_service.search_urls(&query).await.map_err(|e| {
+        tracing::error!("Failed to list urls: {:?}", e);
+        AuthError::Internal
+    })?;
+    let plans = state.plan_service.list_plans().await.map_err(|e| {
+        tracing::error!("Failed to list plans: {:?}", e);
+        AuthError::Internal
+    })?;
+
+    let template = AdminTemplate {
+        csrf_token,
+        query,
+        users,
+        urls,
+        plans,
+    };
+    Ok(Html(template.render().unwrap()))
+}
+
+#[instrument(name = "Admin: Disable user", skip(state, admin))]
+pub async fn disable_user_handler(
+    State(state): State<AppState>,
+    AdminClaims(admin): AdminClaims,
+    Path(user_id): Path<Uuid>,
+) -> Result<impl IntoResponse, AuthError> {
+    // Locking yourself out would leave nobody able to undo it
+    if admin.sub == user_id.to_string() {
+        return Err(AuthError::Forbidden);
+    }
+    set_user_disabled(&state, user_id, true).await?;
+    tracing::info!(admin = %admin.sub, %user_id, "User disabled");
+    Ok(Redirect::to("/admin"))
+}
+
+#[instrument(name = "Admin: Enable user", skip(state, admin))]
+pub async fn enable_user_handler(
+    State(state): State<AppState>,
+    AdminClaims(admin): AdminClaims,
+    Path(user_id): Path<Uuid>,
+) -> Result<impl IntoResponse, AuthError> {
+    set_user_disabled(&state, user_id, false).await?;
+    tracing::info!(admin = %admin.sub, %user_id, "User enabled");
+    Ok(Redirect::to("/admin"))
+}
+
+#[instrument(name = "Admin: Change user role", skip(state, admin, form))]
+pub async fn user_role_handler(
+    State(state): State<AppState>,
+    AdminClaims(admin): AdminClaims,
+    Path(user_id): Path<Uuid>,
+    Form(form): Form<RoleForm>,
+) -> Result<impl IntoResponse, AuthError> {
+    if admin.sub == user_id.to_string() {
+        return Err(AuthError::Forbidden);
+    }
+    let updated = state
+        .auth_service
+        .set_user_role(user_id, form.role)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to update role: {:?}", e);
+            AuthError::Internal
+        })?;
+    if !updated {
+        return Err(AuthError::NotFound);
+    }
+    tracing::info!(admin = %admin.sub, %user_id, role = %form.role, "User role changed");
+    Ok(Redirect::to("/admin"))
+}
+
+#[instrument(name = "Admin: Change user plan", skip(state, admin, form))]
+pub async fn user_plan_handler(
+    State(state): State<AppState>,
+    AdminClaims(admin): AdminClaims,
+    Path(user_id): Path<Uuid>,
+    Form(form): Form<PlanForm>,
+) -> Result<impl IntoResponse, AuthError> {
+    let updated = state
+        .plan_service
+        .assign(user_id, &form.plan_id)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to update plan: {:?}", e);
+            AuthError::Internal
+        })?;
+    if !updated {
+        return Err(AuthError::NotFound);
+    }
+    tracing::info!(admin = %admin.sub, %user_id, plan = %form.plan_id, "User plan changed");
+    Ok(Redirect::to("/admin"))
+}
+
+/// Metered usage for a billing period. Only hours that have ended are
+/// included, exporting the same period again returns the same records.
+#[instrument(name = "Admin: Export usage", skip(state, admin))]
+pub async fn usage_export_handler(
+    State(state): State<AppState>,
+    AdminClaims(admin): AdminClaims,
+    Query(query): Query<UsageExportQuery>,
+) -> Result<impl IntoResponse, AuthError> {
+    if query.from >= query.to {
+        let mut errors = FieldErrors::new();
+        errors.insert(
+            "to",
+            vec!["The period must end after it starts".to_string()],
+        );
+        return Err(AuthError::Validation(errors));
+    }
+    let export = state
+        .usage_service
+        .export(query.from, query.to)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to export usage: {:?}", e);
+            AuthError::Internal
+        })?;
+
+    let (content_type, extension, body) = match query.format {
+        ExportFormat::Json => (
+            "application/json",
+            "json",
+            serde_json::to_vec_pretty(&export).map_err(|e| {
+                tracing::error!("Failed to serialize usage: {:?}", e);
+                AuthError::Internal
+            })?,
+        ),
+        ExportFormat::Csv => (
+            "text/csv",
+            "csv",
+            usage_csv(&export.records).map_err(|e| {
+                tracing::error!("Failed to write usage CSV: {:?}", e);
+                AuthError::Internal
+            })?,
+        ),
+    };
+
+    tracing::info!(admin = %admin.sub, from = %query.from, to = %query.to, records = export.records.len(), "Usage exported");
+    let disposition = format!(
+        "attachment; filename=\"usage-{}-{}.{}\"",
+        query.from, query.to, extension
+    );
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        body,
+    ))
+}
+
+#[instrument(name = "Admin: Take down url", skip(state, admin))]
+pub async fn takedown_url_handler(
+    State(state): State<AppState>,
+    AdminClaims(admin): AdminClaims,
+    Path(short_code): Path<String>,
+) -> Result<impl IntoResponse, AuthError> {
+    set_taken_down(&state, &short_code, true).await?;
+    tracing::info!(admin = %admin.sub, %short_code, "Url taken down");
+    Ok(Redirect::to("/admin"))
+}
+
+#[instrument(name = "Admin: Restore url", skip(state, admin))]
+pub async fn restore_url_handler(
+    State(state): State<AppState>,
+    AdminClaims(admin): AdminClaims,
+    Path(short_code): Path<String>,
+) -> Result<impl IntoResponse, AuthError> {
+    set_taken_down(&state, &short_code, false).await?;
+    tracing::info!(admin = %admin.sub, %short_code, "Url restored");
+    Ok(Redirect::to("/admin"))
+}
+
+async fn set_user_disabled(
+    state: &AppState,
+    user_id: Uuid,
+    disabled: bool,
+) -> Result<(), AuthError> {
+    let updated = state
+        .auth_service
+        .set_user_disabled(user_id, disabled)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to update user: {:?}", e);
+            AuthError::Internal
+        })?;
+    if !updated {
+        return Err(AuthError::NotFound);
+    }
+    Ok(())
+}
+
+async fn set_taken_down(
+    state: &AppState,
+    short_code: &str,
+    taken_down: bool,
+) -> Result<(), AuthError> {
+    let updated = state
+        .url_service
+        .set_taken_down(short_code, taken_down)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to update url: {:?}", e);
+            AuthError::Internal
+        })?;
+    if !updated {
+        return Err(AuthError::NotFound);
+    }
+    Ok(())
+}
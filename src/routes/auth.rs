@@ -1,118 +1,210 @@
-use axum_extra::extract::CookieJar;
+use axum_extra::extract::{CookieJar, SignedCookieJar};
 use std::fmt::Display;
-use std::sync::LazyLock;
 
 use askama::Template;
 use axum::Form;
 use axum::Json;
 use axum::RequestPartsExt;
-use axum::extract::{FromRequestParts, State};
-use axum::http::StatusCode;
+use axum::extract::{FromRef, FromRequestParts, State};
 use axum::http::request::Parts;
+use axum::http::{HeaderMap, StatusCode};
 use axum::response::Html;
 use axum::response::IntoResponse;
 use axum::response::Redirect;
+use axum::response::Response;
 use axum_extra::extract::TypedHeader;
 use axum_extra::extract::cookie::Cookie;
 use axum_extra::headers::{Authorization, authorization::Bearer};
-use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation, decode, encode};
+use jsonwebtoken::jwk::JwkSet;
 use serde::{Deserialize, Serialize};
 use tracing::instrument;
 
 use crate::errors::AuthError;
+use crate::middleware::csrf::CsrfToken;
+use crate::models::user::{Role, UserModel};
+use crate::routes::flash::{self, Flash};
+use crate::services::jwt::JwtService;
 use crate::startup::AppState;
 
 #[derive(Template)]
 #[template(path = "signup.html")]
-struct SignupTemplate {}
+struct SignupTemplate {
+    csrf_token: String,
+    flash: Flash,
+    min_password_length: usize,
+}
 
 #[derive(Template)]
 #[template(path = "login.html")]
-struct LoginTemplate {}
+struct LoginTemplate {
+    csrf_token: String,
+    flash: Flash,
+    password_login_enabled: bool,
+    oidc_name: Option<String>,
+}
 
-pub async fn signup_page() -> impl IntoResponse {
-    Html(SignupTemplate {}.render().unwrap())
+pub async fn signup_page(
+    State(state): State<AppState>,
+    CsrfToken(csrf_token): CsrfToken,
+    jar: SignedCookieJar,
+) -> impl IntoResponse {
+    if !state.auth_service.password_login_enabled() {
+        return Redirect::to("/login").into_response();
+    }
+    let (jar, flash) = flash::take(jar);
+    let template = SignupTemplate {
+        csrf_token,
+        flash,
+        min_password_length: state.auth_service.password_policy().min_length(),
+    };
+    (jar, Html(template.render().unwrap())).into_response()
 }
 
-pub async fn login_page() -> impl IntoResponse {
-    Html(LoginTemplate {}.render().unwrap())
+pub async fn login_page(
+    State(state): State<AppState>,
+    CsrfToken(csrf_token): CsrfToken,
+    jar: SignedCookieJar,
+) -> impl IntoResponse {
+    let (jar, flash) = flash::take(jar);
+    let template = LoginTemplate {
+        csrf_token,
+        flash,
+        password_login_enabled: state.auth_service.password_login_enabled(),
+        oidc_name: state
+            .oidc_service
+            .as_ref()
+            .map(|oidc| oidc.display_name().to_string()),
+    };
+    (jar, Html(template.render().unwrap()))
 }
 
-#[instrument(name = "Web: Login POST", skip(state, jar, payload))]
+/// The HttpOnly cookie carrying the session JWT for browser clients
+pub(crate) fn session_cookie(token: String) -> Cookie<'static> {
+    Cookie::build(("jwt", token))
+        .path("/")
+        .http_only(true)
+        .same_site(axum_extra::extract::cookie::SameSite::Lax)
+        .build()
+}
+
+#[instrument(
+    name = "Web: Login POST",
+    skip(state, jar, flash_jar, headers, payload)
+)]
 pub async fn login_post(
     State(state): State<AppState>,
     jar: CookieJar,
+    flash_jar: SignedCookieJar,
+    headers: HeaderMap,
     Form(payload): Form<AuthPayload>,
-) -> Result<impl IntoResponse, AuthError> {
+) -> Response {
     tracing::info!("Request to login user recieved!");
     // 1. Verify credentials via service
-    let user_id = state
+    let user = match state
         .auth_service
         .login(&payload.email, &payload.password)
-        .await?;
+        .await
+    {
+        Ok(user) => user,
+        Err(e) => {
+            return flash::form_error(
+                flash_jar,
+                &headers,
+                "/login",
+                e,
+                &[("email", &payload.email)],
+            );
+        }
+    };
 
     // 2. Create JWT
-    let claims = Claims {
-        sub: user_id.to_string(),
-        exp: (chrono::Utc::now() + chrono::Duration::hours(24)).timestamp() as usize,
+    let claims = Claims::for_user(&user, &state.jwt_service);
+    let token = match state.jwt_service.encode(&claims) {
+        Ok(token) => token,
+        Err(e) => return flash::form_error(flash_jar, &headers, "/login", e, &[]),
     };
-    let token = encode(&Header::default(), &claims, &KEYS.encoding)
-        .map_err(|_| AuthError::TokenCreation)?;
 
     // 3. Set HttpOnly Cookie and Redirect to Dashboard
-    let cookie = Cookie::build(("jwt", token))
-        .path("/")
-        .http_only(true)
-        .same_site(axum_extra::extract::cookie::SameSite::Lax);
-
-    Ok((jar.add(cookie), Redirect::to("/dashboard")))
+    (jar.add(session_cookie(token)), Redirect::to("/dashboard")).into_response()
 }
 
-#[instrument(name = "Web: Signup POST", skip(state, payload))]
+#[instrument(name = "Web: Signup POST", skip(state, jar, headers, payload))]
 pub async fn signup_post(
     State(state): State<AppState>,
+    jar: SignedCookieJar,
+    headers: HeaderMap,
     Form(payload): Form<AuthPayload>,
-) -> Result<impl IntoResponse, AuthError> {
-    state
+) -> Response {
+    if !state.auth_service.password_login_enabled() {
+        return flash::form_error(
+            jar,
+            &headers,
+            "/login",
+            AuthError::PasswordLoginDisabled,
+            &[],
+        );
+    }
+    match state
         .auth_service
         .register(&payload.email, &payload.password)
         .await
-        .map_err(|_| AuthError::Internal)?;
-
-    Ok(Redirect::to("/login"))
+    {
+        Ok(_) => {
+            let flash = Flash::success("Account created, sign in to continue")
+                .with_input("email", &payload.email);
+            (flash::put(jar, &flash), Redirect::to("/login")).into_response()
+        }
+        // Problems are shown next to the fields, the email is kept
+        Err(e) => flash::form_error(jar, &headers, "/signup", e, &[("email", &payload.email)]),
+    }
 }
 
-#[instrument(name = "Web: Logout GET", skip(jar))]
+#[instrument(name = "Web: Logout POST", skip(jar))]
 pub async fn logout_handler(jar: CookieJar) -> impl IntoResponse {
     let updated_jar = jar.remove(Cookie::from("jwt"));
     (updated_jar, Redirect::to("/login"))
 }
 
-static KEYS: LazyLock<Keys> = LazyLock::new(|| {
-    let secret = std::env::var("JWT_SECRET").expect("JWT_SECRET must be set");
-    Keys::new(secret.as_bytes())
-});
-
-pub struct Keys {
-    encoding: EncodingKey,
-    decoding: DecodingKey,
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Claims {
+    pub sub: String,
+    pub iss: String,
+    pub iat: usize,
+    pub exp: usize,
+    // Tokens issued before roles existed carry no role claim
+    #[serde(default)]
+    pub role: Role,
+    /// Session version of the user when the token was issued
+    #[serde(default)]
+    pub ver: i32,
 }
 
-impl Keys {
-    pub fn new(secret: &[u8]) -> Self {
+impl Claims {
+    pub fn for_user(user: &UserModel, jwt: &JwtService) -> Self {
         Self {
-            encoding: EncodingKey::from_secret(secret),
-            decoding: DecodingKey::from_secret(secret),
+            sub: user.id.to_string(),
+            iss: jwt.issuer().to_string(),
+            iat: chrono::Utc::now().timestamp() as usize,
+            exp: jwt.expires_at(),
+            role: user.role,
+            ver: user.session_version,
         }
     }
-}
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Claims {
-    pub sub: String,
-    pub exp: usize,
+    pub fn is_admin(&self) -> bool {
+        self.role == Role::Admin
+    }
 }
 
+/// Wraps an authenticated extractor for HTML pages: a missing, expired or
+/// revoked session sends the browser to `/login` instead of a JSON error
+#[derive(Debug, Clone)]
+pub struct Page<T>(pub T);
+
+/// Claims of an authenticated user holding the admin role
+#[derive(Debug, Clone)]
+pub struct AdminClaims(pub Claims);
+
 #[derive(Debug, Serialize)]
 pub struct AuthBody {
     access_token: String,
@@ -145,11 +237,13 @@ pub async fn register_handler(
     State(state): State<AppState>,
     Json(payload): Json<AuthPayload>,
 ) -> Result<impl IntoResponse, AuthError> {
+    if !state.auth_service.password_login_enabled() {
+        return Err(AuthError::PasswordLoginDisabled);
+    }
     state
         .auth_service
         .register(&payload.email, &payload.password)
-        .await
-        .map_err(|_| AuthError::UserAlreadyExists)?;
+        .await?;
 
     Ok(StatusCode::CREATED)
 }
@@ -168,7 +262,7 @@ pub async fn authorize_handler(
 ) -> Result<Json<AuthBody>, AuthError> {
     tracing::info!("Received login request");
 
-    let user_id = state
+    let user = state
         .auth_service
         .login(&payload.email, &payload.password)
         .await
@@ -177,28 +271,27 @@ pub async fn authorize_handler(
             e
         })?;
 
-    let claims = Claims {
-        sub: user_id.to_string(),
-        exp: (chrono::Utc::now() + chrono::Duration::hours(24)).timestamp() as usize,
-    };
-
-    let token = encode(&Header::default(), &claims, &KEYS.encoding).map_err(|e| {
-        tracing::error!("JWT Encoding failed: {:?}", e);
-        AuthError::TokenCreation
-    })?;
+    let claims = Claims::for_user(&user, &state.jwt_service);
+    let token = state.jwt_service.encode(&claims)?;
 
     tracing::info!("JWT issued for user");
     Ok(Json(AuthBody::new(token)))
 }
 
+/// Public signing keys so other services can verify shorty-issued tokens
+pub async fn jwks_handler(State(state): State<AppState>) -> Json<JwkSet> {
+    Json(state.jwt_service.jwks())
+}
+
 impl<S> FromRequestParts<S> for Claims
 where
+    AppState: FromRef<S>,
     S: Send + Sync,
 {
     type Rejection = AuthError;
 
-    #[instrument(name = "Extracting Claims", skip(_state, parts))]
-    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+    #[instrument(name = "Extracting Claims", skip(state, parts))]
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
         // 1. Try to get token from Cookies (for Browser/Dashboard)
         let cookie_token = parts
             .extract::<CookieJar>()
@@ -220,13 +313,60 @@ where
             bearer.token().to_string()
         };
 
-        // 3. Decode the token
-        let token_data =
-            decode::<Claims>(&token, &KEYS.decoding, &Validation::default()).map_err(|e| {
-                tracing::error!("JWT decoding failed: {:?}", e);
-                AuthError::InvalidToken
-            })?;
+        // 3. Decode the token with the key named in its header
+        let state = AppState::from_ref(state);
+        let mut claims: Claims = state.jwt_service.decode(&token)?;
+
+        // 4. Make sure the account still exists and has not been disabled.
+        //    The role is taken from the database so demotions apply immediately.
+        let user_id = uuid::Uuid::parse_str(&claims.sub).map_err(|_| AuthError::InvalidToken)?;
+        let user = state.auth_service.active_user(user_id).await?;
+        claims.role = user.role;
+
+        // 5. Reject sessions issued before the last password change
+        if claims.ver != user.session_version {
+            tracing::warn!(%user_id, "Session was revoked by a credential change");
+            return Err(AuthError::InvalidToken);
+        }
 
-        Ok(token_data.claims)
+        Ok(claims)
+    }
+}
+
+impl<S> FromRequestParts<S> for AdminClaims
+where
+    AppState: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = AuthError;
+
+    #[instrument(name = "Extracting Admin Claims", skip(state, parts))]
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let claims = Claims::from_request_parts(parts, state).await?;
+        if !claims.is_admin() {
+            tracing::warn!(user_id = %claims.sub, "Non-admin tried to access admin area");
+            return Err(AuthError::Forbidden);
+        }
+        Ok(AdminClaims(claims))
+    }
+}
+
+impl<S, T> FromRequestParts<S> for Page<T>
+where
+    T: FromRequestParts<S, Rejection = AuthError>,
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        match T::from_request_parts(parts, state).await {
+            Ok(inner) => Ok(Page(inner)),
+            Err(AuthError::InvalidToken) => {
+                // Drop the stale cookie so the login page does not bounce back
+                let jar = CookieJar::new().remove(Cookie::from("jwt"));
+                Err((jar, Redirect::to("/login")).into_response())
+            }
+            Err(e) => Err(e.into_response()),
+        }
     }
 }
@@ -1,36 +1,123 @@
-use crate::{models::url::UrlModel, routes::auth::Claims, startup::AppState};
+use crate::{
+    errors::AuthError,
+    middleware::csrf::CsrfToken,
+    models::{
+        plan::PlanUsage,
+        tag::{FolderModel, TagModel},
+        url::{LinkFilter, LinkPage, LinkSort, LinkStatus, QueryPassthrough, SortOrder},
+        user::UserModel,
+        utm::UtmPreset,
+        workspace::WorkspaceMembership,
+    },
+    routes::{
+        auth::Page,
+        flash::{self, Flash},
+        workspace::CurrentWorkspace,
+    },
+    startup::AppState,
+};
 use askama::Template;
 use axum::{
-    extract::State,
-    response::{Html, IntoResponse},
+    extract::{Query, State},
+    http::HeaderMap,
+    response::{Html, IntoResponse, Response},
 };
+use axum_extra::extract::SignedCookieJar;
 
 #[derive(Template)]
 #[template(path = "dashboard.html")]
 struct DashboardTemplate {
-    email: String,
-    urls: Vec<UrlModel>,
-    total_clicks: i32,
+    csrf_token: String,
+    flash: Flash,
+    user: UserModel,
+    usage: PlanUsage,
+    page: LinkPage,
+    filter: LinkFilter,
+    tags: Vec<TagModel>,
+    folders: Vec<FolderModel>,
+    utm_presets: Vec<UtmPreset>,
+    total_links: i64,
+    total_clicks: i64,
+    is_admin: bool,
+    workspace: WorkspaceMembership,
+    workspaces: Vec<WorkspaceMembership>,
 }
 
 pub async fn dashboard_handler(
     State(state): State<AppState>,
-    claims: Claims, // Authenticated user
-) -> impl IntoResponse {
-    // 1. Fetch user URLs from DB
-    let user_id = uuid::Uuid::parse_str(&claims.sub).unwrap();
-    let urls = state
+    Page(current): Page<CurrentWorkspace>, // Authenticated user and selected workspace
+    CsrfToken(csrf_token): CsrfToken,
+    jar: SignedCookieJar,
+    headers: HeaderMap,
+    Query(filter): Query<LinkFilter>,
+) -> Result<Response, AuthError> {
+    let user = state.auth_service.active_user(current.user_id).await?;
+    let usage = state
+        .plan_service
+        .usage(current.user_id)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to load plan usage: {:?}", e);
+            AuthError::Internal
+        })?;
+
+    // 1. Fetch one page of workspace URLs from DB
+    let page = match state
         .url_service
-        .get_user_urls(user_id)
+        .get_workspace_urls(current.workspace.id, &filter)
+        .await
+    {
+        Ok(page) => page,
+        // A bad filter or cursor goes back to the plain dashboard, which has
+        // neither, anything else would come straight back here
+        Err(e @ AuthError::Validation(_)) => {
+            return Ok(flash::form_error(jar, &headers, "/dashboard", e, &[]));
+        }
+        Err(e) => return Err(e),
+    };
+    let (total_links, total_clicks) = state
+        .url_service
+        .workspace_totals(current.workspace.id)
+        .await
+        .unwrap_or_default();
+    let tags = state
+        .tag_service
+        .list_tags(current.workspace.id)
+        .await
+        .unwrap_or_default();
+    let folders = state
+        .tag_service
+        .list_folders(current.workspace.id)
+        .await
+        .unwrap_or_default();
+    let utm_presets = state
+        .utm_service
+        .list_presets(current.user_id)
+        .await
+        .unwrap_or_default();
+    let workspaces = state
+        .workspace_service
+        .list_for_user(current.user_id)
         .await
         .unwrap_or_default();
-    let total_clicks = urls.iter().map(|u| u.clicks).sum();
 
     // 2. Render Template
+    let (jar, flash) = flash::take(jar);
     let template = DashboardTemplate {
-        email: claims.sub,
-        urls,
+        csrf_token,
+        flash,
+        is_admin: current.claims.is_admin(),
+        user,
+        usage,
+        page,
+        filter,
+        tags,
+        folders,
+        utm_presets,
+        total_links,
         total_clicks,
+        workspace: current.workspace,
+        workspaces,
     };
-    Html(template.render().unwrap())
+    Ok((jar, Html(template.render().unwrap())).into_response())
 }
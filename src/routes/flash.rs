@@ -0,0 +1,144 @@
+use std::collections::BTreeMap;
+
+use axum::{
+    http::{HeaderMap, header},
+    response::{IntoResponse, Redirect, Response},
+};
+use axum_extra::extract::{SignedCookieJar, cookie::Cookie};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use serde::{Deserialize, Serialize};
+
+use crate::errors::AuthError;
+
+/// Signed cookie carrying a message across a redirect
+pub const FLASH_COOKIE: &str = "flash";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FlashLevel {
+    #[default]
+    Success,
+    Error,
+}
+
+/// One-shot message shown on the next page, with inline errors and the
+/// values to put back into the form. An empty message means there is nothing to show.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Flash {
+    pub level: FlashLevel,
+    pub message: String,
+    #[serde(default)]
+    pub fields: BTreeMap<String, Vec<String>>,
+    /// Never holds passwords
+    #[serde(default)]
+    pub input: BTreeMap<String, String>,
+}
+
+impl Flash {
+    pub fn success(message: impl Into<String>) -> Self {
+        Self {
+            level: FlashLevel::Success,
+            message: message.into(),
+            ..Self::default()
+        }
+    }
+
+    pub fn error(error: &AuthError) -> Self {
+        let fields = match error {
+            AuthError::Validation(fields) => fields
+                .iter()
+                .map(|(field, messages)| (field.to_string(), messages.clone()))
+                .collect(),
+            _ => BTreeMap::new(),
+        };
+        Self {
+            level: FlashLevel::Error,
+            message: error.status_and_message().1.to_string(),
+            fields,
+            input: BTreeMap::new(),
+        }
+    }
+
+    pub fn with_input(mut self, field: &str, value: &str) -> Self {
+        self.input.insert(field.to_string(), value.to_string());
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.level == FlashLevel::Error
+    }
+
+    pub fn field_errors(&self, field: &str) -> &[String] {
+        self.fields
+            .get(field)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// The submitted value of a field, or an empty string
+    pub fn input(&self, field: &str) -> &str {
+        self.input
+            .get(field)
+            .map(String::as_str)
+            .unwrap_or_default()
+    }
+}
+
+/// Read the pending flash and drop its cookie, the returned jar has to be
+/// part of the response
+pub fn take(jar: SignedCookieJar) -> (SignedCookieJar, Flash) {
+    let Some(cookie) = jar.get(FLASH_COOKIE) else {
+        return (jar, Flash::default());
+    };
+    let flash = URL_SAFE_NO_PAD
+        .decode(cookie.value())
+        .ok()
+        .and_then(|json| serde_json::from_slice(&json).ok())
+        .unwrap_or_default();
+    (jar.remove(Cookie::from(FLASH_COOKIE)), flash)
+}
+
+pub fn put(jar: SignedCookieJar, flash: &Flash) -> SignedCookieJar {
+    // The JSON is encoded because cookie values cannot hold quotes or semicolons
+    let value = URL_SAFE_NO_PAD.encode(serde_json::to_vec(flash).unwrap_or_default());
+    jar.add(
+        Cookie::build((FLASH_COOKIE, value))
+            .path("/")
+            .http_only(true)
+            .same_site(axum_extra::extract::cookie::SameSite::Lax)
+            .build(),
+    )
+}
+
+/// Whether the client asked for JSON rather than HTML
+pub fn wants_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|accept| accept.contains("application/json"))
+}
+
+/// Answer a failed form submission: clients asking for JSON get the error
+/// body, browsers are sent back to `to` with the error and their input flashed
+pub fn form_error(
+    jar: SignedCookieJar,
+    headers: &HeaderMap,
+    to: &str,
+    error: AuthError,
+    input: &[(&str, &str)],
+) -> Response {
+    if wants_json(headers) {
+        return error.into_response();
+    }
+    let flash = input
+        .iter()
+        .fold(Flash::error(&error), |flash, (field, value)| {
+            flash.with_input(field, value)
+        });
+    (put(jar, &flash), Redirect::to(to)).into_response()
+}
+
+/// Send the browser to `to` with a success message
+pub fn redirect_with(jar: SignedCookieJar, to: &str, message: &str) -> Response {
+    (put(jar, &Flash::success(message)), Redirect::to(to)).into_response()
+}
@@ -0,0 +1,328 @@
+use axum::{
+    Json,
+    extract::{Query, State},
+    http::{HeaderMap, HeaderValue, header},
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use serde_json::Value;
+use tracing::instrument;
+
+use crate::{
+    errors::{AuthError, FieldErrors},
+    models::import::ImportMode,
+    routes::{
+        profile::ExportFormat,
+        url::{CreateUrlForm, validate_url_form},
+        workspace::CurrentWorkspace,
+    },
+    services::url::{ImportRow, import_report_csv},
+    startup::AppState,
+};
+
+/// Most rows a single upload may hold
+const MAX_IMPORT_ROWS: usize = 1000;
+
+#[derive(Debug, Deserialize)]
+pub struct ImportQuery {
+    #[serde(default)]
+    pub mode: ImportMode,
+    /// Format of the per-row report
+    #[serde(default)]
+    pub report: ExportFormat,
+}
+
+/// One link of an upload. `long_url` is accepted for `url` so a link export
+/// can be imported again.
+#[derive(Debug, Deserialize)]
+struct ImportLine {
+    #[serde(alias = "long_url")]
+    url: String,
+    #[serde(default)]
+    site_name: String,
+    #[serde(default)]
+    alias: String,
+    /// A number or a numeric string
+    #[serde(default)]
+    expires_in_days: Value,
+    #[serde(default)]
+    folder_id: String,
+    /// A comma or semicolon separated string, or a JSON array
+    #[serde(default)]
+    tags: Value,
+    #[serde(default)]
+    password: String,
+    /// A number or a numeric string
+    #[serde(default)]
+    max_clicks: Value,
+    #[serde(default)]
+    starts_at: String,
+    #[serde(default)]
+    fallback_url: String,
+    #[serde(default)]
+    query_passthrough: String,
+    /// A boolean or a string such as `true`
+    #[serde(default)]
+    path_passthrough: Value,
+    #[serde(default)]
+    utm_source: String,
+    #[serde(default)]
+    utm_medium: String,
+    #[serde(default)]
+    utm_campaign: String,
+    #[serde(default)]
+    utm_term: String,
+    #[serde(default)]
+    utm_content: String,
+}
+
+impl ImportLine {
+    fn into_row(self, line: usize) -> ImportRow {
+        let form = CreateUrlForm {
+            url: self.url,
+            site_name: self.site_name,
+            alias: self.alias,
+            expires_in_days: text(&self.expires_in_days),
+            folder_id: self.folder_id,
+            tags: text(&self.tags).replace(';', ","),
+            password: self.password,
+            max_clicks: text(&self.max_clicks),
+            starts_at: self.starts_at,
+            fallback_url: self.fallback_url,
+            query_passthrough: self.query_passthrough,
+            path_passthrough: text(&self.path_passthrough),
+            utm_source: self.utm_source,
+            utm_medium: self.utm_medium,
+            utm_campaign: self.utm_campaign,
+            utm_term: self.utm_term,
+            utm_content: self.utm_content,
+            // Presets are not applied to imports, rows carry their parameters
+            utm_preset: String::new(),
+        };
+        ImportRow {
+            line,
+            long_url: form.url.trim().to_string(),
+            site_name: form.site_name.trim().to_string(),
+            options: validate_url_form(&form),
+        }
+    }
+}
+
+/// Create links in bulk from a CSV (`text/csv`) or JSON lines
+/// (`application/x-ndjson`) body and answer with a per-row report
+#[instrument(
+    name = "HTTP: Import urls",
+    skip(state, current, headers, body),
+    fields(workspace_id = %current.workspace.id)
+)]
+pub async fn import_handler(
+    State(state): State<AppState>,
+    current: CurrentWorkspace,
+    headers: HeaderMap,
+    Query(query): Query<ImportQuery>,
+    body: String,
+) -> Result<Response, AuthError> {
+    if !current.workspace.role.can_edit() {
+        return Err(AuthError::Forbidden);
+    }
+    let rows = parse_upload(&headers, &body).map_err(|message| {
+        let mut errors = FieldErrors::new();
+        errors.insert("file", vec![message]);
+        AuthError::Validation(errors)
+    })?;
+
+    let report = state
+        .url_service
+        .import(current.user_id, current.workspace.id, rows, query.mode)
+        .await?;
+
+    match query.report {
+        ExportFormat::Json => Ok(Json(report).into_response()),
+        ExportFormat::Csv => {
+            let body = import_report_csv(&report.results).map_err(|e| {
+                tracing::error!("Failed to write import report: {:?}", e);
+                AuthError::Internal
+            })?;
+            let mut headers = HeaderMap::new();
+            headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("text/csv"));
+            headers.insert(
+                header::CONTENT_DISPOSITION,
+                HeaderValue::from_static("attachment; filename=\"shorty-import-report.csv\""),
+            );
+            // The CSV only lists rows, the totals travel alongside it
+            headers.insert("x-import-created", HeaderValue::from(report.created));
+            headers.insert("x-import-failed", HeaderValue::from(report.failed));
+            Ok((headers, body).into_response())
+        }
+    }
+}
+
+/// Read the rows of an upload, the format follows its content type
+fn parse_upload(headers: &HeaderMap, body: &str) -> Result<Vec<ImportRow>, String> {
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+    let rows = match content_type.split(';').next().unwrap_or_default().trim() {
+        "text/csv" => parse_csv(body)?,
+        "application/x-ndjson" | "application/jsonl" | "application/json" => parse_json_lines(body),
+        _ => return Err("Upload a .csv or a JSON lines file".to_string()),
+    };
+    if rows.is_empty() {
+        return Err("The file has no links".to_string());
+    }
+    if rows.len() > MAX_IMPORT_ROWS {
+        return Err(format!(
+            "Upload at most {} links at a time",
+            MAX_IMPORT_ROWS
+        ));
+    }
+    Ok(rows)
+}
+
+fn parse_csv(body: &str) -> Result<Vec<ImportRow>, String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(body.as_bytes());
+    let header = reader
+        .headers()
+        .map_err(|e| format!("The CSV header cannot be read: {}", e))?
+        .clone();
+    if !header
+        .iter()
+        .any(|column| column == "url" || column == "long_url")
+    {
+        return Err("The CSV needs a url column".to_string());
+    }
+
+    let mut rows = Vec::new();
+    for record in reader.records() {
+        let row = match record {
+            Ok(record) => {
+                let line = csv_line(body, record.position());
+                match record.deserialize::<ImportLine>(Some(&header)) {
+                    Ok(parsed) => parsed.into_row(line),
+                    Err(e) => unreadable(line, e),
+                }
+            }
+            Err(e) => unreadable(csv_line(body, e.position()), e),
+        };
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+/// Line a CSV record starts on. The reader's position is taken before the
+/// blank lines it skips, so those are counted here.
+fn csv_line(body: &str, position: Option<&csv::Position>) -> usize {
+    let Some(position) = position else {
+        return 0;
+    };
+    let skipped = body
+        .get(position.byte() as usize..)
+        .unwrap_or_default()
+        .split('\n')
+        .take_while(|line| line.trim_end_matches('\r').is_empty())
+        .count();
+    position.line() as usize + skipped
+}
+
+fn parse_json_lines(body: &str) -> Vec<ImportRow> {
+    body.lines()
+        .enumerate()
+        .filter(|(_, raw)| !raw.trim().is_empty())
+        .map(
+            |(index, raw)| match serde_json::from_str::<ImportLine>(raw) {
+                Ok(parsed) => parsed.into_row(index + 1),
+                Err(e) => unreadable(index + 1, e),
+            },
+        )
+        .collect()
+}
+
+fn unreadable(line: usize, e: impl std::fmt::Display) -> ImportRow {
+    let mut errors = FieldErrors::new();
+    errors.insert("row", vec![format!("The row cannot be read: {}", e)]);
+    ImportRow {
+        line,
+        long_url: String::new(),
+        site_name: String::new(),
+        options: Err(errors),
+    }
+}
+
+/// A loosely typed value as form text: numbers as digits, arrays joined with commas
+fn text(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        Value::Array(items) => items.iter().map(text).collect::<Vec<_>>().join(","),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(rows: &[ImportRow]) -> Vec<(usize, &str)> {
+        rows.iter()
+            .map(|row| (row.line, row.long_url.as_str()))
+            .collect()
+    }
+
+    fn unreadable_row(row: &ImportRow) -> bool {
+        row.options.as_ref().is_err_and(|e| e.contains_key("row"))
+    }
+
+    #[test]
+    fn csv_rows_keep_their_file_line() {
+        let body = "url,site_name\nhttps://a.example,A\n\nhttps://b.example,\"B\nsplit\"\nhttps://c.example,C\n";
+        let rows = parse_csv(body).unwrap();
+        // Blank lines are skipped and a quoted line break stays in its row
+        assert_eq!(
+            lines(&rows),
+            vec![
+                (2, "https://a.example"),
+                (4, "https://b.example"),
+                (6, "https://c.example"),
+            ]
+        );
+    }
+
+    #[test]
+    fn csv_lines_count_windows_line_breaks() {
+        let body = "url\r\nhttps://a.example\r\n\r\n\r\nhttps://b.example\r\n";
+        let rows = parse_csv(body).unwrap();
+        assert_eq!(
+            lines(&rows),
+            vec![(2, "https://a.example"), (5, "https://b.example")]
+        );
+    }
+
+    #[test]
+    fn unreadable_csv_rows_are_reported_on_their_line() {
+        let body = "long_url,site_name\nhttps://a.example,A\nhttps://b.example,B,extra\n";
+        let rows = parse_csv(body).unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].long_url, "https://a.example");
+        assert_eq!(rows[1].line, 3);
+        assert!(unreadable_row(&rows[1]));
+    }
+
+    #[test]
+    fn csv_needs_a_url_column() {
+        assert!(parse_csv("site_name\nA\n").is_err());
+    }
+
+    #[test]
+    fn json_lines_keep_their_file_line() {
+        let body = "{\"url\": \"https://a.example\"}\n\n  \n{\"long_url\": \"https://b.example\"}\n{oops\n";
+        let rows = parse_json_lines(body);
+        assert_eq!(
+            lines(&rows),
+            vec![(1, "https://a.example"), (4, "https://b.example"), (5, "")]
+        );
+        assert!(unreadable_row(&rows[2]));
+    }
+}
@@ -1,3 +1,12 @@
+pub mod admin;
 pub mod auth;
 pub mod dashboard;
+pub mod flash;
+pub mod import;
+pub mod oidc;
+pub mod profile;
+pub mod qr;
+pub mod tag;
 pub mod url;
+pub mod utm;
+pub mod workspace;
@@ -0,0 +1,95 @@
+use axum::{
+    extract::{Query, State},
+    response::{IntoResponse, Redirect},
+};
+use axum_extra::extract::{
+    CookieJar, SignedCookieJar,
+    cookie::{Cookie, SameSite},
+};
+use serde::Deserialize;
+use tracing::instrument;
+
+use crate::{
+    errors::AuthError,
+    routes::auth::{Claims, session_cookie},
+    services::oidc::OidcFlow,
+    startup::AppState,
+};
+
+const FLOW_COOKIE: &str = "oidc_flow";
+
+#[derive(Debug, Deserialize)]
+pub struct CallbackParams {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    pub error: Option<String>,
+}
+
+#[instrument(name = "Web: OIDC login", skip(state, signed))]
+pub async fn oidc_login(
+    State(state): State<AppState>,
+    signed: SignedCookieJar,
+) -> Result<impl IntoResponse, AuthError> {
+    let oidc = state.oidc_service.as_ref().ok_or(AuthError::NotFound)?;
+
+    let (url, flow) = oidc.authorization_url().await.map_err(|e| {
+        tracing::error!("OIDC discovery failed: {:?}", e);
+        AuthError::Internal
+    })?;
+    let flow = serde_json::to_string(&flow).map_err(|_| AuthError::Internal)?;
+
+    let cookie = Cookie::build((FLOW_COOKIE, flow))
+        .path("/auth/oidc")
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .max_age(time::Duration::minutes(10));
+
+    Ok((signed.add(cookie), Redirect::to(&url)))
+}
+
+#[instrument(name = "Web: OIDC callback", skip(state, signed, jar, params))]
+pub async fn oidc_callback(
+    State(state): State<AppState>,
+    signed: SignedCookieJar,
+    jar: CookieJar,
+    Query(params): Query<CallbackParams>,
+) -> Result<impl IntoResponse, AuthError> {
+    let oidc = state.oidc_service.as_ref().ok_or(AuthError::NotFound)?;
+
+    if let Some(error) = params.error {
+        tracing::warn!(%error, "OIDC provider returned an error");
+        return Err(AuthError::WrongCredentials);
+    }
+
+    // 1. The flow secrets must come back in our signed cookie with a matching state
+    let flow: OidcFlow = signed
+        .get(FLOW_COOKIE)
+        .and_then(|c| serde_json::from_str(c.value()).ok())
+        .ok_or_else(|| {
+            tracing::warn!("OIDC callback without a login in progress");
+            AuthError::InvalidToken
+        })?;
+    let signed = signed.remove(Cookie::build(FLOW_COOKIE).path("/auth/oidc"));
+    if !flow.matches_state(params.state.as_deref()) {
+        tracing::warn!("OIDC state mismatch");
+        return Err(AuthError::InvalidToken);
+    }
+    let code = params.code.ok_or(AuthError::MissingCredentials)?;
+
+    // 2. Exchange the code and validate the ID token
+    let identity = oidc.exchange(&code, &flow).await.map_err(|e| {
+        tracing::error!("OIDC code exchange failed: {:?}", e);
+        AuthError::InvalidToken
+    })?;
+
+    // 3. Find, link or provision the local user and start a session
+    let user = state.auth_service.login_oidc(&identity).await?;
+    let claims = Claims::for_user(&user, &state.jwt_service);
+    let token = state.jwt_service.encode(&claims)?;
+
+    Ok((
+        signed,
+        jar.add(session_cookie(token)),
+        Redirect::to("/dashboard"),
+    ))
+}
@@ -0,0 +1,409 @@
+use askama::Template;
+use axum::{
+    Form,
+    extract::{Query, State},
+    http::{HeaderMap, header},
+    response::{Html, IntoResponse, Response},
+};
+use axum_extra::extract::{CookieJar, SignedCookieJar, cookie::Cookie};
+use serde::Deserialize;
+use tracing::instrument;
+use uuid::Uuid;
+
+use crate::{
+    errors::AuthError,
+    middleware::csrf::CsrfToken,
+    models::{user::UserModel, utm::UtmPreset},
+    routes::{
+        auth::{Claims, Page, session_cookie},
+        flash::{self, Flash},
+        workspace::WORKSPACE_COOKIE,
+    },
+    services::privacy::links_csv,
+    startup::AppState,
+};
+
+#[derive(Template)]
+#[template(path = "profile.html")]
+struct ProfileTemplate {
+    csrf_token: String,
+    user: UserModel,
+    utm_presets: Vec<UtmPreset>,
+    flash: Flash,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateProfileForm {
+    #[serde(default)]
+    pub display_name: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FallbackUrlForm {
+    /// Empty to answer unavailable links with an error page
+    #[serde(default)]
+    pub fallback_url: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChangeEmailForm {
+    pub email: String,
+    pub password: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ConfirmEmailQuery {
+    pub token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChangePasswordForm {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+/// What happens to the links of a deleted account
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkDisposal {
+    Delete,
+    Orphan,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeleteAccountForm {
+    // Single sign-on users have no password to confirm with
+    #[serde(default)]
+    pub password: String,
+    pub links: LinkDisposal,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Json,
+    Csv,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExportQuery {
+    #[serde(default)]
+    pub format: ExportFormat,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EraseAccountForm {
+    #[serde(default)]
+    pub password: String,
+}
+
+#[instrument(name = "Web: Profile page", skip(state, claims))]
+pub async fn profile_page(
+    State(state): State<AppState>,
+    Page(claims): Page<Claims>,
+    CsrfToken(csrf_token): CsrfToken,
+    jar: SignedCookieJar,
+) -> Result<impl IntoResponse, AuthError> {
+    let user_id = Uuid::parse_str(&claims.sub).map_err(|_| AuthError::InvalidToken)?;
+    let user = state.auth_service.active_user(user_id).await?;
+    let utm_presets = state.utm_service.list_presets(user_id).await.map_err(|e| {
+        tracing::error!("Failed to list UTM presets: {:?}", e);
+        AuthError::Internal
+    })?;
+
+    let (jar, flash) = flash::take(jar);
+    let template = ProfileTemplate {
+        csrf_token,
+        user,
+        utm_presets,
+        flash,
+    };
+    Ok((jar, Html(template.render().unwrap())))
+}
+
+#[instrument(name = "Web: Update profile", skip(state, claims, jar, headers, form))]
+pub async fn update_profile_handler(
+    State(state): State<AppState>,
+    claims: Claims,
+    jar: SignedCookieJar,
+    headers: HeaderMap,
+    Form(form): Form<UpdateProfileForm>,
+) -> Response {
+    let result = async {
+        let user_id = Uuid::parse_str(&claims.sub).map_err(|_| AuthError::InvalidToken)?;
+        state
+            .auth_service
+            .update_display_name(user_id, &form.display_name)
+            .await
+            .map_err(|e| {
+                tracing::error!("Failed to update display name: {:?}", e);
+                AuthError::Internal
+            })
+    }
+    .await;
+
+    match result {
+        Ok(()) => flash::redirect_with(jar, "/dashboard", "Profile updated"),
+        Err(e) => flash::form_error(jar, &headers, "/dashboard", e, &[]),
+    }
+}
+
+/// Set where the user's links go when they cannot be followed and have no
+/// fallback of their own
+#[instrument(
+    name = "Web: Update fallback url",
+    skip(state, claims, jar, headers, form)
+)]
+pub async fn fallback_url_handler(
+    State(state): State<AppState>,
+    claims: Claims,
+    jar: SignedCookieJar,
+    headers: HeaderMap,
+    Form(form): Form<FallbackUrlForm>,
+) -> Response {
+    let result = async {
+        let user_id = Uuid::parse_str(&claims.sub).map_err(|_| AuthError::InvalidToken)?;
+        state
+            .auth_service
+            .update_fallback_url(user_id, &form.fallback_url)
+            .await
+    }
+    .await;
+
+    match result {
+        Ok(()) => flash::redirect_with(jar, "/profile", "Fallback url updated"),
+        Err(e) => flash::form_error(
+            jar,
+            &headers,
+            "/profile",
+            e,
+            &[("fallback_url", &form.fallback_url)],
+        ),
+    }
+}
+
+#[instrument(name = "Web: Change email", skip(state, claims, jar, headers, form))]
+pub async fn change_email_handler(
+    State(state): State<AppState>,
+    claims: Claims,
+    jar: SignedCookieJar,
+    headers: HeaderMap,
+    Form(form): Form<ChangeEmailForm>,
+) -> Response {
+    let result = async {
+        let user_id = Uuid::parse_str(&claims.sub).map_err(|_| AuthError::InvalidToken)?;
+        let email = form.email.trim();
+        if email.is_empty() || form.password.is_empty() {
+            return Err(AuthError::MissingCredentials);
+        }
+
+        state
+            .auth_service
+            .request_email_change(user_id, &form.password, email)
+            .await?;
+        tracing::info!(%user_id, "Email change requested");
+        Ok(())
+    }
+    .await;
+
+    match result {
+        Ok(()) => flash::redirect_with(
+            jar,
+            "/profile",
+            "Check your new inbox for a confirmation link",
+        ),
+        Err(e) => flash::form_error(jar, &headers, "/profile", e, &[("email", &form.email)]),
+    }
+}
+
+#[instrument(
+    name = "Web: Confirm email change",
+    skip(state, claims, jar, headers, query)
+)]
+pub async fn confirm_email_handler(
+    State(state): State<AppState>,
+    Page(claims): Page<Claims>,
+    jar: SignedCookieJar,
+    headers: HeaderMap,
+    Query(query): Query<ConfirmEmailQuery>,
+) -> Response {
+    let result = async {
+        let user_id = Uuid::parse_str(&claims.sub).map_err(|_| AuthError::InvalidToken)?;
+        state
+            .auth_service
+            .confirm_email_change(user_id, &query.token)
+            .await?;
+        tracing::info!(%user_id, "Email address changed");
+        Ok(())
+    }
+    .await;
+
+    match result {
+        Ok(()) => flash::redirect_with(jar, "/profile", "Your email address has been updated"),
+        Err(e) => flash::form_error(jar, &headers, "/profile", e, &[]),
+    }
+}
+
+#[instrument(
+    name = "Web: Change password",
+    skip(state, claims, jar, flash_jar, headers, form)
+)]
+pub async fn change_password_handler(
+    State(state): State<AppState>,
+    claims: Claims,
+    jar: CookieJar,
+    flash_jar: SignedCookieJar,
+    headers: HeaderMap,
+    Form(form): Form<ChangePasswordForm>,
+) -> Response {
+    let result = async {
+        let user_id = Uuid::parse_str(&claims.sub).map_err(|_| AuthError::InvalidToken)?;
+        if form.new_password.is_empty() {
+            return Err(AuthError::MissingCredentials);
+        }
+
+        let user = state
+            .auth_service
+            .change_password(user_id, &form.current_password, &form.new_password)
+            .await?;
+
+        // Older sessions are now rejected, keep this one signed in
+        let claims = Claims::for_user(&user, &state.jwt_service);
+        state.jwt_service.encode(&claims)
+    }
+    .await;
+
+    match result {
+        Ok(token) => (
+            jar.add(session_cookie(token)),
+            flash::redirect_with(
+                flash_jar,
+                "/profile",
+                "Password updated, other sessions have been signed out",
+            ),
+        )
+            .into_response(),
+        Err(e) => flash::form_error(flash_jar, &headers, "/profile", e, &[]),
+    }
+}
+
+#[instrument(
+    name = "Web: Delete account",
+    skip(state, claims, jar, flash_jar, headers, form)
+)]
+pub async fn delete_account_handler(
+    State(state): State<AppState>,
+    claims: Claims,
+    jar: CookieJar,
+    flash_jar: SignedCookieJar,
+    headers: HeaderMap,
+    Form(form): Form<DeleteAccountForm>,
+) -> Response {
+    let result = async {
+        let user_id = Uuid::parse_str(&claims.sub).map_err(|_| AuthError::InvalidToken)?;
+        let delete_links = matches!(form.links, LinkDisposal::Delete);
+
+        let deleted = state
+            .auth_service
+            .delete_account(user_id, &form.password, delete_links)
+            .await?;
+        if let Err(e) = state.url_service.forget_deleted(user_id, &deleted).await {
+            // The rows are gone, stale cache entries expire on their own
+            tracing::warn!("Failed to clear cache for deleted links: {:?}", e);
+        }
+        Ok(())
+    }
+    .await;
+
+    match result {
+        Ok(()) => signed_out(jar, flash_jar, "Your account has been deleted"),
+        Err(e) => flash::form_error(flash_jar, &headers, "/profile", e, &[]),
+    }
+}
+
+/// Clear the session after the account is gone and say so on the signup page
+fn signed_out(jar: CookieJar, flash_jar: SignedCookieJar, message: &str) -> Response {
+    let jar = jar
+        .remove(Cookie::from("jwt"))
+        .remove(Cookie::from(WORKSPACE_COOKIE));
+    (jar, flash::redirect_with(flash_jar, "/signup", message)).into_response()
+}
+
+/// Download everything stored about the signed-in user. The CSV flavour
+/// holds the links and their click counts, the JSON one adds the profile
+/// and workspace memberships.
+#[instrument(name = "Web: Export personal data", skip(state, claims))]
+pub async fn export_handler(
+    State(state): State<AppState>,
+    claims: Claims,
+    Query(query): Query<ExportQuery>,
+) -> Result<impl IntoResponse, AuthError> {
+    let user_id = Uuid::parse_str(&claims.sub).map_err(|_| AuthError::InvalidToken)?;
+    let export = state.privacy_service.export(user_id).await.map_err(|e| {
+        tracing::error!("Failed to export user data: {:?}", e);
+        AuthError::Internal
+    })?;
+
+    let (content_type, extension, body) = match query.format {
+        ExportFormat::Json => (
+            "application/json",
+            "json",
+            serde_json::to_vec_pretty(&export).map_err(|e| {
+                tracing::error!("Failed to serialize export: {:?}", e);
+                AuthError::Internal
+            })?,
+        ),
+        ExportFormat::Csv => (
+            "text/csv",
+            "csv",
+            links_csv(&export.links).map_err(|e| {
+                tracing::error!("Failed to write CSV export: {:?}", e);
+                AuthError::Internal
+            })?,
+        ),
+    };
+
+    tracing::info!(%user_id, format = extension, "Personal data exported");
+    let disposition = format!("attachment; filename=\"shorty-export.{}\"", extension);
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        body,
+    ))
+}
+
+/// Erase the account and its links for good, leaving only an audit record
+#[instrument(
+    name = "Web: Erase personal data",
+    skip(state, claims, jar, flash_jar, headers, form)
+)]
+pub async fn erase_account_handler(
+    State(state): State<AppState>,
+    claims: Claims,
+    jar: CookieJar,
+    flash_jar: SignedCookieJar,
+    headers: HeaderMap,
+    Form(form): Form<EraseAccountForm>,
+) -> Response {
+    let result = async {
+        let user_id = Uuid::parse_str(&claims.sub).map_err(|_| AuthError::InvalidToken)?;
+        state
+            .auth_service
+            .confirm_account_removal(user_id, &form.password)
+            .await?;
+        state.privacy_service.erase(user_id).await.map_err(|e| {
+            tracing::error!("Failed to erase user data: {:?}", e);
+            AuthError::Internal
+        })?;
+        Ok(())
+    }
+    .await;
+
+    match result {
+        Ok(()) => signed_out(jar, flash_jar, "Your data has been erased"),
+        Err(e) => flash::form_error(flash_jar, &headers, "/profile", e, &[]),
+    }
+}
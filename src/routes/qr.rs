@@ -0,0 +1,128 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::header,
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+use tracing::instrument;
+
+use crate::{
+    errors::{AuthError, FieldErrors},
+    services::qr::{MAX_QR_MARGIN, MAX_QR_SIZE, MIN_QR_SIZE, QrErrorCorrection, QrFormat, QrStyle},
+    startup::AppState,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct QrQuery {
+    #[serde(default)]
+    pub format: QrFormat,
+    /// Side in pixels
+    pub size: Option<u32>,
+    /// Blank border in modules
+    pub margin: Option<u32>,
+    #[serde(default)]
+    pub ec: QrErrorCorrection,
+    /// Color of the dark modules
+    pub fg: Option<String>,
+    /// Color of the background
+    pub bg: Option<String>,
+    /// Send the image as a file to save rather than to display
+    #[serde(default)]
+    pub download: bool,
+}
+
+impl QrQuery {
+    fn style(&self) -> Result<QrStyle, AuthError> {
+        let defaults = QrStyle::default();
+        let mut errors = FieldErrors::new();
+        let size = self.size.unwrap_or(defaults.size);
+        if !(MIN_QR_SIZE..=MAX_QR_SIZE).contains(&size) {
+            errors.insert(
+                "size",
+                vec![format!(
+                    "Use between {} and {} pixels",
+                    MIN_QR_SIZE, MAX_QR_SIZE
+                )],
+            );
+        }
+        let margin = self.margin.unwrap_or(defaults.margin);
+        if margin > MAX_QR_MARGIN {
+            errors.insert(
+                "margin",
+                vec![format!("Use at most {} modules", MAX_QR_MARGIN)],
+            );
+        }
+        let mut color = |field: &'static str, value: &Option<String>, default| match value {
+            Some(value) => value.parse().unwrap_or_else(|message| {
+                errors.insert(field, vec![message]);
+                default
+            }),
+            None => default,
+        };
+        let dark = color("fg", &self.fg, defaults.dark);
+        let light = color("bg", &self.bg, defaults.light);
+        if dark == light {
+            errors.insert(
+                "fg",
+                vec!["Pick a foreground that differs from the background".to_string()],
+            );
+        }
+        if !errors.is_empty() {
+            return Err(AuthError::Validation(errors));
+        }
+
+        Ok(QrStyle {
+            format: self.format,
+            size,
+            margin,
+            error_correction: self.ec,
+            dark,
+            light,
+        })
+    }
+}
+
+/// QR code of a short link. Public, like the link itself.
+#[instrument(name = "HTTP: QR code", skip(state))]
+pub async fn qr_handler(
+    State(state): State<AppState>,
+    Path(short_code): Path<String>,
+    Query(query): Query<QrQuery>,
+) -> Result<Response, AuthError> {
+    let style = query.style()?;
+    let exists = state.url_service.exists(&short_code).await.map_err(|e| {
+        tracing::error!("Failed to look up url: {:?}", e);
+        AuthError::Internal
+    })?;
+    if !exists {
+        return Err(AuthError::NotFound);
+    }
+
+    let content = state.qr_service.link_for(&short_code);
+    let image = state.qr_service.render(&content, &style).map_err(|e| {
+        tracing::error!("Failed to render QR code: {:?}", e);
+        AuthError::Internal
+    })?;
+
+    let disposition = if query.download {
+        format!(
+            "attachment; filename=\"{}-qr.{}\"",
+            short_code,
+            style.format.extension()
+        )
+    } else {
+        "inline".to_string()
+    };
+    Ok((
+        [
+            (
+                header::CONTENT_TYPE,
+                style.format.content_type().to_string(),
+            ),
+            (header::CONTENT_DISPOSITION, disposition),
+            (header::CACHE_CONTROL, "public, max-age=86400".to_string()),
+        ],
+        image,
+    )
+        .into_response())
+}
@@ -0,0 +1,262 @@
+use askama::Template;
+use axum::{
+    Form, Json,
+    extract::{Path, State},
+    http::HeaderMap,
+    response::{Html, IntoResponse, Response},
+};
+use axum_extra::extract::SignedCookieJar;
+use serde::Deserialize;
+use tracing::instrument;
+use uuid::Uuid;
+
+use crate::{
+    errors::{AuthError, FieldErrors},
+    middleware::csrf::CsrfToken,
+    models::{
+        tag::{FolderModel, TagModel},
+        workspace::WorkspaceMembership,
+    },
+    routes::{
+        auth::Page,
+        flash::{self, Flash},
+        workspace::CurrentWorkspace,
+    },
+    services::tag::parse_tags,
+    startup::AppState,
+};
+
+#[derive(Template)]
+#[template(path = "tags.html")]
+struct TagsTemplate {
+    csrf_token: String,
+    flash: Flash,
+    workspace: WorkspaceMembership,
+    tags: Vec<TagModel>,
+    folders: Vec<FolderModel>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NameForm {
+    pub name: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MergeTagForm {
+    pub into: Uuid,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LabelLinkForm {
+    /// Empty to take the link out of its folder
+    #[serde(default)]
+    pub folder_id: String,
+    #[serde(default)]
+    pub tags: String,
+}
+
+#[instrument(name = "Web: Tags page", skip(state, current))]
+pub async fn tags_page(
+    State(state): State<AppState>,
+    Page(current): Page<CurrentWorkspace>,
+    CsrfToken(csrf_token): CsrfToken,
+    jar: SignedCookieJar,
+) -> Result<impl IntoResponse, AuthError> {
+    let tags = state
+        .tag_service
+        .list_tags(current.workspace.id)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to list tags: {:?}", e);
+            AuthError::Internal
+        })?;
+    let folders = state
+        .tag_service
+        .list_folders(current.workspace.id)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to list folders: {:?}", e);
+            AuthError::Internal
+        })?;
+
+    let (jar, flash) = flash::take(jar);
+    let template = TagsTemplate {
+        csrf_token,
+        flash,
+        workspace: current.workspace,
+        tags,
+        folders,
+    };
+    Ok((jar, Html(template.render().unwrap())))
+}
+
+#[instrument(name = "HTTP: List tags", skip(state, current), fields(workspace_id = %current.workspace.id))]
+pub async fn list_tags(
+    State(state): State<AppState>,
+    current: CurrentWorkspace,
+) -> Result<Json<Vec<TagModel>>, AuthError> {
+    let tags = state
+        .tag_service
+        .list_tags(current.workspace.id)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to list tags: {:?}", e);
+            AuthError::Internal
+        })?;
+    Ok(Json(tags))
+}
+
+#[instrument(name = "HTTP: List folders", skip(state, current), fields(workspace_id = %current.workspace.id))]
+pub async fn list_folders(
+    State(state): State<AppState>,
+    current: CurrentWorkspace,
+) -> Result<Json<Vec<FolderModel>>, AuthError> {
+    let folders = state
+        .tag_service
+        .list_folders(current.workspace.id)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to list folders: {:?}", e);
+            AuthError::Internal
+        })?;
+    Ok(Json(folders))
+}
+
+#[instrument(name = "Web: Rename tag", skip(state, current, jar, headers, form))]
+pub async fn rename_tag_handler(
+    State(state): State<AppState>,
+    current: CurrentWorkspace,
+    jar: SignedCookieJar,
+    headers: HeaderMap,
+    Path(tag_id): Path<Uuid>,
+    Form(form): Form<NameForm>,
+) -> Response {
+    let result = state
+        .tag_service
+        .rename_tag(&current.workspace, tag_id, &form.name)
+        .await;
+    respond(jar, &headers, result, "Tag renamed")
+}
+
+#[instrument(name = "Web: Merge tags", skip(state, current, jar, headers, form))]
+pub async fn merge_tag_handler(
+    State(state): State<AppState>,
+    current: CurrentWorkspace,
+    jar: SignedCookieJar,
+    headers: HeaderMap,
+    Path(tag_id): Path<Uuid>,
+    Form(form): Form<MergeTagForm>,
+) -> Response {
+    let result = state
+        .tag_service
+        .merge_tags(&current.workspace, tag_id, form.into)
+        .await;
+    respond(jar, &headers, result, "Tags merged")
+}
+
+#[instrument(name = "Web: Delete tag", skip(state, current, jar, headers))]
+pub async fn delete_tag_handler(
+    State(state): State<AppState>,
+    current: CurrentWorkspace,
+    jar: SignedCookieJar,
+    headers: HeaderMap,
+    Path(tag_id): Path<Uuid>,
+) -> Response {
+    let result = state
+        .tag_service
+        .delete_tag(&current.workspace, tag_id)
+        .await;
+    respond(jar, &headers, result, "Tag deleted")
+}
+
+#[instrument(name = "Web: Create folder", skip(state, current, jar, headers, form))]
+pub async fn create_folder_handler(
+    State(state): State<AppState>,
+    current: CurrentWorkspace,
+    jar: SignedCookieJar,
+    headers: HeaderMap,
+    Form(form): Form<NameForm>,
+) -> Response {
+    let result = state
+        .tag_service
+        .create_folder(&current.workspace, &form.name)
+        .await
+        .map(|_| ());
+    respond(jar, &headers, result, "Folder created")
+}
+
+#[instrument(name = "Web: Rename folder", skip(state, current, jar, headers, form))]
+pub async fn rename_folder_handler(
+    State(state): State<AppState>,
+    current: CurrentWorkspace,
+    jar: SignedCookieJar,
+    headers: HeaderMap,
+    Path(folder_id): Path<Uuid>,
+    Form(form): Form<NameForm>,
+) -> Response {
+    let result = state
+        .tag_service
+        .rename_folder(&current.workspace, folder_id, &form.name)
+        .await;
+    respond(jar, &headers, result, "Folder renamed")
+}
+
+#[instrument(name = "Web: Delete folder", skip(state, current, jar, headers))]
+pub async fn delete_folder_handler(
+    State(state): State<AppState>,
+    current: CurrentWorkspace,
+    jar: SignedCookieJar,
+    headers: HeaderMap,
+    Path(folder_id): Path<Uuid>,
+) -> Response {
+    let result = state
+        .tag_service
+        .delete_folder(&current.workspace, folder_id)
+        .await;
+    respond(jar, &headers, result, "Folder deleted, its links were kept")
+}
+
+/// Move a link to a folder and replace its tags
+#[instrument(name = "Web: Label link", skip(state, current, jar, headers, form))]
+pub async fn label_link_handler(
+    State(state): State<AppState>,
+    current: CurrentWorkspace,
+    jar: SignedCookieJar,
+    headers: HeaderMap,
+    Path(short_code): Path<String>,
+    Form(form): Form<LabelLinkForm>,
+) -> Response {
+    let result = async {
+        let tags = parse_tags(&form.tags).map_err(|message| {
+            let mut errors = FieldErrors::new();
+            errors.insert("tags", vec![message]);
+            AuthError::Validation(errors)
+        })?;
+        let folder_id = match form.folder_id.trim() {
+            "" => None,
+            id => Some(Uuid::parse_str(id).map_err(|_| AuthError::NotFound)?),
+        };
+        state
+            .tag_service
+            .label_link(&current.workspace, &short_code, folder_id, &tags)
+            .await
+    }
+    .await;
+
+    match result {
+        Ok(()) => flash::redirect_with(jar, "/dashboard", "Link updated"),
+        Err(e) => flash::form_error(jar, &headers, "/dashboard", e, &[]),
+    }
+}
+
+fn respond(
+    jar: SignedCookieJar,
+    headers: &HeaderMap,
+    result: Result<(), AuthError>,
+    message: &str,
+) -> Response {
+    match result {
+        Ok(()) => flash::redirect_with(jar, "/tags", message),
+        Err(e) => flash::form_error(jar, headers, "/tags", e, &[]),
+    }
+}
@@ -1,53 +1,305 @@
 use std::collections::HashMap;
 
-use axum::{Form, Json, extract::{Path, Query, State}, http::{self, StatusCode}, response::{IntoResponse, Redirect}};
+use askama::Template;
+use axum::{
+    Form, Json,
+    body::Body,
+    extract::{OriginalUri, Path, Query, State},
+    http::{self, HeaderMap, HeaderValue, StatusCode, header},
+    response::{Html, IntoResponse, Redirect, Response},
+};
+use axum_extra::extract::{
+    SignedCookieJar,
+    cookie::{Cookie, SameSite},
+};
+use chrono::{DateTime, NaiveDateTime, NaiveTime, Utc};
+use futures_util::{StreamExt, TryStreamExt, future, stream};
 use tracing::instrument;
 use uuid::Uuid;
 
-use crate::{errors::AuthError, routes::auth::Claims, startup::AppState}; // Your JWT Claims struct
+use crate::{
+    errors::{AuthError, FieldErrors},
+    middleware::csrf::CsrfToken,
+    models::{
+        targeting::{DeviceClass, TargetOs, TargetingRule},
+        url::{ClickSource, LinkFilter, QueryPassthrough},
+        utm::UtmParams,
+    },
+    routes::{flash, profile::ExportFormat, workspace::CurrentWorkspace},
+    services::{
+        export,
+        tag::parse_tags,
+        url::{LinkOptions, Resolution, Visit, is_web_url, link_password_problem},
+        utm::parse_utm,
+    },
+    startup::AppState,
+};
 
-use tracing::{info, warn, error};
+use tracing::{error, info, warn};
 use serde_json::json;
 
 #[derive(serde::Deserialize)]
 pub struct CreateUrlForm{
     pub url: String,
-    pub site_name: String
+    pub site_name: String,
+    #[serde(default)]
+    pub alias: String,
+    #[serde(default)]
+    pub expires_in_days: String,
+    #[serde(default)]
+    pub folder_id: String,
+    #[serde(default)]
+    pub tags: String,
+    /// Empty for a link anyone can follow
+    #[serde(default)]
+    pub password: String,
+    /// Empty for a link without a click limit
+    #[serde(default)]
+    pub max_clicks: String,
+    /// RFC 3339, or `YYYY-MM-DDTHH:MM` in UTC as sent by a datetime input.
+    /// Empty to go live right away.
+    #[serde(default)]
+    pub starts_at: String,
+    #[serde(default)]
+    pub fallback_url: String,
+    /// `off` (or empty), `keep`, `override` or `append`
+    #[serde(default)]
+    pub query_passthrough: String,
+    /// A checkbox, anything but empty or `false` turns it on
+    #[serde(default)]
+    pub path_passthrough: String,
+    #[serde(default)]
+    pub utm_source: String,
+    #[serde(default)]
+    pub utm_medium: String,
+    #[serde(default)]
+    pub utm_campaign: String,
+    #[serde(default)]
+    pub utm_term: String,
+    #[serde(default)]
+    pub utm_content: String,
+    /// Name of a saved preset filling the UTM parameters left empty
+    #[serde(default)]
+    pub utm_preset: String,
 }
 
-#[instrument(name = "Web: Create URL", skip(state, claims, form))]
+#[instrument(name = "Web: Create URL", skip(state, current, jar, headers, form))]
 pub async fn shorten_form_handler(
     State(state): State<AppState>,
-    claims: Claims,
+    current: CurrentWorkspace,
+    jar: SignedCookieJar,
+    headers: HeaderMap,
     Form(form): Form<CreateUrlForm>,
-) -> Result<impl IntoResponse, AuthError> {
-    let user_id = uuid::Uuid::parse_str(&claims.sub)
-        .map_err(|_| AuthError::InvalidToken)?;
+) -> Response {
+    let input = [
+        ("url", form.url.as_str()),
+        ("site_name", form.site_name.as_str()),
+        ("alias", form.alias.as_str()),
+        ("expires_in_days", form.expires_in_days.as_str()),
+        ("folder_id", form.folder_id.as_str()),
+        ("tags", form.tags.as_str()),
+        ("max_clicks", form.max_clicks.as_str()),
+        ("starts_at", form.starts_at.as_str()),
+        ("fallback_url", form.fallback_url.as_str()),
+        ("query_passthrough", form.query_passthrough.as_str()),
+        ("path_passthrough", form.path_passthrough.as_str()),
+        ("utm_source", form.utm_source.as_str()),
+        ("utm_medium", form.utm_medium.as_str()),
+        ("utm_campaign", form.utm_campaign.as_str()),
+        ("utm_term", form.utm_term.as_str()),
+        ("utm_content", form.utm_content.as_str()),
+        ("utm_preset", form.utm_preset.as_str()),
+    ];
+    if !current.workspace.role.can_edit() {
+        return flash::form_error(jar, &headers, "/dashboard", AuthError::Forbidden, &input);
+    }
+    let mut options = match validate_url_form(&form) {
+        Ok(options) => options,
+        Err(errors) => {
+            return flash::form_error(
+                jar,
+                &headers,
+                "/dashboard",
+                AuthError::Validation(errors),
+                &input,
+            );
+        }
+    };
+    options.utm = match state
+        .utm_service
+        .with_preset(current.user_id, Some(form.utm_preset.as_str()), options.utm)
+        .await
+    {
+        Ok(utm) => utm,
+        Err(e) => return flash::form_error(jar, &headers, "/dashboard", e, &input),
+    };
 
     // Use your existing service logic
-    state.url_service
-        .shorten(&form.url,&form.site_name, user_id)
+    if let Err(e) = state
+        .url_service
+        .shorten(
+            form.url.trim(),
+            form.site_name.trim(),
+            current.user_id,
+            current.workspace.id,
+            options,
+        )
         .await
-        .map_err(|e| {
-            tracing::error!("Failed to shorten URL: {:?}", e);
-            AuthError::Internal
-        })?;
-
+    {
+        return flash::form_error(jar, &headers, "/dashboard", e, &input);
+    }
 
     // Redirect back to the dashboard to show the new link in the list
-    Ok(Redirect::to("/dashboard"))
+    flash::redirect_with(jar, "/dashboard", "Short link created")
+}
+
+/// Only absolute http(s) links can be shortened
+pub(crate) fn validate_url_form(form: &CreateUrlForm) -> Result<LinkOptions, FieldErrors> {
+    let mut errors = FieldErrors::new();
+    if !is_web_url(form.url.trim()) {
+        errors.insert(
+            "url",
+            vec!["Enter a full http:// or https:// address".to_string()],
+        );
+    }
+    if form.site_name.trim().is_empty() {
+        errors.insert("site_name", vec!["Give the link a name".to_string()]);
+    }
+    let expires_in_days = match parse_days(&form.expires_in_days) {
+        Ok(days) => days,
+        Err(message) => {
+            errors.insert("expires_in_days", vec![message]);
+            None
+        }
+    };
+    let tags = parse_tags(&form.tags).unwrap_or_else(|message| {
+        errors.insert("tags", vec![message]);
+        Vec::new()
+    });
+    let max_clicks = parse_max_clicks(&form.max_clicks).unwrap_or_else(|message| {
+        errors.insert("max_clicks", vec![message]);
+        None
+    });
+    let starts_at = parse_starts_at(&form.starts_at).unwrap_or_else(|message| {
+        errors.insert("starts_at", vec![message]);
+        None
+    });
+    let fallback_url = optional(&form.fallback_url);
+    if fallback_url.as_deref().is_some_and(|url| !is_web_url(url)) {
+        errors.insert(
+            "fallback_url",
+            vec!["Enter a full http:// or https:// address".to_string()],
+        );
+    }
+    let query_passthrough =
+        parse_query_passthrough(&form.query_passthrough).unwrap_or_else(|message| {
+            errors.insert("query_passthrough", vec![message]);
+            QueryPassthrough::Off
+        });
+    // Passwords are taken as typed, spaces included
+    let password = (!form.password.is_empty()).then(|| form.password.clone());
+    if let Some(problem) = password.as_deref().and_then(link_password_problem) {
+        errors.insert("password", vec![problem]);
+    }
+    let utm = parse_utm(
+        &form.utm_source,
+        &form.utm_medium,
+        &form.utm_campaign,
+        &form.utm_term,
+        &form.utm_content,
+    )
+    .unwrap_or_else(|utm_errors| {
+        errors.extend(utm_errors);
+        UtmParams::default()
+    });
+    if errors.is_empty() {
+        Ok(LinkOptions {
+            alias: optional(&form.alias),
+            starts_at,
+            expires_in_days,
+            fallback_url,
+            query_passthrough,
+            path_passthrough: is_checked(&form.path_passthrough),
+            utm,
+            folder_id: optional(&form.folder_id).and_then(|id| Uuid::parse_str(&id).ok()),
+            tags,
+            password,
+            max_clicks,
+        })
+    } else {
+        Err(errors)
+    }
+}
+
+fn optional(value: &str) -> Option<String> {
+    let value = value.trim();
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+/// An empty value means no expiry was asked for
+fn parse_days(value: &str) -> Result<Option<u32>, String> {
+    match optional(value) {
+        None => Ok(None),
+        Some(days) => match days.parse::<u32>() {
+            Ok(days) if days > 0 => Ok(Some(days)),
+            _ => Err("Enter a number of days greater than zero".to_string()),
+        },
+    }
+}
+
+fn parse_query_passthrough(value: &str) -> Result<QueryPassthrough, String> {
+    match optional(value) {
+        None => Ok(QueryPassthrough::Off),
+        Some(mode) => mode
+            .parse()
+            .map_err(|_| "Pick off, keep, override or append".to_string()),
+    }
+}
+
+fn is_checked(value: &str) -> bool {
+    !matches!(value.trim(), "" | "false" | "0" | "off")
+}
+
+/// An empty value means the link is live right away. Times without an offset are UTC.
+fn parse_starts_at(value: &str) -> Result<Option<DateTime<Utc>>, String> {
+    let Some(value) = optional(value) else {
+        return Ok(None);
+    };
+    DateTime::parse_from_rfc3339(&value)
+        .map(|at| at.with_timezone(&Utc))
+        .or_else(|_| NaiveDateTime::parse_from_str(&value, "%Y-%m-%dT%H:%M").map(|at| at.and_utc()))
+        .or_else(|_| {
+            NaiveDateTime::parse_from_str(&value, "%Y-%m-%dT%H:%M:%S").map(|at| at.and_utc())
+        })
+        .map(Some)
+        .map_err(|_| "Enter a date and time such as 2026-03-01T09:00".to_string())
+}
+
+/// An empty value means the link can be clicked any number of times
+fn parse_max_clicks(value: &str) -> Result<Option<i32>, String> {
+    match optional(value) {
+        None => Ok(None),
+        Some(clicks) => match clicks.parse::<i32>() {
+            Ok(clicks) if clicks > 0 => Ok(Some(clicks)),
+            _ => Err("Enter a number of clicks greater than zero".to_string()),
+        },
+    }
 }
 
 #[instrument(
     name = "HTTP: Shorten request", 
-    skip(state, claims, params), 
-    fields(user_id = %claims.sub)
+    skip(state, current, params), 
+    fields(user_id = %current.user_id, workspace_id = %current.workspace.id)
 )]
 pub async fn shorten(
     State(state): State<AppState>,
-    claims: Claims, // Extractor ensures user is authorized
+    current: CurrentWorkspace, // Extractor ensures user is authorized
     Query(params): Query<HashMap<String, String>>,
 ) -> impl IntoResponse {
+    if !current.workspace.role.can_edit() {
+        return AuthError::Forbidden.into_response();
+    }
+
     let url = match params.get("url") {
         Some(url) => url,
         None => return (http::StatusCode::BAD_REQUEST, "Missing url parameter").into_response(),
@@ -58,33 +310,788 @@ pub async fn shorten(
         None => return (http::StatusCode::BAD_REQUEST, "Missing site_name parameter").into_response(),
     };
 
-    // Convert the string 'sub' from JWT back to a Uuid
-    let user_id = match Uuid::parse_str(&claims.sub) {
-        Ok(id) => id,
-        Err(_) => return (http::StatusCode::UNAUTHORIZED, "Invalid user ID in token").into_response(),
+    let expires_in_days = match parse_days(params.get("expires_in_days").map_or("", String::as_str))
+    {
+        Ok(days) => days,
+        Err(message) => {
+            let mut errors = FieldErrors::new();
+            errors.insert("expires_in_days", vec![message]);
+            return AuthError::Validation(errors).into_response();
+        }
+    };
+    let tags = match parse_tags(params.get("tags").map_or("", String::as_str)) {
+        Ok(tags) => tags,
+        Err(message) => {
+            let mut errors = FieldErrors::new();
+            errors.insert("tags", vec![message]);
+            return AuthError::Validation(errors).into_response();
+        }
+    };
+    let max_clicks = match parse_max_clicks(params.get("max_clicks").map_or("", String::as_str)) {
+        Ok(max_clicks) => max_clicks,
+        Err(message) => {
+            let mut errors = FieldErrors::new();
+            errors.insert("max_clicks", vec![message]);
+            return AuthError::Validation(errors).into_response();
+        }
+    };
+    let starts_at = match parse_starts_at(params.get("starts_at").map_or("", String::as_str)) {
+        Ok(starts_at) => starts_at,
+        Err(message) => {
+            let mut errors = FieldErrors::new();
+            errors.insert("starts_at", vec![message]);
+            return AuthError::Validation(errors).into_response();
+        }
+    };
+    let fallback_url = params.get("fallback_url").and_then(|url| optional(url));
+    if fallback_url.as_deref().is_some_and(|url| !is_web_url(url)) {
+        let mut errors = FieldErrors::new();
+        errors.insert(
+            "fallback_url",
+            vec!["Enter a full http:// or https:// address".to_string()],
+        );
+        return AuthError::Validation(errors).into_response();
+    }
+    let query_passthrough =
+        match parse_query_passthrough(params.get("query_passthrough").map_or("", String::as_str)) {
+            Ok(mode) => mode,
+            Err(message) => {
+                let mut errors = FieldErrors::new();
+                errors.insert("query_passthrough", vec![message]);
+                return AuthError::Validation(errors).into_response();
+            }
+        };
+    let path_passthrough = params
+        .get("path_passthrough")
+        .is_some_and(|value| is_checked(value));
+    let password = params
+        .get("password")
+        .filter(|password| !password.is_empty())
+        .cloned();
+    if let Some(problem) = password.as_deref().and_then(link_password_problem) {
+        let mut errors = FieldErrors::new();
+        errors.insert("password", vec![problem]);
+        return AuthError::Validation(errors).into_response();
+    }
+    let param = |name: &str| params.get(name).map_or("", String::as_str);
+    let utm = match parse_utm(
+        param("utm_source"),
+        param("utm_medium"),
+        param("utm_campaign"),
+        param("utm_term"),
+        param("utm_content"),
+    ) {
+        Ok(utm) => utm,
+        Err(errors) => return AuthError::Validation(errors).into_response(),
+    };
+    let utm = match state
+        .utm_service
+        .with_preset(
+            current.user_id,
+            params.get("utm_preset").map(String::as_str),
+            utm,
+        )
+        .await
+    {
+        Ok(utm) => utm,
+        Err(e) => return e.into_response(),
     };
+    let options = LinkOptions {
+        alias: params.get("alias").and_then(|alias| optional(alias)),
+        starts_at,
+        expires_in_days,
+        fallback_url,
+        query_passthrough,
+        path_passthrough,
+        utm,
+        folder_id: params.get("folder").and_then(|id| Uuid::parse_str(id).ok()),
+        tags,
+        password,
+        max_clicks,
+    };
+
+    match state
+        .url_service
+        .shorten(
+            url,
+            site_name,
+            current.user_id,
+            current.workspace.id,
+            options,
+        )
+        .await
+    {
+        Ok(shortened) => Json(json!({ "short_url": shortened })).into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+#[instrument(
+    name = "HTTP: List urls",
+    skip(state, current),
+    fields(workspace_id = %current.workspace.id)
+)]
+pub async fn list_urls(
+    State(state): State<AppState>,
+    current: CurrentWorkspace,
+    Query(filter): Query<LinkFilter>,
+) -> Result<impl IntoResponse, AuthError> {
+    let page = state
+        .url_service
+        .get_workspace_urls(current.workspace.id, &filter)
+        .await?;
+
+    // The body stays a plain array, the next page is announced RFC 8288 style
+    let mut headers = HeaderMap::new();
+    if let Some(next) = &page.next {
+        let link = format!(
+            "</api/urls?{}>; rel=\"next\"",
+            filter.query_string_after(Some(next))
+        );
+        if let Ok(value) = HeaderValue::from_str(&link) {
+            headers.insert(header::LINK, value);
+        }
+    }
+    Ok((headers, Json(page.links)))
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct LinkExportQuery {
+    #[serde(default)]
+    pub format: ExportFormat,
+    /// Add clicks per day to every link
+    #[serde(default)]
+    pub daily: bool,
+}
+
+/// Download every link of the workspace matching the listing filters,
+/// streamed as it is read
+#[instrument(
+    name = "HTTP: Export urls",
+    skip(state, current, filter),
+    fields(workspace_id = %current.workspace.id)
+)]
+pub async fn export_urls(
+    State(state): State<AppState>,
+    current: CurrentWorkspace,
+    Query(filter): Query<LinkFilter>,
+    Query(query): Query<LinkExportQuery>,
+) -> Response {
+    let batches = state
+        .url_service
+        .export_links(current.workspace.id, filter, query.daily);
 
-    match state.url_service.shorten(url,site_name,user_id).await {
-        Ok(shortened) => {
-            Json(json!({ "short_url": shortened })).into_response()
+    let (content_type, extension, body) = match query.format {
+        ExportFormat::Csv => {
+            let head = stream::once(future::ready(export::csv_head(query.daily)));
+            let rows = batches.map(|batch| batch.and_then(|links| export::csv_chunk(&links)));
+            ("text/csv", "csv", head.chain(rows).boxed())
+        }
+        ExportFormat::Json => {
+            let mut first = true;
+            let items = batches.map(move |batch| {
+                let chunk = export::json_chunk(&batch?, first)?;
+                first &= chunk.is_empty();
+                Ok(chunk)
+            });
+            let body = stream::once(future::ready(Ok(export::json_head())))
+                .chain(items)
+                .chain(stream::once(future::ready(Ok(export::json_tail()))));
+            ("application/json", "json", body.boxed())
         }
-        Err(e) => {
-            error!("Failed to shorten URL: {:?}", e);
-            (http::StatusCode::INTERNAL_SERVER_ERROR, "Internal server error").into_response()
+    };
+    // A failure halfway through can only cut the download short
+    let body = body.inspect_err(|e| error!("Link export failed: {:?}", e));
+
+    info!(user_id = %current.user_id, format = extension, daily = query.daily, "Links exported");
+    let disposition = format!("attachment; filename=\"shorty-links.{}\"", extension);
+    (
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        Body::from_stream(body),
+    )
+        .into_response()
+}
+
+/// Signed cookie remembering that a visitor entered a link's password
+const UNLOCK_COOKIE: &str = "link_unlock";
+/// How long an unlocked link keeps redirecting without asking again
+const UNLOCK_MINUTES: i64 = 30;
+
+#[derive(Template)]
+#[template(path = "unlock.html")]
+struct UnlockTemplate {
+    csrf_token: String,
+    short_code: String,
+    /// Address the visitor asked for, with its query and extra path, to go
+    /// back to once unlocked
+    next: String,
+    error: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "scheduled.html")]
+struct ScheduledTemplate {
+    starts_at: DateTime<Utc>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct RedirectQuery {
+    /// `qr` when the link was reached by scanning its QR code
+    pub src: Option<String>,
+}
+
+impl RedirectQuery {
+    fn source(&self) -> ClickSource {
+        match self.src.as_deref() {
+            Some("qr") => ClickSource::Qr,
+            _ => ClickSource::Link,
         }
     }
 }
 
-#[instrument(name = "HTTP: Redirect request", skip(state))]
+#[instrument(
+    name = "HTTP: Redirect request",
+    skip(state, uri, headers, jar, csrf_token)
+)]
 pub async fn redirect(
     Path(short_url): Path<String>, 
-    State(state): State<AppState>
-) -> impl IntoResponse {
-    if let Some(url) = state.url_service.resolve(&short_url).await {
-        info!(short_code = %short_url, "Redirecting to {}", url);
-        return Redirect::permanent(url.as_str()).into_response();
+    State(state): State<AppState>,
+    Query(query): Query<RedirectQuery>,
+    OriginalUri(uri): OriginalUri,
+    headers: HeaderMap,
+    jar: SignedCookieJar,
+    CsrfToken(csrf_token): CsrfToken,
+) -> Response {
+    let visit = visit(&short_url, None, &query, &uri, &headers, &jar);
+    follow(state, short_url, visit, uri, csrf_token).await
+}
+
+/// A short link followed by more path, `/url/{key}/rest/of/path`. Links that
+/// pass the path through add it to their destination, others ignore it.
+#[instrument(
+    name = "HTTP: Redirect request with path",
+    skip(state, uri, headers, jar, csrf_token)
+)]
+pub async fn redirect_path(
+    Path((short_url, rest)): Path<(String, String)>,
+    State(state): State<AppState>,
+    Query(query): Query<RedirectQuery>,
+    OriginalUri(uri): OriginalUri,
+    headers: HeaderMap,
+    jar: SignedCookieJar,
+    CsrfToken(csrf_token): CsrfToken,
+) -> Response {
+    let visit = visit(&short_url, Some(rest), &query, &uri, &headers, &jar);
+    follow(state, short_url, visit, uri, csrf_token).await
+}
+
+/// Everything about a request the redirect path goes by
+fn visit(
+    short_url: &str,
+    path: Option<String>,
+    query: &RedirectQuery,
+    uri: &http::Uri,
+    headers: &HeaderMap,
+    jar: &SignedCookieJar,
+) -> Visit {
+    let header = |name: header::HeaderName| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string)
+    };
+    Visit {
+        source: query.source(),
+        unlocked: is_unlocked(jar, short_url),
+        query: uri.query().map(str::to_string),
+        path,
+        user_agent: header(header::USER_AGENT),
+        accept_language: header(header::ACCEPT_LANGUAGE),
+        referrer: header(header::REFERER),
+    }
+}
+
+async fn follow(
+    state: AppState,
+    short_url: String,
+    visit: Visit,
+    uri: http::Uri,
+    csrf_token: String,
+) -> Response {
+    match state.url_service.resolve(&short_url, &visit).await {
+        Resolution::Found(url) => {
+            info!(short_code = %short_url, "Redirecting to {}", url);
+            // Temporary, browsers must keep asking so expiry and quotas apply
+            Redirect::temporary(url.as_str()).into_response()
+        }
+        Resolution::Fallback(url) => {
+            info!(short_code = %short_url, "Redirecting to the fallback {}", url);
+            Redirect::temporary(url.as_str()).into_response()
+        }
+        Resolution::Scheduled(starts_at) => {
+            info!(short_code = %short_url, %starts_at, "Short URL is not live yet");
+            let template = ScheduledTemplate { starts_at };
+            // The same address redirects once the link is live
+            (
+                [(header::CACHE_CONTROL, "no-store")],
+                Html(template.render().unwrap()),
+            )
+                .into_response()
+        }
+        Resolution::Locked => {
+            info!(short_code = %short_url, "Asking for the link password");
+            let next = uri
+                .path_and_query()
+                .map_or_else(|| uri.path().to_string(), |p| p.to_string());
+            unlock_page(csrf_token, short_url, next, None, StatusCode::OK)
+        }
+        Resolution::Expired => {
+            info!(short_code = %short_url, "Short URL has expired");
+            (StatusCode::GONE, "url has expired").into_response()
+        }
+        Resolution::Exhausted => {
+            info!(short_code = %short_url, "Short URL has used up its clicks");
+            (StatusCode::GONE, "this link has reached its click limit").into_response()
+        }
+        Resolution::QuotaExceeded => {
+            warn!(short_code = %short_url, "Owner is out of monthly redirects");
+            (
+                StatusCode::TOO_MANY_REQUESTS,
+                "this link has reached its monthly redirect limit",
+            )
+                .into_response()
+        }
+        Resolution::Unavailable => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "this link cannot be followed right now",
+        )
+            .into_response(),
+        Resolution::NotFound => {
+            warn!(short_code = %short_url, "Short URL not found");
+            (StatusCode::BAD_REQUEST, "url not found").into_response()
+        }
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct UnlockForm {
+    pub password: String,
+    /// Where to go once unlocked, only followed within the link
+    #[serde(default)]
+    pub next: String,
+}
+
+/// Check the password of a protected link and remember the visitor for a while
+#[instrument(name = "Web: Unlock url", skip(state, jar, csrf_token, form))]
+pub async fn unlock_handler(
+    Path(short_url): Path<String>,
+    State(state): State<AppState>,
+    jar: SignedCookieJar,
+    CsrfToken(csrf_token): CsrfToken,
+    Form(form): Form<UnlockForm>,
+) -> Result<Response, AuthError> {
+    let path = format!("/url/{}", short_url);
+    // Anything else would make this an open redirect
+    let next = match form.next.strip_prefix(&path) {
+        Some(rest) if rest.is_empty() || rest.starts_with(['/', '?']) => form.next.clone(),
+        _ => path.clone(),
+    };
+    if !state.url_service.unlock(&short_url, &form.password).await? {
+        warn!(short_code = %short_url, "Wrong link password");
+        let error = Some("That password is not right".to_string());
+        return Ok(unlock_page(
+            csrf_token,
+            short_url,
+            next,
+            error,
+            StatusCode::UNAUTHORIZED,
+        ));
+    }
+
+    let until = chrono::Utc::now().timestamp() + UNLOCK_MINUTES * 60;
+    let cookie = Cookie::build((UNLOCK_COOKIE, format!("{}|{}", short_url, until)))
+        .path(path)
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .max_age(time::Duration::minutes(UNLOCK_MINUTES));
+    Ok((jar.add(cookie), Redirect::to(&next)).into_response())
+}
+
+/// The cookie names the code it was issued for and when it runs out, so it
+/// cannot be replayed on another link or kept past its lifetime
+fn is_unlocked(jar: &SignedCookieJar, short_code: &str) -> bool {
+    jar.get(UNLOCK_COOKIE).is_some_and(|cookie| {
+        cookie
+            .value()
+            .rsplit_once('|')
+            .is_some_and(|(code, until)| {
+                code == short_code
+                    && until
+                        .parse::<i64>()
+                        .is_ok_and(|until| until > chrono::Utc::now().timestamp())
+            })
+    })
+}
+
+fn unlock_page(
+    csrf_token: String,
+    short_code: String,
+    next: String,
+    error: Option<String>,
+    status: StatusCode,
+) -> Response {
+    let template = UnlockTemplate {
+        csrf_token,
+        short_code,
+        next,
+        error,
+    };
+    // Never cache the prompt, the same address redirects once unlocked
+    (
+        status,
+        [(header::CACHE_CONTROL, "no-store")],
+        Html(template.render().unwrap()),
+    )
+        .into_response()
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct LinkFallbackForm {
+    /// Empty to fall back to the account's fallback
+    #[serde(default)]
+    pub fallback_url: String,
+}
+
+/// Set or clear where visitors go when the link cannot be followed
+#[instrument(
+    name = "Web: Set url fallback",
+    skip(state, current, jar, headers, form)
+)]
+pub async fn link_fallback_handler(
+    State(state): State<AppState>,
+    current: CurrentWorkspace,
+    jar: SignedCookieJar,
+    headers: HeaderMap,
+    Path(short_url): Path<String>,
+    Form(form): Form<LinkFallbackForm>,
+) -> Response {
+    let fallback_url = optional(&form.fallback_url);
+    let result = state
+        .url_service
+        .set_fallback_url(&current.workspace, &short_url, fallback_url.as_deref())
+        .await;
+    match result {
+        Ok(()) => flash::redirect_with(jar, "/dashboard", "Link fallback updated"),
+        Err(e) => flash::form_error(jar, &headers, "/dashboard", e, &[]),
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct LinkPassthroughForm {
+    #[serde(default)]
+    pub query_passthrough: String,
+    /// A checkbox, missing when unticked
+    #[serde(default)]
+    pub path_passthrough: String,
+}
+
+/// Choose how the query string and extra path of a visit reach the destination
+#[instrument(
+    name = "Web: Set url passthrough",
+    skip(state, current, jar, headers, form)
+)]
+pub async fn link_passthrough_handler(
+    State(state): State<AppState>,
+    current: CurrentWorkspace,
+    jar: SignedCookieJar,
+    headers: HeaderMap,
+    Path(short_url): Path<String>,
+    Form(form): Form<LinkPassthroughForm>,
+) -> Response {
+    let result = async {
+        let query_passthrough =
+            parse_query_passthrough(&form.query_passthrough).map_err(|message| {
+                let mut errors = FieldErrors::new();
+                errors.insert("query_passthrough", vec![message]);
+                AuthError::Validation(errors)
+            })?;
+        state
+            .url_service
+            .set_passthrough(
+                &current.workspace,
+                &short_url,
+                query_passthrough,
+                is_checked(&form.path_passthrough),
+            )
+            .await
+    }
+    .await;
+    match result {
+        Ok(()) => flash::redirect_with(jar, "/dashboard", "Link forwarding updated"),
+        Err(e) => flash::form_error(jar, &headers, "/dashboard", e, &[]),
     }
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct LinkUtmForm {
+    #[serde(default)]
+    pub utm_source: String,
+    #[serde(default)]
+    pub utm_medium: String,
+    #[serde(default)]
+    pub utm_campaign: String,
+    #[serde(default)]
+    pub utm_term: String,
+    #[serde(default)]
+    pub utm_content: String,
+    #[serde(default)]
+    pub utm_preset: String,
+}
+
+/// Replace the UTM parameters of a link, all empty to stop adding them
+#[instrument(
+    name = "Web: Set url UTM parameters",
+    skip(state, current, jar, headers, form)
+)]
+pub async fn link_utm_handler(
+    State(state): State<AppState>,
+    current: CurrentWorkspace,
+    jar: SignedCookieJar,
+    headers: HeaderMap,
+    Path(short_url): Path<String>,
+    Form(form): Form<LinkUtmForm>,
+) -> Response {
+    let result = async {
+        let utm = parse_utm(
+            &form.utm_source,
+            &form.utm_medium,
+            &form.utm_campaign,
+            &form.utm_term,
+            &form.utm_content,
+        )
+        .map_err(AuthError::Validation)?;
+        let utm = state
+            .utm_service
+            .with_preset(current.user_id, Some(form.utm_preset.as_str()), utm)
+            .await?;
+        state
+            .url_service
+            .set_utm(&current.workspace, &short_url, &utm)
+            .await
+    }
+    .await;
+    match result {
+        Ok(()) => flash::redirect_with(jar, "/dashboard", "Link UTM parameters updated"),
+        Err(e) => flash::form_error(jar, &headers, "/dashboard", e, &[]),
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct LinkRuleForm {
+    pub destination: String,
+    /// Empty for any operating system
+    #[serde(default)]
+    pub os: String,
+    /// Empty for any device
+    #[serde(default)]
+    pub device: String,
+    #[serde(default)]
+    pub language: String,
+    /// `HH:MM` in UTC, as sent by a time input
+    #[serde(default)]
+    pub active_from: String,
+    #[serde(default)]
+    pub active_until: String,
+    #[serde(default)]
+    pub referrer_host: String,
+}
+
+impl LinkRuleForm {
+    fn parse(&self) -> Result<TargetingRule, FieldErrors> {
+        let mut errors = FieldErrors::new();
+        let os = optional(&self.os).and_then(|os| {
+            os.parse::<TargetOs>()
+                .map_err(|_| {
+                    errors.insert(
+                        "os",
+                        vec!["Pick ios, android, windows, macos or linux".to_string()],
+                    )
+                })
+                .ok()
+        });
+        let device = optional(&self.device).and_then(|device| {
+            device
+                .parse::<DeviceClass>()
+                .map_err(|_| {
+                    errors.insert("device", vec!["Pick mobile, tablet or desktop".to_string()])
+                })
+                .ok()
+        });
+        let mut time = |field: &'static str, value: &str| {
+            let value = optional(value)?;
+            NaiveTime::parse_from_str(&value, "%H:%M")
+                .or_else(|_| NaiveTime::parse_from_str(&value, "%H:%M:%S"))
+                .map_err(|_| errors.insert(field, vec!["Enter a time such as 09:30".to_string()]))
+                .ok()
+        };
+        let active_from = time("active_from", &self.active_from);
+        let active_until = time("active_until", &self.active_until);
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        Ok(TargetingRule {
+            id: Uuid::nil(),
+            url_id: Uuid::nil(),
+            destination: self.destination.clone(),
+            os,
+            device,
+            language: optional(&self.language),
+            active_from,
+            active_until,
+            referrer_host: optional(&self.referrer_host),
+        })
+    }
+}
+
+/// Add a targeting rule after the link's existing ones
+#[instrument(name = "Web: Add url rule", skip(state, current, jar, headers, form))]
+pub async fn add_link_rule_handler(
+    State(state): State<AppState>,
+    current: CurrentWorkspace,
+    jar: SignedCookieJar,
+    headers: HeaderMap,
+    Path(short_url): Path<String>,
+    Form(form): Form<LinkRuleForm>,
+) -> Response {
+    let result = async {
+        let rule = form.parse().map_err(AuthError::Validation)?;
+        state
+            .url_service
+            .add_rule(&current.workspace, &short_url, rule)
+            .await
+    }
+    .await;
+    match result {
+        Ok(()) => flash::redirect_with(jar, "/dashboard", "Targeting rule added"),
+        Err(e) => flash::form_error(jar, &headers, "/dashboard", e, &[]),
+    }
+}
+
+#[instrument(name = "Web: Delete url rule", skip(state, current, jar, headers))]
+pub async fn delete_link_rule_handler(
+    State(state): State<AppState>,
+    current: CurrentWorkspace,
+    jar: SignedCookieJar,
+    headers: HeaderMap,
+    Path((short_url, rule_id)): Path<(String, Uuid)>,
+) -> Response {
+    let result = state
+        .url_service
+        .remove_rule(&current.workspace, &short_url, rule_id)
+        .await;
+    match result {
+        Ok(()) => flash::redirect_with(jar, "/dashboard", "Targeting rule removed"),
+        Err(e) => flash::form_error(jar, &headers, "/dashboard", e, &[]),
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct MoveRuleForm {
+    /// `up` to try the rule earlier, anything else to try it later
+    pub direction: String,
+}
+
+#[instrument(name = "Web: Move url rule", skip(state, current, jar, headers, form))]
+pub async fn move_link_rule_handler(
+    State(state): State<AppState>,
+    current: CurrentWorkspace,
+    jar: SignedCookieJar,
+    headers: HeaderMap,
+    Path((short_url, rule_id)): Path<(String, Uuid)>,
+    Form(form): Form<MoveRuleForm>,
+) -> Response {
+    let result = state
+        .url_service
+        .move_rule(
+            &current.workspace,
+            &short_url,
+            rule_id,
+            form.direction == "up",
+        )
+        .await;
+    match result {
+        Ok(()) => flash::redirect_with(jar, "/dashboard", "Targeting rules reordered"),
+        Err(e) => flash::form_error(jar, &headers, "/dashboard", e, &[]),
+    }
+}
+
+#[instrument(name = "HTTP: List url rules", skip(state, current), fields(workspace_id = %current.workspace.id))]
+pub async fn list_link_rules(
+    State(state): State<AppState>,
+    current: CurrentWorkspace,
+    Path(short_url): Path<String>,
+) -> Result<Json<Vec<TargetingRule>>, AuthError> {
+    Ok(Json(
+        state
+            .url_service
+            .rules(&current.workspace, &short_url)
+            .await?,
+    ))
+}
+
+/// Replace every targeting rule of a link with the JSON list in the body
+#[instrument(name = "HTTP: Replace url rules", skip(state, current, rules), fields(workspace_id = %current.workspace.id))]
+pub async fn replace_link_rules(
+    State(state): State<AppState>,
+    current: CurrentWorkspace,
+    Path(short_url): Path<String>,
+    Json(rules): Json<Vec<TargetingRule>>,
+) -> Result<Json<Vec<TargetingRule>>, AuthError> {
+    state
+        .url_service
+        .set_rules(&current.workspace, &short_url, rules)
+        .await?;
+    Ok(Json(
+        state
+            .url_service
+            .rules(&current.workspace, &short_url)
+            .await?,
+    ))
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct LinkPasswordForm {
+    /// Empty to remove the password
+    #[serde(default)]
+    pub password: String,
+}
 
-    warn!(short_code = %short_url, "Short URL not found");
-    (StatusCode::BAD_REQUEST, "url not found").into_response()
+/// Set, change or remove the password of a link
+#[instrument(
+    name = "Web: Set url password",
+    skip(state, current, jar, headers, form)
+)]
+pub async fn link_password_handler(
+    State(state): State<AppState>,
+    current: CurrentWorkspace,
+    jar: SignedCookieJar,
+    headers: HeaderMap,
+    Path(short_url): Path<String>,
+    Form(form): Form<LinkPasswordForm>,
+) -> Response {
+    let password = (!form.password.is_empty()).then_some(form.password.as_str());
+    let result = state
+        .url_service
+        .set_password(&current.workspace, &short_url, password)
+        .await;
+    match result {
+        Ok(()) if password.is_some() => {
+            flash::redirect_with(jar, "/dashboard", "Link password set")
+        }
+        Ok(()) => flash::redirect_with(jar, "/dashboard", "Link password removed"),
+        Err(e) => flash::form_error(jar, &headers, "/dashboard", e, &[]),
+    }
 }
@@ -0,0 +1,108 @@
+use axum::{
+    Form, Json,
+    extract::{Path, State},
+    http::HeaderMap,
+    response::Response,
+};
+use axum_extra::extract::SignedCookieJar;
+use serde::Deserialize;
+use tracing::instrument;
+use uuid::Uuid;
+
+use crate::{
+    errors::AuthError,
+    models::utm::UtmPreset,
+    routes::{auth::Claims, flash},
+    services::utm::parse_utm,
+    startup::AppState,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct UtmPresetForm {
+    pub name: String,
+    #[serde(default)]
+    pub utm_source: String,
+    #[serde(default)]
+    pub utm_medium: String,
+    #[serde(default)]
+    pub utm_campaign: String,
+    #[serde(default)]
+    pub utm_term: String,
+    #[serde(default)]
+    pub utm_content: String,
+}
+
+#[instrument(name = "HTTP: List UTM presets", skip(state, claims))]
+pub async fn list_utm_presets(
+    State(state): State<AppState>,
+    claims: Claims,
+) -> Result<Json<Vec<UtmPreset>>, AuthError> {
+    let user_id = Uuid::parse_str(&claims.sub).map_err(|_| AuthError::InvalidToken)?;
+    let presets = state.utm_service.list_presets(user_id).await.map_err(|e| {
+        tracing::error!("Failed to list UTM presets: {:?}", e);
+        AuthError::Internal
+    })?;
+    Ok(Json(presets))
+}
+
+#[instrument(
+    name = "Web: Create UTM preset",
+    skip(state, claims, jar, headers, form)
+)]
+pub async fn create_utm_preset_handler(
+    State(state): State<AppState>,
+    claims: Claims,
+    jar: SignedCookieJar,
+    headers: HeaderMap,
+    Form(form): Form<UtmPresetForm>,
+) -> Response {
+    let result = async {
+        let user_id = Uuid::parse_str(&claims.sub).map_err(|_| AuthError::InvalidToken)?;
+        let params = parse_utm(
+            &form.utm_source,
+            &form.utm_medium,
+            &form.utm_campaign,
+            &form.utm_term,
+            &form.utm_content,
+        )
+        .map_err(AuthError::Validation)?;
+        state
+            .utm_service
+            .create_preset(user_id, &form.name, &params)
+            .await
+    }
+    .await;
+
+    let input = [
+        ("name", form.name.as_str()),
+        ("utm_source", form.utm_source.as_str()),
+        ("utm_medium", form.utm_medium.as_str()),
+        ("utm_campaign", form.utm_campaign.as_str()),
+        ("utm_term", form.utm_term.as_str()),
+        ("utm_content", form.utm_content.as_str()),
+    ];
+    match result {
+        Ok(_) => flash::redirect_with(jar, "/profile", "UTM preset saved"),
+        Err(e) => flash::form_error(jar, &headers, "/profile", e, &input),
+    }
+}
+
+#[instrument(name = "Web: Delete UTM preset", skip(state, claims, jar, headers))]
+pub async fn delete_utm_preset_handler(
+    State(state): State<AppState>,
+    claims: Claims,
+    jar: SignedCookieJar,
+    headers: HeaderMap,
+    Path(preset_id): Path<Uuid>,
+) -> Response {
+    let result = async {
+        let user_id = Uuid::parse_str(&claims.sub).map_err(|_| AuthError::InvalidToken)?;
+        state.utm_service.delete_preset(user_id, preset_id).await
+    }
+    .await;
+
+    match result {
+        Ok(()) => flash::redirect_with(jar, "/profile", "UTM preset deleted"),
+        Err(e) => flash::form_error(jar, &headers, "/profile", e, &[]),
+    }
+}
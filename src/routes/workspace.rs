@@ -0,0 +1,298 @@
+use askama::Template;
+use axum::{
+    Form, RequestPartsExt,
+    extract::{FromRef, FromRequestParts, Path, State},
+    http::request::Parts,
+    response::{Html, IntoResponse, Redirect},
+};
+use axum_extra::extract::{CookieJar, cookie::Cookie};
+use serde::Deserialize;
+use tracing::instrument;
+use uuid::Uuid;
+
+use crate::{
+    errors::AuthError,
+    middleware::csrf::CsrfToken,
+    models::workspace::{InvitationModel, MemberModel, WorkspaceMembership, WorkspaceRole},
+    routes::auth::{Claims, Page},
+    startup::AppState,
+};
+
+/// Cookie remembering the workspace selected in the browser
+pub const WORKSPACE_COOKIE: &str = "workspace";
+/// Header API clients use to pick a workspace
+pub const WORKSPACE_HEADER: &str = "x-workspace-id";
+
+/// The authenticated user together with the workspace the request acts on.
+///
+/// The workspace comes from the `X-Workspace-Id` header or the `workspace` cookie
+/// and falls back to the user's personal workspace.
+#[derive(Debug, Clone)]
+pub struct CurrentWorkspace {
+    pub claims: Claims,
+    pub user_id: Uuid,
+    pub workspace: WorkspaceMembership,
+}
+
+impl<S> FromRequestParts<S> for CurrentWorkspace
+where
+    AppState: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = AuthError;
+
+    #[instrument(name = "Extracting current workspace", skip(parts, state))]
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let claims = Claims::from_request_parts(parts, state).await?;
+        let user_id = Uuid::parse_str(&claims.sub).map_err(|_| AuthError::InvalidToken)?;
+
+        let header = parts
+            .headers
+            .get(WORKSPACE_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string);
+        let cookie = parts
+            .extract::<CookieJar>()
+            .await
+            .ok()
+            .and_then(|jar| jar.get(WORKSPACE_COOKIE).map(|c| c.value().to_string()));
+        let requested = header.or(cookie).and_then(|id| Uuid::parse_str(&id).ok());
+
+        let workspace = AppState::from_ref(state)
+            .workspace_service
+            .resolve(user_id, requested)
+            .await?;
+
+        Ok(Self {
+            claims,
+            user_id,
+            workspace,
+        })
+    }
+}
+
+fn workspace_cookie(workspace_id: Uuid) -> Cookie<'static> {
+    Cookie::build((WORKSPACE_COOKIE, workspace_id.to_string()))
+        .path("/")
+        .http_only(true)
+        .same_site(axum_extra::extract::cookie::SameSite::Lax)
+        .build()
+}
+
+#[derive(Template)]
+#[template(path = "workspaces.html")]
+struct WorkspacesTemplate {
+    csrf_token: String,
+    workspaces: Vec<WorkspaceMembership>,
+    current: WorkspaceMembership,
+    members: Vec<MemberModel>,
+    invitations: Vec<InvitationModel>,
+    user_id: Uuid,
+}
+
+#[derive(Template)]
+#[template(path = "invitation.html")]
+struct InvitationTemplate {
+    csrf_token: String,
+    invitation: InvitationModel,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateWorkspaceForm {
+    pub name: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct InviteForm {
+    pub email: String,
+    pub role: WorkspaceRole,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MemberRoleForm {
+    pub role: WorkspaceRole,
+}
+
+#[instrument(name = "Web: Workspaces page", skip(state, current))]
+pub async fn workspaces_page(
+    State(state): State<AppState>,
+    Page(current): Page<CurrentWorkspace>,
+    CsrfToken(csrf_token): CsrfToken,
+) -> Result<impl IntoResponse, AuthError> {
+    let service = &state.workspace_service;
+    let workspaces = service.list_for_user(current.user_id).await.map_err(|e| {
+        tracing::error!("Failed to list workspaces: {:?}", e);
+        AuthError::Internal
+    })?;
+    let members = service.members(current.workspace.id).await.map_err(|e| {
+        tracing::error!("Failed to list members: {:?}", e);
+        AuthError::Internal
+    })?;
+    let invitations = if current.workspace.role.can_manage() {
+        service
+            .pending_invitations(current.workspace.id)
+            .await
+            .map_err(|e| {
+                tracing::error!("Failed to list invitations: {:?}", e);
+                AuthError::Internal
+            })?
+    } else {
+        Vec::new()
+    };
+
+    let template = WorkspacesTemplate {
+        csrf_token,
+        workspaces,
+        current: current.workspace,
+        members,
+        invitations,
+        user_id: current.user_id,
+    };
+    Ok(Html(template.render().unwrap()))
+}
+
+#[instrument(name = "Web: Create workspace", skip(state, claims, jar, form))]
+pub async fn create_workspace_handler(
+    State(state): State<AppState>,
+    claims: Claims,
+    jar: CookieJar,
+    Form(form): Form<CreateWorkspaceForm>,
+) -> Result<impl IntoResponse, AuthError> {
+    let user_id = Uuid::parse_str(&claims.sub).map_err(|_| AuthError::InvalidToken)?;
+    let name = form.name.trim();
+    if name.is_empty() {
+        return Err(AuthError::MissingCredentials);
+    }
+
+    let workspace_id = state
+        .workspace_service
+        .create(name, user_id)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to create workspace: {:?}", e);
+            AuthError::Internal
+        })?;
+
+    Ok((
+        jar.add(workspace_cookie(workspace_id)),
+        Redirect::to("/workspaces"),
+    ))
+}
+
+#[instrument(name = "Web: Select workspace", skip(state, claims, jar))]
+pub async fn select_workspace_handler(
+    State(state): State<AppState>,
+    claims: Claims,
+    jar: CookieJar,
+    Path(workspace_id): Path<Uuid>,
+) -> Result<impl IntoResponse, AuthError> {
+    let user_id = Uuid::parse_str(&claims.sub).map_err(|_| AuthError::InvalidToken)?;
+    state
+        .workspace_service
+        .membership(workspace_id, user_id)
+        .await?;
+
+    Ok((
+        jar.add(workspace_cookie(workspace_id)),
+        Redirect::to("/dashboard"),
+    ))
+}
+
+#[instrument(name = "Web: Invite to workspace", skip(state, claims, form))]
+pub async fn invite_handler(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path(workspace_id): Path<Uuid>,
+    Form(form): Form<InviteForm>,
+) -> Result<impl IntoResponse, AuthError> {
+    let user_id = Uuid::parse_str(&claims.sub).map_err(|_| AuthError::InvalidToken)?;
+    let workspace = state
+        .workspace_service
+        .membership(workspace_id, user_id)
+        .await?;
+
+    let email = form.email.trim();
+    if email.is_empty() {
+        return Err(AuthError::MissingCredentials);
+    }
+    let token = state
+        .workspace_service
+        .invite(&workspace, user_id, email, form.role)
+        .await?;
+
+    // There is no mailer yet, the link is shown to owners on the workspace page
+    tracing::info!(%workspace_id, invitee = %email, "Invitation created: /invitations/{}", token);
+    Ok(Redirect::to("/workspaces"))
+}
+
+#[instrument(name = "Web: Change member role", skip(state, claims, form))]
+pub async fn member_role_handler(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path((workspace_id, member_id)): Path<(Uuid, Uuid)>,
+    Form(form): Form<MemberRoleForm>,
+) -> Result<impl IntoResponse, AuthError> {
+    let user_id = Uuid::parse_str(&claims.sub).map_err(|_| AuthError::InvalidToken)?;
+    let workspace = state
+        .workspace_service
+        .membership(workspace_id, user_id)
+        .await?;
+    state
+        .workspace_service
+        .set_member_role(&workspace, member_id, form.role)
+        .await?;
+    Ok(Redirect::to("/workspaces"))
+}
+
+#[instrument(name = "Web: Remove member", skip(state, claims))]
+pub async fn remove_member_handler(
+    State(state): State<AppState>,
+    claims: Claims,
+    Path((workspace_id, member_id)): Path<(Uuid, Uuid)>,
+) -> Result<impl IntoResponse, AuthError> {
+    let user_id = Uuid::parse_str(&claims.sub).map_err(|_| AuthError::InvalidToken)?;
+    let workspace = state
+        .workspace_service
+        .membership(workspace_id, user_id)
+        .await?;
+    state
+        .workspace_service
+        .remove_member(&workspace, member_id)
+        .await?;
+    Ok(Redirect::to("/workspaces"))
+}
+
+#[instrument(name = "Web: Invitation page", skip(state, _claims, token))]
+pub async fn invitation_page(
+    State(state): State<AppState>,
+    Page(_claims): Page<Claims>,
+    Path(token): Path<String>,
+    CsrfToken(csrf_token): CsrfToken,
+) -> Result<impl IntoResponse, AuthError> {
+    let invitation = state.workspace_service.find_invitation(&token).await?;
+    let template = InvitationTemplate {
+        csrf_token,
+        invitation,
+    };
+    Ok(Html(template.render().unwrap()))
+}
+
+#[instrument(name = "Web: Accept invitation", skip(state, claims, jar, token))]
+pub async fn accept_invitation_handler(
+    State(state): State<AppState>,
+    claims: Claims,
+    jar: CookieJar,
+    Path(token): Path<String>,
+) -> Result<impl IntoResponse, AuthError> {
+    let user_id = Uuid::parse_str(&claims.sub).map_err(|_| AuthError::InvalidToken)?;
+    let user = state.auth_service.active_user(user_id).await?;
+    let workspace_id = state
+        .workspace_service
+        .accept(&token, user_id, &user.email)
+        .await?;
+
+    Ok((
+        jar.add(workspace_cookie(workspace_id)),
+        Redirect::to("/dashboard"),
+    ))
+}
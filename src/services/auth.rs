@@ -1,28 +1,93 @@
-use crate::{errors::AuthError, store::user::UserRepository};
+use crate::{
+    errors::{AuthError, FieldErrors},
+    models::user::{Role, UserModel},
+    services::{
+        mailer::{Email, Mailer},
+        oidc::OidcIdentity,
+        password::PasswordPolicy,
+        url::is_web_url,
+    },
+    store::user::UserRepository,
+};
 use argon2::{
     Argon2,
     password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString, rand_core::OsRng},
 };
+use std::sync::Arc;
 use tracing::instrument;
 
 #[derive(Clone, Debug)]
 pub struct AuthService {
     repo: UserRepository,
+    password_login_enabled: bool,
+    password_policy: PasswordPolicy,
+    mailer: Option<Arc<dyn Mailer>>,
+    /// Base of the links put into emails
+    public_url: String,
 }
 
 impl AuthService {
-    pub fn new(repo: UserRepository) -> Self {
-        Self { repo }
+    pub fn new(
+        repo: UserRepository,
+        password_login_enabled: bool,
+        password_policy: PasswordPolicy,
+        mailer: Option<Arc<dyn Mailer>>,
+        public_url: String,
+    ) -> Self {
+        Self {
+            repo,
+            password_login_enabled,
+            password_policy,
+            mailer,
+            public_url,
+        }
+    }
+
+    pub fn password_login_enabled(&self) -> bool {
+        self.password_login_enabled
+    }
+
+    pub fn password_policy(&self) -> &PasswordPolicy {
+        &self.password_policy
     }
 
-    pub async fn register(&self, email: &str, password: &str) -> anyhow::Result<uuid::Uuid> {
-        let salt = SaltString::generate(&mut OsRng);
-        let hash = Argon2::default()
-            .hash_password(password.as_bytes(), &salt)
-            .map_err(|_| anyhow::anyhow!("failed to hash password"))?
-            .to_string();
+    #[instrument(name = "AuthService: Register", skip(self, password), fields(user_email = %email))]
+    pub async fn register(&self, email: &str, password: &str) -> Result<uuid::Uuid, AuthError> {
+        let mut errors = FieldErrors::new();
+        if !email.contains('@') {
+            errors.insert("email", vec!["Enter a valid email address".to_string()]);
+        }
+        let problems = self.password_problems(password).await?;
+        if !problems.is_empty() {
+            errors.insert("password", problems);
+        }
+        if !errors.is_empty() {
+            return Err(AuthError::Validation(errors));
+        }
+
+        let hash = hash_password(password).map_err(|e| {
+            tracing::error!("Failed to hash password: {:?}", e);
+            AuthError::Internal
+        })?;
+        self.repo.create_user(email, &hash).await.map_err(|e| {
+            let duplicate = e
+                .downcast_ref::<sqlx::Error>()
+                .and_then(|e| e.as_database_error())
+                .is_some_and(|e| e.is_unique_violation());
+            if duplicate {
+                return AuthError::UserAlreadyExists;
+            }
+            tracing::error!("Database error during registration: {:?}", e);
+            AuthError::Internal
+        })
+    }
 
-        self.repo.create_user(email, &hash).await
+    /// Policy violations for a new password, empty when it is acceptable
+    async fn password_problems(&self, password: &str) -> Result<Vec<String>, AuthError> {
+        self.password_policy.check(password).await.map_err(|e| {
+            tracing::error!("Failed to check password policy: {:?}", e);
+            AuthError::Internal
+        })
     }
 
     #[instrument(
@@ -30,7 +95,11 @@ impl AuthService {
         skip(self, password), 
         fields(user_email = %email)
     )]
-    pub async fn login(&self, email: &str, password: &str) -> Result<uuid::Uuid, AuthError> {
+    pub async fn login(&self, email: &str, password: &str) -> Result<UserModel, AuthError> {
+        if !self.password_login_enabled {
+            return Err(AuthError::PasswordLoginDisabled);
+        }
+
         // 1. Fetch User
         let user = self.repo.find_by_email(email).await.map_err(|e| {
             tracing::error!("Database error during login: {:?}", e);
@@ -45,23 +114,370 @@ impl AuthService {
             }
         };
 
-        // 2. Parse Hash
-        let parsed_hash = PasswordHash::new(&user.password_hash).map_err(|e| {
-            tracing::error!("Critial: Failed to parse password hash from DB: {:?}", e);
+        // 2. Verify Password (single sign-on users have none)
+        verify_password(&user, password)?;
+
+        if user.disabled {
+            tracing::warn!("Login failed: Account is disabled");
+            return Err(AuthError::AccountDisabled);
+        }
+
+        tracing::info!("User authenticated successfully");
+        Ok(user)
+    }
+
+    /// Sign in through an OIDC provider: match on the provider's subject first,
+    /// then link an existing account by verified email, otherwise provision one.
+    #[instrument(
+        name = "AuthService: OIDC login",
+        skip(self, identity),
+        fields(user_email = %identity.email, issuer = %identity.issuer)
+    )]
+    pub async fn login_oidc(&self, identity: &OidcIdentity) -> Result<UserModel, AuthError> {
+        let internal = |e: anyhow::Error| {
+            tracing::error!("Database error during OIDC login: {:?}", e);
             AuthError::Internal
-        })?;
+        };
 
-        // 3. Verify Password
-        if Argon2::default()
-            .verify_password(password.as_bytes(), &parsed_hash)
-            .is_err() 
+        let user = match self
+            .repo
+            .find_by_oidc_subject(&identity.issuer, &identity.subject)
+            .await
+            .map_err(internal)?
         {
-            tracing::warn!("Login failed: Invalid password provided");
-            return Err(AuthError::WrongCredentials);
+            Some(user) => user,
+            None => {
+                if !identity.email_verified {
+                    tracing::warn!("OIDC login failed: Email is not verified by the provider");
+                    return Err(AuthError::EmailNotVerified);
+                }
+
+                let user_id = match self
+                    .repo
+                    .find_by_email(&identity.email)
+                    .await
+                    .map_err(internal)?
+                {
+                    Some(existing) => {
+                        tracing::info!("Linking OIDC identity to existing user");
+                        let linked = self
+                            .repo
+                            .link_oidc_subject(existing.id, &identity.issuer, &identity.subject)
+                            .await
+                            .map_err(internal)?;
+                        // A reused or reassigned email must not take over the account
+                        if !linked {
+                            tracing::warn!(
+                                "OIDC login failed: Account is linked to another identity"
+                            );
+                            return Err(AuthError::OidcIdentityConflict);
+                        }
+                        existing.id
+                    }
+                    None => {
+                        tracing::info!("Provisioning new user from OIDC identity");
+                        self.repo
+                            .create_oidc_user(&identity.email, &identity.issuer, &identity.subject)
+                            .await
+                            .map_err(internal)?
+                    }
+                };
+                self.repo
+                    .find_by_id(user_id)
+                    .await
+                    .map_err(internal)?
+                    .ok_or(AuthError::Internal)?
+            }
+        };
+
+        if user.disabled {
+            tracing::warn!("OIDC login failed: Account is disabled");
+            return Err(AuthError::AccountDisabled);
         }
+        Ok(user)
+    }
 
-        tracing::info!("User authenticated successfully");
-        Ok(user.id)
+    /// Load the user behind a session, rejecting unknown or disabled accounts
+    #[instrument(name = "AuthService: Load active user", skip(self))]
+    pub async fn active_user(&self, user_id: uuid::Uuid) -> Result<UserModel, AuthError> {
+        let user = self.repo.find_by_id(user_id).await.map_err(|e| {
+            tracing::error!("Database error while loading user: {:?}", e);
+            AuthError::Internal
+        })?;
+
+        match user {
+            Some(u) if u.disabled => Err(AuthError::AccountDisabled),
+            Some(u) => Ok(u),
+            None => Err(AuthError::InvalidToken),
+        }
+    }
+
+    pub async fn search_users(&self, query: &str) -> anyhow::Result<Vec<UserModel>> {
+        self.repo.search(query, 100).await
+    }
+
+    pub async fn set_user_disabled(
+        &self,
+        user_id: uuid::Uuid,
+        disabled: bool,
+    ) -> anyhow::Result<bool> {
+        self.repo.set_disabled(user_id, disabled).await
+    }
+
+    pub async fn set_user_role(&self, user_id: uuid::Uuid, role: Role) -> anyhow::Result<bool> {
+        self.repo.set_role(user_id, role).await
+    }
+
+    /// Set or clear (with an empty name) the name shown instead of the email
+    #[instrument(name = "AuthService: Update display name", skip(self))]
+    pub async fn update_display_name(
+        &self,
+        user_id: uuid::Uuid,
+        display_name: &str,
+    ) -> anyhow::Result<()> {
+        let display_name: String = display_name
+            .trim()
+            .chars()
+            .take(MAX_DISPLAY_NAME_CHARS)
+            .collect();
+        let display_name = (!display_name.is_empty()).then_some(display_name.as_str());
+        self.repo.set_display_name(user_id, display_name).await
     }
 
+    /// Set or clear (with an empty value) where the user's unavailable links go
+    #[instrument(name = "AuthService: Update fallback url", skip(self))]
+    pub async fn update_fallback_url(
+        &self,
+        user_id: uuid::Uuid,
+        fallback_url: &str,
+    ) -> Result<(), AuthError> {
+        let fallback_url = fallback_url.trim();
+        let fallback_url = (!fallback_url.is_empty()).then_some(fallback_url);
+        if fallback_url.is_some_and(|url| !is_web_url(url)) {
+            let mut errors = FieldErrors::new();
+            errors.insert(
+                "fallback_url",
+                vec!["Enter a full http:// or https:// address".to_string()],
+            );
+            return Err(AuthError::Validation(errors));
+        }
+        self.repo
+            .set_fallback_url(user_id, fallback_url)
+            .await
+            .map_err(|e| {
+                tracing::error!("Failed to update fallback url: {:?}", e);
+                AuthError::Internal
+            })
+    }
+
+    /// Start an email change: the new address only replaces the current one
+    /// once the returned token has been confirmed
+    #[instrument(
+        name = "AuthService: Request email change",
+        skip(self, password, new_email)
+    )]
+    pub async fn request_email_change(
+        &self,
+        user_id: uuid::Uuid,
+        password: &str,
+        new_email: &str,
+    ) -> Result<(), AuthError> {
+        let mailer = self.mailer.as_ref().ok_or(AuthError::MailerUnavailable)?;
+        let user = self.active_user(user_id).await?;
+        verify_password(&user, password)?;
+        if !new_email.contains('@') {
+            let mut errors = FieldErrors::new();
+            errors.insert("email", vec!["Enter a valid email address".to_string()]);
+            return Err(AuthError::Validation(errors));
+        }
+
+        let taken = self.repo.find_by_email(new_email).await.map_err(|e| {
+            tracing::error!("Database error during email change: {:?}", e);
+            AuthError::Internal
+        })?;
+        if taken.is_some() {
+            return Err(AuthError::UserAlreadyExists);
+        }
+
+        let token = nanoid::nanoid!(32);
+        let expires_at = chrono::Utc::now() + chrono::Duration::hours(EMAIL_CHANGE_TTL_HOURS);
+        self.repo
+            .set_pending_email(user_id, new_email, &token, expires_at)
+            .await
+            .map_err(|e| {
+                tracing::error!("Failed to store pending email: {:?}", e);
+                AuthError::Internal
+            })?;
+
+        let email = Email {
+            to: new_email.to_string(),
+            subject: "Confirm your new email address".to_string(),
+            body: format!(
+                "Open {}/profile/email/confirm?token={} while signed in to confirm the change. \
+                 The link expires in {} hours.",
+                self.public_url, token, EMAIL_CHANGE_TTL_HOURS
+            ),
+        };
+        mailer.send(&email).await.map_err(|e| {
+            tracing::error!("Failed to send email change confirmation: {:?}", e);
+            AuthError::Internal
+        })
+    }
+
+    #[instrument(name = "AuthService: Confirm email change", skip(self, token))]
+    pub async fn confirm_email_change(
+        &self,
+        user_id: uuid::Uuid,
+        token: &str,
+    ) -> Result<String, AuthError> {
+        let user = self.active_user(user_id).await?;
+        if let Some(pending) = user.pending_email.as_deref() {
+            // Someone may have signed up with the address in the meantime
+            let taken = self.repo.find_by_email(pending).await.map_err(|e| {
+                tracing::error!("Database error during email change: {:?}", e);
+                AuthError::Internal
+            })?;
+            if taken.is_some() {
+                return Err(AuthError::UserAlreadyExists);
+            }
+        }
+
+        self.repo
+            .confirm_email_change(user_id, token)
+            .await
+            .map_err(|e| {
+                tracing::error!("Failed to confirm email change: {:?}", e);
+                AuthError::Internal
+            })?
+            .ok_or(AuthError::NotFound)
+    }
+
+    /// Replace the password after checking the current one. Every session issued
+    /// before the change is invalidated; the returned user carries the new
+    /// session version so the caller can re-issue its own token.
+    #[instrument(name = "AuthService: Change password", skip(self, current, new))]
+    pub async fn change_password(
+        &self,
+        user_id: uuid::Uuid,
+        current: &str,
+        new: &str,
+    ) -> Result<UserModel, AuthError> {
+        let mut user = self.active_user(user_id).await?;
+        verify_password(&user, current)?;
+
+        let problems = self.password_problems(new).await?;
+        if !problems.is_empty() {
+            return Err(AuthError::Validation(FieldErrors::from([(
+                "new_password",
+                problems,
+            )])));
+        }
+
+        let internal = |e: anyhow::Error| {
+            tracing::error!("Failed to change password: {:?}", e);
+            AuthError::Internal
+        };
+        let hash = hash_password(new).map_err(internal)?;
+        user.session_version = self
+            .repo
+            .update_password(user_id, &hash)
+            .await
+            .map_err(internal)?;
+        user.password_hash = Some(hash);
+
+        tracing::info!("Password changed, older sessions revoked");
+        Ok(user)
+    }
+
+    /// Check that the user may remove their account: the password must match
+    /// and no shared workspace may be left without an owner
+    #[instrument(name = "AuthService: Confirm account removal", skip(self, password))]
+    pub async fn confirm_account_removal(
+        &self,
+        user_id: uuid::Uuid,
+        password: &str,
+    ) -> Result<UserModel, AuthError> {
+        let user = self.active_user(user_id).await?;
+        // Single sign-on users have no password to confirm with
+        if user.password_hash.is_some() {
+            verify_password(&user, password)?;
+        }
+
+        let blocking = self
+            .repo
+            .solely_owned_shared_workspaces(user_id)
+            .await
+            .map_err(|e| {
+                tracing::error!("Failed to check owned workspaces: {:?}", e);
+                AuthError::Internal
+            })?;
+        if !blocking.is_empty() {
+            tracing::warn!(workspaces = ?blocking, "Account removal blocked by owned workspaces");
+            return Err(AuthError::SoleWorkspaceOwner);
+        }
+        Ok(user)
+    }
+
+    /// Delete the account after checking the password. Returns the short codes
+    /// of the links that were deleted with it.
+    #[instrument(name = "AuthService: Delete account", skip(self, password))]
+    pub async fn delete_account(
+        &self,
+        user_id: uuid::Uuid,
+        password: &str,
+        delete_links: bool,
+    ) -> Result<Vec<String>, AuthError> {
+        self.confirm_account_removal(user_id, password).await?;
+
+        let deleted = self
+            .repo
+            .delete_account(user_id, delete_links)
+            .await
+            .map_err(|e| {
+                tracing::error!("Failed to delete account: {:?}", e);
+                AuthError::Internal
+            })?;
+        tracing::info!(deleted_links = deleted.len(), "Account deleted");
+        Ok(deleted)
+    }
+}
+
+const MAX_DISPLAY_NAME_CHARS: usize = 64;
+
+/// How long a confirmation link for a new email address stays valid
+const EMAIL_CHANGE_TTL_HOURS: i64 = 24;
+
+/// Argon2 hash of a password, also used for link passwords
+pub(crate) fn hash_password(password: &str) -> anyhow::Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|_| anyhow::anyhow!("failed to hash password"))?
+        .to_string();
+    Ok(hash)
+}
+
+fn verify_password(user: &UserModel, password: &str) -> Result<(), AuthError> {
+    let Some(password_hash) = user.password_hash.as_deref() else {
+        tracing::warn!("Password check failed: User has no local password");
+        return Err(AuthError::WrongCredentials);
+    };
+    verify_hash(password_hash, password)
+}
+
+/// Check a password against a hash made by [`hash_password`]
+pub(crate) fn verify_hash(password_hash: &str, password: &str) -> Result<(), AuthError> {
+    let parsed_hash = PasswordHash::new(password_hash).map_err(|e| {
+        tracing::error!("Critial: Failed to parse password hash from DB: {:?}", e);
+        AuthError::Internal
+    })?;
+
+    if Argon2::default()
+        .verify_password(password.as_bytes(), &parsed_hash)
+        .is_err()
+    {
+        tracing::warn!("Password check failed: Invalid password provided");
+        return Err(AuthError::WrongCredentials);
+    }
+    Ok(())
 }
@@ -0,0 +1,298 @@
+use std::borrow::Cow;
+
+use reqwest::Url;
+
+use crate::models::url::{LinkTarget, QueryPassthrough};
+
+/// The part of a visit that can be forwarded to the destination
+#[derive(Debug, Default)]
+pub struct Forwarded<'a> {
+    /// Raw query string of the request, without the `?`
+    pub query: Option<&'a str>,
+    /// Path after the short code, without the leading `/`, already
+    /// percent-decoded
+    pub path: Option<&'a str>,
+}
+
+/// Where a visitor ends up: the destination with the link's UTM parameters,
+/// replacing any the destination already has, then the visit's extra path and
+/// query parameters added as the link allows. `src=qr` only marks the scan
+/// and is never forwarded. Parameters are forwarded as they were written,
+/// bare keys and their own escaping included.
+pub fn forward(destination: &str, target: &LinkTarget, forwarded: &Forwarded) -> String {
+    let query_passthrough = target.query_passthrough;
+    let Ok(mut url) = Url::parse(destination) else {
+        return destination.to_string();
+    };
+
+    if target.path_passthrough
+        && let Some(path) = forwarded.path
+    {
+        // Dot segments would walk out of the destination's path
+        let segments: Vec<&str> = path
+            .split('/')
+            .filter(|segment| !matches!(*segment, "" | "." | ".."))
+            .collect();
+        if !segments.is_empty()
+            && let Ok(mut path_segments) = url.path_segments_mut()
+        {
+            path_segments.pop_if_empty().extend(segments);
+        }
+    }
+
+    let incoming: Vec<Param> = match (query_passthrough, forwarded.query) {
+        (QueryPassthrough::Off, _) | (_, None) => Vec::new(),
+        (_, Some(query)) => params(query)
+            .filter(|param| !(param.key == "src" && param.value == "qr"))
+            .collect(),
+    };
+    if !incoming.is_empty() || !target.utm.is_empty() {
+        // Parameters are moved around as they were written, only the keys
+        // are decoded to compare them
+        let existing = url.query().unwrap_or_default().to_string();
+        let utm: Vec<Param> = target.utm.pairs().map(Param::encode).collect();
+        let mut query: Vec<Param> = params(&existing)
+            .filter(|param| !has_key(&utm, &param.key))
+            .collect();
+        query.extend(utm);
+        match query_passthrough {
+            QueryPassthrough::Off => {}
+            QueryPassthrough::Keep => {
+                let kept: Vec<_> = incoming
+                    .into_iter()
+                    .filter(|param| !has_key(&query, &param.key))
+                    .collect();
+                query.extend(kept);
+            }
+            QueryPassthrough::Override => {
+                query.retain(|param| !has_key(&incoming, &param.key));
+                query.extend(incoming);
+            }
+            QueryPassthrough::Append => query.extend(incoming),
+        }
+        let query: Vec<&str> = query.iter().map(|param| param.raw.as_ref()).collect();
+        url.set_query((!query.is_empty()).then(|| query.join("&")).as_deref());
+    }
+    url.into()
+}
+
+/// One `&`-separated parameter of a query string
+struct Param<'a> {
+    key: String,
+    value: String,
+    /// The parameter as it was written, which is what gets forwarded
+    raw: Cow<'a, str>,
+}
+
+impl<'a> Param<'a> {
+    fn parse(raw: &'a str) -> Self {
+        let (key, value) = serde_urlencoded::from_str::<Vec<(String, String)>>(raw)
+            .ok()
+            .and_then(|pairs| pairs.into_iter().next())
+            .unwrap_or_default();
+        Self {
+            key,
+            value,
+            raw: Cow::Borrowed(raw),
+        }
+    }
+
+    fn encode((key, value): (&str, &str)) -> Param<'static> {
+        Param {
+            key: key.to_string(),
+            value: value.to_string(),
+            raw: Cow::Owned(serde_urlencoded::to_string([(key, value)]).unwrap_or_default()),
+        }
+    }
+}
+
+fn params(query: &str) -> impl Iterator<Item = Param<'_>> {
+    query
+        .split('&')
+        .filter(|raw| !raw.is_empty())
+        .map(Param::parse)
+}
+
+fn has_key(params: &[Param], key: &str) -> bool {
+    params.iter().any(|param| param.key == key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::utm::UtmParams;
+
+    fn target(query_passthrough: QueryPassthrough, path_passthrough: bool) -> LinkTarget {
+        LinkTarget {
+            long_url: String::new(),
+            user_id: None,
+            starts_at: None,
+            expires_at: None,
+            fallback_url: None,
+            query_passthrough,
+            path_passthrough,
+            utm: UtmParams::default(),
+            rules: Vec::new(),
+            protected: false,
+            max_clicks: None,
+        }
+    }
+
+    fn query(query: &str) -> Forwarded<'_> {
+        Forwarded {
+            query: Some(query),
+            path: None,
+        }
+    }
+
+    fn path(path: &str) -> Forwarded<'_> {
+        Forwarded {
+            query: None,
+            path: Some(path),
+        }
+    }
+
+    const DESTINATION: &str = "https://example.com/docs?flag&q=a%20b&next=/x:y~z";
+
+    #[test]
+    fn leaves_the_destination_alone_without_anything_to_add() {
+        for passthrough in [QueryPassthrough::Off, QueryPassthrough::Keep] {
+            let target = target(passthrough, false);
+            assert_eq!(
+                forward(DESTINATION, &target, &Forwarded::default()),
+                DESTINATION
+            );
+        }
+        let off = target(QueryPassthrough::Off, false);
+        assert_eq!(forward(DESTINATION, &off, &query("q=other")), DESTINATION);
+    }
+
+    #[test]
+    fn keep_only_adds_parameters_the_destination_lacks() {
+        let target = target(QueryPassthrough::Keep, false);
+        assert_eq!(
+            forward(DESTINATION, &target, &query("q=other&ref=mail&flag=1")),
+            "https://example.com/docs?flag&q=a%20b&next=/x:y~z&ref=mail"
+        );
+    }
+
+    #[test]
+    fn override_replaces_the_destination_parameters() {
+        let target = target(QueryPassthrough::Override, false);
+        assert_eq!(
+            forward(DESTINATION, &target, &query("q=other+one&ref=mail")),
+            "https://example.com/docs?flag&next=/x:y~z&q=other+one&ref=mail"
+        );
+    }
+
+    #[test]
+    fn append_keeps_both_values() {
+        let target = target(QueryPassthrough::Append, false);
+        assert_eq!(
+            forward(DESTINATION, &target, &query("q=other&flag")),
+            "https://example.com/docs?flag&q=a%20b&next=/x:y~z&q=other&flag"
+        );
+    }
+
+    #[test]
+    fn compares_decoded_keys() {
+        let target = target(QueryPassthrough::Override, false);
+        assert_eq!(
+            forward("https://example.com/?a%20b=1&c=2", &target, &query("a+b=3")),
+            "https://example.com/?c=2&a+b=3"
+        );
+    }
+
+    #[test]
+    fn strips_the_qr_marker() {
+        let target = target(QueryPassthrough::Append, false);
+        assert_eq!(
+            forward("https://example.com/", &target, &query("src=qr&ref=mail")),
+            "https://example.com/?ref=mail"
+        );
+        assert_eq!(
+            forward("https://example.com/", &target, &query("src=qr")),
+            "https://example.com/"
+        );
+        assert_eq!(
+            forward("https://example.com/", &target, &query("src=feed")),
+            "https://example.com/?src=feed"
+        );
+    }
+
+    #[test]
+    fn drops_dot_segments_from_the_extra_path() {
+        let target = target(QueryPassthrough::Off, true);
+        assert_eq!(
+            forward(
+                "https://example.com/docs/",
+                &target,
+                &path("../../admin/./users/")
+            ),
+            "https://example.com/docs/admin/users"
+        );
+        assert_eq!(
+            forward("https://example.com/docs", &target, &path("..")),
+            "https://example.com/docs"
+        );
+    }
+
+    #[test]
+    fn keeps_an_encoded_slash_inside_its_segment() {
+        // The path arrives decoded, a `%2F` still in it was written as `%252F`
+        // by the visitor and must not turn into a separator
+        let target = target(QueryPassthrough::Off, true);
+        assert_eq!(
+            forward(
+                "https://example.com/docs",
+                &target,
+                &path("..%2F..%2Fadmin")
+            ),
+            "https://example.com/docs/..%252F..%252Fadmin"
+        );
+    }
+
+    #[test]
+    fn ignores_the_extra_path_unless_passed_through() {
+        let target = target(QueryPassthrough::Off, false);
+        assert_eq!(
+            forward("https://example.com/docs", &target, &path("more")),
+            "https://example.com/docs"
+        );
+    }
+
+    #[test]
+    fn utm_parameters_replace_the_destinations_and_keep_the_rest_as_written() {
+        let mut target = target(QueryPassthrough::Off, false);
+        target.utm = UtmParams {
+            utm_source: Some("news letter".to_string()),
+            utm_campaign: Some("spring".to_string()),
+            ..UtmParams::default()
+        };
+        assert_eq!(
+            forward(
+                "https://example.com/?flag&utm_source=old&q=a%20b",
+                &target,
+                &Forwarded::default()
+            ),
+            "https://example.com/?flag&q=a%20b&utm_source=news+letter&utm_campaign=spring"
+        );
+    }
+
+    #[test]
+    fn visitor_utm_parameters_only_win_with_override() {
+        let mut keep = target(QueryPassthrough::Keep, false);
+        keep.utm.utm_source = Some("link".to_string());
+        let mut replace = keep.clone();
+        replace.query_passthrough = QueryPassthrough::Override;
+        let visit = query("utm_source=visitor");
+        assert_eq!(
+            forward("https://example.com/", &keep, &visit),
+            "https://example.com/?utm_source=link"
+        );
+        assert_eq!(
+            forward("https://example.com/", &replace, &visit),
+            "https://example.com/?utm_source=visitor"
+        );
+    }
+}
@@ -0,0 +1,62 @@
+use crate::models::url::{ExportedLink, UrlCsvRow};
+
+/// Header row of a CSV export. Daily exports add the day, its clicks, QR scans and fallback visits.
+pub fn csv_head(daily: bool) -> anyhow::Result<Vec<u8>> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    if daily {
+        let mut header = UrlCsvRow::HEADER.to_vec();
+        header.extend(["day", "day_clicks", "day_qr_scans", "day_fallbacks"]);
+        writer.write_record(header)?;
+    } else {
+        writer.write_record(UrlCsvRow::HEADER)?;
+    }
+    Ok(writer.into_inner()?)
+}
+
+/// CSV rows for a batch. With days, a link takes one row per day it was
+/// clicked, or a single row with an empty day when it was not.
+pub fn csv_chunk(links: &[ExportedLink]) -> anyhow::Result<Vec<u8>> {
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(Vec::new());
+    for exported in links {
+        let row = || UrlCsvRow::from(&exported.link);
+        match exported.daily_clicks.as_deref() {
+            None => writer.serialize(row())?,
+            Some([]) => writer.serialize((
+                row(),
+                None::<chrono::NaiveDate>,
+                None::<i64>,
+                None::<i64>,
+                None::<i64>,
+            ))?,
+            Some(days) => {
+                for day in days {
+                    writer.serialize((row(), day.day, day.clicks, day.qr_scans, day.fallbacks))?;
+                }
+            }
+        }
+    }
+    Ok(writer.into_inner()?)
+}
+
+/// Exports are streamed batch by batch, JSON ones as a single array
+pub fn json_head() -> Vec<u8> {
+    b"[".to_vec()
+}
+
+/// Array elements for a batch, `first` when nothing was written before it
+pub fn json_chunk(links: &[ExportedLink], first: bool) -> anyhow::Result<Vec<u8>> {
+    let mut chunk = Vec::new();
+    for (i, link) in links.iter().enumerate() {
+        if !first || i > 0 {
+            chunk.push(b',');
+        }
+        serde_json::to_writer(&mut chunk, link)?;
+    }
+    Ok(chunk)
+}
+
+pub fn json_tail() -> Vec<u8> {
+    b"]".to_vec()
+}
@@ -0,0 +1,231 @@
+use std::sync::Arc;
+
+use aws_lc_rs::signature::{Ed25519KeyPair, KeyPair, RsaKeyPair, RsaPublicKeyComponents};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use chrono::{DateTime, Utc};
+use jsonwebtoken::{
+    Algorithm, DecodingKey, EncodingKey, Header, Validation, decode, decode_header, encode,
+    jwk::{
+        AlgorithmParameters, CommonParameters, EllipticCurve, Jwk, JwkSet, KeyAlgorithm,
+        OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse, RSAKeyParameters, RSAKeyType,
+    },
+};
+use serde::{Serialize, de::DeserializeOwned};
+
+use crate::{
+    configuration::{AuthSettings, JwtAlgorithm, JwtKeySettings},
+    errors::AuthError,
+};
+
+/// Issues and verifies session tokens with asymmetric keys.
+///
+/// Several keys can be configured at once: new tokens are signed with the most
+/// recently activated key, while every key that has not been retired is still
+/// accepted and published in the JWKS so other services can verify tokens.
+#[derive(Clone)]
+pub struct JwtService {
+    inner: Arc<JwtKeys>,
+}
+
+struct JwtKeys {
+    issuer: String,
+    ttl: chrono::Duration,
+    keys: Vec<SigningKey>,
+}
+
+struct SigningKey {
+    kid: String,
+    algorithm: Algorithm,
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+    jwk: Jwk,
+    active_from: Option<DateTime<Utc>>,
+    retire_at: Option<DateTime<Utc>>,
+}
+
+impl std::fmt::Debug for JwtService {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kids: Vec<&str> = self.inner.keys.iter().map(|k| k.kid.as_str()).collect();
+        f.debug_struct("JwtService")
+            .field("issuer", &self.inner.issuer)
+            .field("keys", &kids)
+            .finish()
+    }
+}
+
+impl SigningKey {
+    fn load(settings: &JwtKeySettings) -> anyhow::Result<Self> {
+        let pem_text = settings.private_key_pem()?;
+        let der = pem::parse(pem_text.as_bytes())
+            .map_err(|e| anyhow::anyhow!("key `{}` is not valid PEM: {}", settings.kid, e))?;
+
+        let (algorithm, encoding, decoding, params) = match settings.algorithm {
+            JwtAlgorithm::EdDSA => {
+                let pair = Ed25519KeyPair::from_pkcs8(der.contents()).map_err(|e| {
+                    anyhow::anyhow!("key `{}` is not an Ed25519 key: {}", settings.kid, e)
+                })?;
+                let x = URL_SAFE_NO_PAD.encode(pair.public_key().as_ref());
+                (
+                    Algorithm::EdDSA,
+                    EncodingKey::from_ed_der(der.contents()),
+                    DecodingKey::from_ed_components(&x)?,
+                    AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                        key_type: OctetKeyPairType::OctetKeyPair,
+                        curve: EllipticCurve::Ed25519,
+                        x,
+                    }),
+                )
+            }
+            JwtAlgorithm::RS256 => {
+                let pair = RsaKeyPair::from_pkcs8(der.contents())
+                    .or_else(|_| RsaKeyPair::from_der(der.contents()))
+                    .map_err(|e| {
+                        anyhow::anyhow!("key `{}` is not an RSA key: {}", settings.kid, e)
+                    })?;
+                let components = RsaPublicKeyComponents::<Vec<u8>>::from(pair.public_key());
+                (
+                    Algorithm::RS256,
+                    EncodingKey::from_rsa_pem(pem_text.as_bytes())?,
+                    DecodingKey::from_rsa_raw_components(&components.n, &components.e),
+                    AlgorithmParameters::RSA(RSAKeyParameters {
+                        key_type: RSAKeyType::RSA,
+                        n: URL_SAFE_NO_PAD.encode(&components.n),
+                        e: URL_SAFE_NO_PAD.encode(&components.e),
+                    }),
+                )
+            }
+        };
+
+        let jwk = Jwk {
+            common: CommonParameters {
+                public_key_use: Some(PublicKeyUse::Signature),
+                key_algorithm: Some(match algorithm {
+                    Algorithm::EdDSA => KeyAlgorithm::EdDSA,
+                    _ => KeyAlgorithm::RS256,
+                }),
+                key_id: Some(settings.kid.clone()),
+                ..Default::default()
+            },
+            algorithm: params,
+        };
+
+        Ok(Self {
+            kid: settings.kid.clone(),
+            algorithm,
+            encoding,
+            decoding,
+            jwk,
+            active_from: settings.active_from,
+            retire_at: settings.retire_at,
+        })
+    }
+
+    fn is_retired(&self, now: DateTime<Utc>) -> bool {
+        self.retire_at.is_some_and(|at| at <= now)
+    }
+
+    /// A key may sign once it is active and only if every token it signs
+    /// expires before the key is retired.
+    fn can_sign(&self, now: DateTime<Utc>, ttl: chrono::Duration) -> bool {
+        self.active_from.is_none_or(|at| at <= now) && !self.is_retired(now + ttl)
+    }
+}
+
+impl JwtService {
+    pub fn from_settings(settings: &AuthSettings) -> anyhow::Result<Self> {
+        let keys = settings
+            .keys
+            .iter()
+            .map(SigningKey::load)
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let service = Self {
+            inner: Arc::new(JwtKeys {
+                issuer: settings.issuer.clone(),
+                ttl: chrono::Duration::hours(settings.token_ttl_hours),
+                keys,
+            }),
+        };
+        if service.current_key(Utc::now()).is_none() {
+            anyhow::bail!("no JWT signing key is currently active");
+        }
+        Ok(service)
+    }
+
+    pub fn issuer(&self) -> &str {
+        &self.inner.issuer
+    }
+
+    /// Expiry timestamp for a token issued now
+    pub fn expires_at(&self) -> usize {
+        (Utc::now() + self.inner.ttl).timestamp() as usize
+    }
+
+    fn current_key(&self, now: DateTime<Utc>) -> Option<&SigningKey> {
+        self.inner
+            .keys
+            .iter()
+            .filter(|k| k.can_sign(now, self.inner.ttl))
+            .max_by_key(|k| k.active_from)
+    }
+
+    pub fn encode<T: Serialize>(&self, claims: &T) -> Result<String, AuthError> {
+        let key = self.current_key(Utc::now()).ok_or_else(|| {
+            tracing::error!("No JWT signing key is currently active");
+            AuthError::TokenCreation
+        })?;
+
+        let mut header = Header::new(key.algorithm);
+        header.kid = Some(key.kid.clone());
+        encode(&header, claims, &key.encoding).map_err(|e| {
+            tracing::error!("JWT Encoding failed: {:?}", e);
+            AuthError::TokenCreation
+        })
+    }
+
+    pub fn decode<T: DeserializeOwned>(&self, token: &str) -> Result<T, AuthError> {
+        let header = decode_header(token).map_err(|e| {
+            tracing::error!("JWT header decoding failed: {:?}", e);
+            AuthError::InvalidToken
+        })?;
+        let kid = header.kid.ok_or_else(|| {
+            tracing::warn!("JWT has no key id");
+            AuthError::InvalidToken
+        })?;
+
+        let now = Utc::now();
+        let key = self
+            .inner
+            .keys
+            .iter()
+            .find(|k| k.kid == kid && !k.is_retired(now))
+            .ok_or_else(|| {
+                tracing::warn!(%kid, "JWT signed with an unknown or retired key");
+                AuthError::InvalidToken
+            })?;
+
+        let mut validation = Validation::new(key.algorithm);
+        validation.set_issuer(&[&self.inner.issuer]);
+        decode::<T>(token, &key.decoding, &validation)
+            .map(|data| data.claims)
+            .map_err(|e| {
+                tracing::error!("JWT decoding failed: {:?}", e);
+                AuthError::InvalidToken
+            })
+    }
+
+    /// Public keys of every key that has not been retired yet, including keys
+    /// scheduled for the future so verifiers can pick them up ahead of time.
+    pub fn jwks(&self) -> JwkSet {
+        let now = Utc::now();
+        JwkSet {
+            keys: self
+                .inner
+                .keys
+                .iter()
+                .filter(|k| !k.is_retired(now))
+                .map(|k| k.jwk.clone())
+                .collect(),
+        }
+    }
+}
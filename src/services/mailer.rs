@@ -0,0 +1,35 @@
+use crate::configuration::MailerSettings;
+use futures_util::future::BoxFuture;
+use std::sync::Arc;
+
+/// A plain text email to a single recipient
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Delivers account emails such as email change confirmations
+pub trait Mailer: std::fmt::Debug + Send + Sync {
+    fn send<'a>(&'a self, email: &'a Email) -> BoxFuture<'a, anyhow::Result<()>>;
+}
+
+pub fn from_settings(settings: &MailerSettings) -> Arc<dyn Mailer> {
+    match settings {
+        MailerSettings::Log => Arc::new(LogMailer),
+    }
+}
+
+/// Writes emails to the log instead of sending them, for local development.
+/// Production configurations refuse it since bodies carry confirmation tokens.
+#[derive(Debug)]
+pub struct LogMailer;
+
+impl Mailer for LogMailer {
+    fn send<'a>(&'a self, email: &'a Email) -> BoxFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            tracing::info!(to = %email.to, subject = %email.subject, "{}", email.body);
+            Ok(())
+        })
+    }
+}
@@ -1,2 +1,16 @@
 pub mod auth;
+pub mod destination;
+pub mod export;
+pub mod jwt;
+pub mod mailer;
+pub mod oidc;
+pub mod password;
+pub mod plan;
+pub mod privacy;
+pub mod qr;
+pub mod tag;
+pub mod targeting;
 pub mod url;
+pub mod usage;
+pub mod utm;
+pub mod workspace;
@@ -0,0 +1,540 @@
+use std::sync::Arc;
+
+use aws_lc_rs::digest::{SHA256, digest};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use jsonwebtoken::{DecodingKey, Validation, decode, decode_header, jwk::JwkSet};
+use nanoid::nanoid;
+use secrecy::ExposeSecret;
+use serde::{Deserialize, Serialize};
+use tokio::sync::OnceCell;
+use tracing::instrument;
+
+use crate::configuration::OidcSettings;
+
+/// Login through an external OpenID Connect provider using the
+/// authorization code flow with PKCE.
+#[derive(Clone)]
+pub struct OidcService {
+    http: reqwest::Client,
+    settings: Arc<OidcSettings>,
+    metadata: Arc<OnceCell<ProviderMetadata>>,
+}
+
+impl std::fmt::Debug for OidcService {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OidcService")
+            .field("issuer_url", &self.settings.issuer_url)
+            .field("client_id", &self.settings.client_id)
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct IdTokenClaims {
+    sub: String,
+    email: Option<String>,
+    // Some providers send this as the string "true"
+    email_verified: Option<serde_json::Value>,
+    nonce: Option<String>,
+}
+
+/// Per-login secrets kept in a signed cookie between the redirect to the
+/// provider and the callback.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OidcFlow {
+    pub state: String,
+    pub nonce: String,
+    pub code_verifier: String,
+}
+
+impl OidcFlow {
+    /// Whether a callback carries the state this login was started with
+    pub fn matches_state(&self, state: Option<&str>) -> bool {
+        state == Some(self.state.as_str())
+    }
+}
+
+/// The identity asserted by a validated ID token
+#[derive(Debug)]
+pub struct OidcIdentity {
+    pub issuer: String,
+    pub subject: String,
+    pub email: String,
+    pub email_verified: bool,
+}
+
+impl OidcService {
+    pub fn new(settings: OidcSettings) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            settings: Arc::new(settings),
+            metadata: Arc::new(OnceCell::new()),
+        }
+    }
+
+    pub fn display_name(&self) -> &str {
+        &self.settings.display_name
+    }
+
+    async fn metadata(&self) -> anyhow::Result<&ProviderMetadata> {
+        self.metadata
+            .get_or_try_init(|| async {
+                let url = format!(
+                    "{}/.well-known/openid-configuration",
+                    self.settings.issuer_url.trim_end_matches('/')
+                );
+                let metadata: ProviderMetadata = self
+                    .http
+                    .get(url)
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json()
+                    .await?;
+                Ok(metadata)
+            })
+            .await
+    }
+
+    /// Build the provider URL to send the browser to, along with the secrets
+    /// needed to complete the flow on callback.
+    #[instrument(name = "OidcService: Authorization url", skip(self))]
+    pub async fn authorization_url(&self) -> anyhow::Result<(String, OidcFlow)> {
+        let metadata = self.metadata().await?;
+        let flow = OidcFlow {
+            state: nanoid!(32),
+            nonce: nanoid!(32),
+            code_verifier: nanoid!(64),
+        };
+        let challenge =
+            URL_SAFE_NO_PAD.encode(digest(&SHA256, flow.code_verifier.as_bytes()).as_ref());
+
+        let url = reqwest::Url::parse_with_params(
+            &metadata.authorization_endpoint,
+            &[
+                ("response_type", "code"),
+                ("client_id", self.settings.client_id.as_str()),
+                ("redirect_uri", self.settings.redirect_url.as_str()),
+                ("scope", self.settings.scopes.join(" ").as_str()),
+                ("state", flow.state.as_str()),
+                ("nonce", flow.nonce.as_str()),
+                ("code_challenge", challenge.as_str()),
+                ("code_challenge_method", "S256"),
+            ],
+        )?;
+        Ok((url.to_string(), flow))
+    }
+
+    /// Redeem the authorization code and validate the returned ID token
+    #[instrument(name = "OidcService: Exchange code", skip(self, code, flow))]
+    pub async fn exchange(&self, code: &str, flow: &OidcFlow) -> anyhow::Result<OidcIdentity> {
+        let metadata = self.metadata().await?;
+
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", self.settings.redirect_url.as_str()),
+            ("client_id", self.settings.client_id.as_str()),
+            ("code_verifier", flow.code_verifier.as_str()),
+        ];
+        if let Some(secret) = &self.settings.client_secret {
+            form.push(("client_secret", secret.expose_secret()));
+        }
+        let tokens: TokenResponse = self
+            .http
+            .post(&metadata.token_endpoint)
+            .form(&form)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        let claims = self.validate_id_token(metadata, &tokens.id_token).await?;
+        if claims.nonce.as_deref() != Some(flow.nonce.as_str()) {
+            anyhow::bail!("ID token nonce does not match");
+        }
+        let email = claims.email.ok_or_else(|| {
+            anyhow::anyhow!("ID token has no email claim, request the `email` scope")
+        })?;
+        let email_verified = matches!(claims.email_verified, Some(serde_json::Value::Bool(true)))
+            || matches!(claims.email_verified, Some(serde_json::Value::String(ref v)) if v == "true");
+
+        Ok(OidcIdentity {
+            issuer: metadata.issuer.clone(),
+            subject: claims.sub,
+            email,
+            email_verified,
+        })
+    }
+
+    async fn validate_id_token(
+        &self,
+        metadata: &ProviderMetadata,
+        id_token: &str,
+    ) -> anyhow::Result<IdTokenClaims> {
+        let header = decode_header(id_token)?;
+        // Only accept signatures made with the provider's published keys
+        if matches!(
+            header.alg,
+            jsonwebtoken::Algorithm::HS256
+                | jsonwebtoken::Algorithm::HS384
+                | jsonwebtoken::Algorithm::HS512
+        ) {
+            anyhow::bail!("ID token uses a symmetric algorithm");
+        }
+
+        // Fetched on every login so provider key rotation is picked up
+        let jwks: JwkSet = self
+            .http
+            .get(&metadata.jwks_uri)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let jwk = match &header.kid {
+            Some(kid) => jwks.find(kid),
+            None if jwks.keys.len() == 1 => jwks.keys.first(),
+            None => None,
+        }
+        .ok_or_else(|| anyhow::anyhow!("no provider key matches the ID token"))?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_audience(&[&self.settings.client_id]);
+        validation.set_issuer(&[&metadata.issuer]);
+        let data = decode::<IdTokenClaims>(id_token, &DecodingKey::from_jwk(jwk)?, &validation)?;
+        Ok(data.claims)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+    };
+
+    use axum::{
+        Form, Json, Router,
+        extract::State,
+        http::StatusCode,
+        routing::{get, post},
+    };
+    use jsonwebtoken::{EncodingKey, Header, encode};
+    use serde_json::{Value, json};
+
+    use super::*;
+    use crate::{
+        configuration::{AuthSettings, JwtAlgorithm, JwtKeySettings},
+        services::jwt::JwtService,
+    };
+
+    const CLIENT_ID: &str = "shorty";
+    const REDIRECT_URL: &str = "http://localhost:8000/auth/oidc/callback";
+    const CODE: &str = "authorization-code";
+
+    /// What the mock provider expects and hands out for the login under test
+    #[derive(Default)]
+    struct Exchange {
+        code_challenge: String,
+        id_token: String,
+    }
+
+    /// An OpenID provider on a local port with discovery, keys and a token
+    /// endpoint that checks the code and the PKCE verifier
+    struct MockIssuer {
+        url: String,
+        exchange: Arc<Mutex<Exchange>>,
+    }
+
+    impl MockIssuer {
+        async fn start() -> Self {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let url = format!("http://{}", listener.local_addr().unwrap());
+            let exchange = Arc::new(Mutex::new(Exchange::default()));
+            let discovery = json!({
+                "issuer": url,
+                "authorization_endpoint": format!("{}/authorize", url),
+                "token_endpoint": format!("{}/token", url),
+                "jwks_uri": format!("{}/jwks", url),
+            });
+            let jwks = serde_json::to_value(signer(&url, "mock-key").jwks()).unwrap();
+            let app = Router::new()
+                .route(
+                    "/.well-known/openid-configuration",
+                    get(move || async move { Json(discovery) }),
+                )
+                .route("/jwks", get(move || async move { Json(jwks) }))
+                .route("/token", post(token))
+                .with_state(exchange.clone());
+            tokio::spawn(async move { axum::serve(listener, app).await });
+            Self { url, exchange }
+        }
+
+        fn service(&self) -> OidcService {
+            OidcService::new(OidcSettings {
+                issuer_url: self.url.clone(),
+                client_id: CLIENT_ID.to_string(),
+                client_secret: None,
+                redirect_url: REDIRECT_URL.to_string(),
+                display_name: "Mock".to_string(),
+                scopes: vec!["openid".into(), "email".into()],
+            })
+        }
+
+        /// Start a login and have the provider answer the code exchange with
+        /// `id_token`
+        async fn login(
+            &self,
+            service: &OidcService,
+            id_token: impl FnOnce(&OidcFlow) -> String,
+        ) -> OidcFlow {
+            let (url, flow) = service.authorization_url().await.unwrap();
+            let url = reqwest::Url::parse(&url).unwrap();
+            let params: HashMap<_, _> = url.query_pairs().into_owned().collect();
+            let mut exchange = self.exchange.lock().unwrap();
+            exchange.code_challenge = params["code_challenge"].clone();
+            exchange.id_token = id_token(&flow);
+            flow
+        }
+
+        /// Claims of a valid ID token for `flow`, to be adjusted per test
+        fn claims(&self, flow: &OidcFlow) -> Value {
+            json!({
+                "iss": self.url,
+                "aud": CLIENT_ID,
+                "sub": "user-1",
+                "email": "ada@example.com",
+                "email_verified": true,
+                "nonce": flow.nonce,
+                "iat": chrono::Utc::now().timestamp(),
+                "exp": chrono::Utc::now().timestamp() + 300,
+            })
+        }
+
+        fn sign(&self, claims: &Value) -> String {
+            signer(&self.url, "mock-key").encode(claims).unwrap()
+        }
+    }
+
+    /// Signs with the development key under `kid`
+    fn signer(issuer: &str, kid: &str) -> JwtService {
+        JwtService::from_settings(&AuthSettings {
+            issuer: issuer.to_string(),
+            token_ttl_hours: 1,
+            keys: vec![JwtKeySettings {
+                kid: kid.to_string(),
+                algorithm: JwtAlgorithm::EdDSA,
+                private_key: None,
+                private_key_path: Some("configurations/keys/dev-ed25519.pem".to_string()),
+                active_from: None,
+                retire_at: None,
+            }],
+            password_login_enabled: true,
+            password_policy: Default::default(),
+        })
+        .unwrap()
+    }
+
+    async fn token(
+        State(exchange): State<Arc<Mutex<Exchange>>>,
+        Form(form): Form<HashMap<String, String>>,
+    ) -> Result<Json<Value>, StatusCode> {
+        let exchange = exchange.lock().unwrap();
+        let verifier = form
+            .get("code_verifier")
+            .map(String::as_str)
+            .unwrap_or_default();
+        let challenge = URL_SAFE_NO_PAD.encode(digest(&SHA256, verifier.as_bytes()).as_ref());
+        let valid = form.get("grant_type").map(String::as_str) == Some("authorization_code")
+            && form.get("code").map(String::as_str) == Some(CODE)
+            && form.get("client_id").map(String::as_str) == Some(CLIENT_ID)
+            && form.get("redirect_uri").map(String::as_str) == Some(REDIRECT_URL)
+            && challenge == exchange.code_challenge;
+        if !valid {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+        Ok(Json(
+            json!({ "id_token": exchange.id_token, "token_type": "Bearer" }),
+        ))
+    }
+
+    #[tokio::test]
+    async fn authorization_url_carries_the_flow() {
+        let issuer = MockIssuer::start().await;
+        let (url, flow) = issuer.service().authorization_url().await.unwrap();
+        let url = reqwest::Url::parse(&url).unwrap();
+        assert_eq!(
+            url.as_str().split('?').next(),
+            Some(format!("{}/authorize", issuer.url).as_str())
+        );
+        let params: HashMap<_, _> = url.query_pairs().into_owned().collect();
+        assert_eq!(params["response_type"], "code");
+        assert_eq!(params["client_id"], CLIENT_ID);
+        assert_eq!(params["redirect_uri"], REDIRECT_URL);
+        assert_eq!(params["scope"], "openid email");
+        assert_eq!(params["state"], flow.state);
+        assert_eq!(params["nonce"], flow.nonce);
+        assert_eq!(params["code_challenge_method"], "S256");
+        assert_eq!(
+            params["code_challenge"],
+            URL_SAFE_NO_PAD.encode(digest(&SHA256, flow.code_verifier.as_bytes()).as_ref())
+        );
+
+        // Every login gets its own secrets
+        let (_, other) = issuer.service().authorization_url().await.unwrap();
+        assert_ne!(other.state, flow.state);
+        assert_ne!(other.nonce, flow.nonce);
+    }
+
+    #[tokio::test]
+    async fn callback_state_must_match() {
+        let issuer = MockIssuer::start().await;
+        let (_, flow) = issuer.service().authorization_url().await.unwrap();
+        assert!(flow.matches_state(Some(&flow.state)));
+        assert!(!flow.matches_state(Some("forged")));
+        assert!(!flow.matches_state(None));
+    }
+
+    #[tokio::test]
+    async fn exchange_returns_the_verified_identity() {
+        let issuer = MockIssuer::start().await;
+        let service = issuer.service();
+        let flow = issuer
+            .login(&service, |flow| issuer.sign(&issuer.claims(flow)))
+            .await;
+
+        let identity = service.exchange(CODE, &flow).await.unwrap();
+        assert_eq!(identity.issuer, issuer.url);
+        assert_eq!(identity.subject, "user-1");
+        assert_eq!(identity.email, "ada@example.com");
+        assert!(identity.email_verified);
+    }
+
+    #[tokio::test]
+    async fn email_verified_may_be_a_string() {
+        let issuer = MockIssuer::start().await;
+        let service = issuer.service();
+        for (verified, expected) in [
+            (json!("true"), true),
+            (json!("false"), false),
+            (Value::Null, false),
+        ] {
+            let flow = issuer
+                .login(&service, |flow| {
+                    let mut claims = issuer.claims(flow);
+                    claims["email_verified"] = verified.clone();
+                    issuer.sign(&claims)
+                })
+                .await;
+            let identity = service.exchange(CODE, &flow).await.unwrap();
+            assert_eq!(identity.email_verified, expected, "{}", verified);
+        }
+    }
+
+    type ClaimsChange = fn(&mut Value);
+
+    #[tokio::test]
+    async fn rejects_invalid_id_tokens() {
+        let issuer = MockIssuer::start().await;
+        let service = issuer.service();
+        let cases: [(&str, ClaimsChange); 6] = [
+            ("another nonce", |claims| {
+                claims["nonce"] = json!("replayed")
+            }),
+            ("no nonce", |claims| claims["nonce"] = Value::Null),
+            ("another audience", |claims| {
+                claims["aud"] = json!("other-client")
+            }),
+            ("another issuer", |claims| {
+                claims["iss"] = json!("https://evil.example.com")
+            }),
+            ("expired", |claims| {
+                claims["exp"] = json!(chrono::Utc::now().timestamp() - 3600)
+            }),
+            ("no email", |claims| {
+                claims.as_object_mut().unwrap().remove("email");
+            }),
+        ];
+        for (case, change) in cases {
+            let flow = issuer
+                .login(&service, |flow| {
+                    let mut claims = issuer.claims(flow);
+                    change(&mut claims);
+                    issuer.sign(&claims)
+                })
+                .await;
+            assert!(service.exchange(CODE, &flow).await.is_err(), "{}", case);
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_tokens_not_signed_by_the_provider() {
+        let issuer = MockIssuer::start().await;
+        let service = issuer.service();
+
+        // A key the provider does not publish
+        let flow = issuer
+            .login(&service, |flow| {
+                signer(&issuer.url, "rogue-key")
+                    .encode(&issuer.claims(flow))
+                    .unwrap()
+            })
+            .await;
+        assert!(service.exchange(CODE, &flow).await.is_err());
+
+        // A shared secret instead of the provider's key
+        let flow = issuer
+            .login(&service, |flow| {
+                let mut header = Header::new(jsonwebtoken::Algorithm::HS256);
+                header.kid = Some("mock-key".to_string());
+                encode(
+                    &header,
+                    &issuer.claims(flow),
+                    &EncodingKey::from_secret(CLIENT_ID.as_bytes()),
+                )
+                .unwrap()
+            })
+            .await;
+        assert!(service.exchange(CODE, &flow).await.is_err());
+
+        // A valid token with its claims changed afterwards
+        let flow = issuer
+            .login(&service, |flow| {
+                let token = issuer.sign(&issuer.claims(flow));
+                let mut parts: Vec<String> = token.split('.').map(str::to_string).collect();
+                let mut claims = issuer.claims(flow);
+                claims["sub"] = json!("admin");
+                parts[1] = URL_SAFE_NO_PAD.encode(claims.to_string());
+                parts.join(".")
+            })
+            .await;
+        assert!(service.exchange(CODE, &flow).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn exchange_needs_the_matching_verifier() {
+        let issuer = MockIssuer::start().await;
+        let service = issuer.service();
+        let mut flow = issuer
+            .login(&service, |flow| issuer.sign(&issuer.claims(flow)))
+            .await;
+        assert!(service.exchange("another-code", &flow).await.is_err());
+        flow.code_verifier = "guessed".to_string();
+        assert!(service.exchange(CODE, &flow).await.is_err());
+    }
+}
@@ -0,0 +1,271 @@
+use std::{collections::HashSet, path::PathBuf, sync::Arc};
+
+use aws_lc_rs::digest;
+
+use crate::configuration::PasswordPolicySettings;
+
+/// SHA-1 hashes of very common passwords, one uppercase hex hash per line
+const BUNDLED_BREACHED: &str = include_str!("breached_passwords.txt");
+
+/// Rules a new password has to satisfy before it is hashed and stored
+#[derive(Clone, Debug)]
+pub struct PasswordPolicy {
+    min_length: usize,
+    min_entropy_bits: f64,
+    breached: Arc<BreachedPasswords>,
+}
+
+#[derive(Debug)]
+enum BreachedPasswords {
+    /// Hashes compiled into the binary
+    Bundled(HashSet<String>),
+    /// Directory of range files in the k-anonymity layout: each file is named
+    /// after the first five hex digits of the SHA-1 hash and lists the remaining
+    /// 35 digits as `SUFFIX:COUNT` lines, so only one small file is read per check
+    RangeDir(PathBuf),
+}
+
+impl PasswordPolicy {
+    pub fn from_settings(settings: &PasswordPolicySettings) -> anyhow::Result<Self> {
+        let breached = match &settings.breached_hashes_dir {
+            Some(dir) => {
+                let dir = PathBuf::from(dir);
+                if !dir.is_dir() {
+                    anyhow::bail!(
+                        "breached password directory {} does not exist",
+                        dir.display()
+                    );
+                }
+                BreachedPasswords::RangeDir(dir)
+            }
+            None => BreachedPasswords::Bundled(
+                BUNDLED_BREACHED
+                    .lines()
+                    .map(|line| {
+                        line.split(':')
+                            .next()
+                            .unwrap_or_default()
+                            .trim()
+                            .to_uppercase()
+                    })
+                    .filter(|hash| !hash.is_empty())
+                    .collect(),
+            ),
+        };
+
+        Ok(Self {
+            min_length: settings.min_length,
+            min_entropy_bits: settings.min_entropy_bits,
+            breached: Arc::new(breached),
+        })
+    }
+
+    pub fn min_length(&self) -> usize {
+        self.min_length
+    }
+
+    /// Every rule the password breaks, as messages that can be shown next to the field
+    pub async fn check(&self, password: &str) -> anyhow::Result<Vec<String>> {
+        let mut problems = Vec::new();
+
+        if password.chars().count() < self.min_length {
+            problems.push(format!("Use at least {} characters", self.min_length));
+        } else if estimate_entropy_bits(password) < self.min_entropy_bits {
+            problems.push("Too easy to guess, mix in more words, numbers or symbols".to_string());
+        }
+
+        if self.is_breached(password).await? {
+            problems.push(
+                "This password has appeared in a data breach, choose another one".to_string(),
+            );
+        }
+        Ok(problems)
+    }
+
+    async fn is_breached(&self, password: &str) -> anyhow::Result<bool> {
+        let hash = hex_upper(
+            digest::digest(&digest::SHA1_FOR_LEGACY_USE_ONLY, password.as_bytes()).as_ref(),
+        );
+        let (prefix, suffix) = hash.split_at(5);
+
+        match self.breached.as_ref() {
+            BreachedPasswords::Bundled(hashes) => Ok(hashes.contains(&hash)),
+            BreachedPasswords::RangeDir(dir) => {
+                let path = [prefix.to_string(), format!("{}.txt", prefix)]
+                    .into_iter()
+                    .map(|name| dir.join(name))
+                    .find(|path| path.is_file());
+                let Some(path) = path else {
+                    return Ok(false);
+                };
+                let contents = tokio::fs::read_to_string(&path).await?;
+                Ok(contents.lines().any(|line| {
+                    line.split(':')
+                        .next()
+                        .is_some_and(|candidate| candidate.trim().eq_ignore_ascii_case(suffix))
+                }))
+            }
+        }
+    }
+}
+
+/// Rough entropy estimate: the size of the character classes in use raised to
+/// the length, with runs of the same character counted once
+fn estimate_entropy_bits(password: &str) -> f64 {
+    let (mut lower, mut upper, mut digit, mut symbol, mut other) =
+        (false, false, false, false, false);
+    let mut length = 0usize;
+    let mut previous = None;
+
+    for c in password.chars() {
+        match c {
+            'a'..='z' => lower = true,
+            'A'..='Z' => upper = true,
+            '0'..='9' => digit = true,
+            c if c.is_ascii() => symbol = true,
+            _ => other = true,
+        }
+        if previous != Some(c) {
+            length += 1;
+        }
+        previous = Some(c);
+    }
+
+    let pool = [
+        (lower, 26),
+        (upper, 26),
+        (digit, 10),
+        (symbol, 33),
+        (other, 100),
+    ]
+    .into_iter()
+    .filter(|(used, _)| *used)
+    .map(|(_, size)| size)
+    .sum::<u32>();
+    if pool == 0 {
+        return 0.0;
+    }
+    length as f64 * f64::from(pool).log2()
+}
+
+fn hex_upper(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STRONG: &str = "correct-Horse-7-battery";
+
+    fn policy(breached_hashes_dir: Option<&PathBuf>) -> PasswordPolicy {
+        PasswordPolicy::from_settings(&PasswordPolicySettings {
+            min_length: 10,
+            min_entropy_bits: 40.0,
+            breached_hashes_dir: breached_hashes_dir.map(|dir| dir.display().to_string()),
+        })
+        .unwrap()
+    }
+
+    fn sha1(password: &str) -> String {
+        hex_upper(digest::digest(&digest::SHA1_FOR_LEGACY_USE_ONLY, password.as_bytes()).as_ref())
+    }
+
+    /// Range directory holding one file, removed again on drop
+    struct RangeDir(PathBuf);
+
+    impl RangeDir {
+        fn with_file(name: &str, contents: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("shorty-breached-{}", nanoid::nanoid!(8)));
+            std::fs::create_dir(&dir).unwrap();
+            std::fs::write(dir.join(name), contents).unwrap();
+            Self(dir)
+        }
+    }
+
+    impl Drop for RangeDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn entropy_grows_with_the_character_classes() {
+        let lower = estimate_entropy_bits("abcdefghij");
+        assert!((lower - 10.0 * 26f64.log2()).abs() < 1e-9);
+        let mixed = estimate_entropy_bits("abcdeFGH1!");
+        assert!((mixed - 10.0 * 95f64.log2()).abs() < 1e-9);
+        assert_eq!(estimate_entropy_bits(""), 0.0);
+    }
+
+    #[test]
+    fn entropy_counts_runs_once() {
+        assert_eq!(
+            estimate_entropy_bits("aaaaaaaaaa"),
+            estimate_entropy_bits("a")
+        );
+        assert_eq!(
+            estimate_entropy_bits("aabbcc"),
+            estimate_entropy_bits("abc")
+        );
+        // Only consecutive repeats collapse
+        assert_eq!(estimate_entropy_bits("abab"), 4.0 * 26f64.log2());
+    }
+
+    #[tokio::test]
+    async fn short_passwords_only_report_the_length() {
+        let problems = policy(None).check("aaaaaaa").await.unwrap();
+        assert_eq!(problems, vec!["Use at least 10 characters".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn long_but_guessable_passwords_report_the_entropy() {
+        let problems = policy(None).check("aaaaaaaaaaaaaaaa").await.unwrap();
+        assert_eq!(
+            problems,
+            vec!["Too easy to guess, mix in more words, numbers or symbols".to_string()]
+        );
+    }
+
+    #[tokio::test]
+    async fn strong_passwords_pass() {
+        assert!(policy(None).check(STRONG).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn bundled_hashes_are_breached() {
+        let problems = policy(None).check("password").await.unwrap();
+        assert!(problems.iter().any(|p| p.contains("data breach")));
+    }
+
+    #[tokio::test]
+    async fn range_files_match_the_suffix() {
+        let hash = sha1(STRONG);
+        let (prefix, suffix) = hash.split_at(5);
+        // Suffixes are compared without regard to case, counts are ignored
+        let contents = format!(
+            "0000000000000000000000000000000000A:3\n{}:12\n",
+            suffix.to_lowercase()
+        );
+
+        for name in [prefix.to_string(), format!("{}.txt", prefix)] {
+            let dir = RangeDir::with_file(&name, &contents);
+            assert!(
+                policy(Some(&dir.0)).is_breached(STRONG).await.unwrap(),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn range_files_ignore_other_suffixes_and_prefixes() {
+        let hash = sha1(STRONG);
+        let (prefix, _) = hash.split_at(5);
+        let dir = RangeDir::with_file(prefix, "0000000000000000000000000000000000A:3\n");
+        let policy = policy(Some(&dir.0));
+        assert!(!policy.is_breached(STRONG).await.unwrap());
+        // No range file for this prefix at all
+        assert!(!policy.is_breached("another-Password-42").await.unwrap());
+    }
+}
@@ -0,0 +1,59 @@
+use tracing::instrument;
+use uuid::Uuid;
+
+use crate::{
+    models::plan::{PlanModel, PlanUsage},
+    store::plan::PlanRepository,
+};
+
+#[derive(Clone, Debug)]
+pub struct PlanService {
+    repo: PlanRepository,
+}
+
+impl PlanService {
+    pub fn new(repo: PlanRepository) -> Self {
+        Self { repo }
+    }
+
+    pub async fn list_plans(&self) -> anyhow::Result<Vec<PlanModel>> {
+        self.repo.list().await
+    }
+
+    pub async fn plan_for(&self, user_id: Uuid) -> anyhow::Result<PlanModel> {
+        self.repo
+            .find_for_user(user_id)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("user {} has no plan", user_id))
+    }
+
+    #[instrument(name = "Service: Plan usage", skip(self))]
+    pub async fn usage(&self, user_id: Uuid) -> anyhow::Result<PlanUsage> {
+        let plan = self.plan_for(user_id).await?;
+        let active_links = self.repo.active_links(user_id).await?;
+        let redirects_this_month = self.repo.redirects_this_month(user_id).await?;
+        Ok(PlanUsage {
+            plan,
+            active_links,
+            redirects_this_month,
+        })
+    }
+
+    pub async fn active_links(&self, user_id: Uuid) -> anyhow::Result<i64> {
+        self.repo.active_links(user_id).await
+    }
+
+    /// Returns false when the user or the plan does not exist
+    pub async fn assign(&self, user_id: Uuid, plan_id: &str) -> anyhow::Result<bool> {
+        self.repo.assign(user_id, plan_id).await
+    }
+
+    /// Count a redirect of one of the user's links, false once this month's quota is used up
+    pub async fn record_redirect(&self, user_id: Uuid) -> anyhow::Result<bool> {
+        self.repo.record_redirect(user_id).await
+    }
+
+    pub async fn prune_analytics(&self) -> anyhow::Result<u64> {
+        self.repo.prune_click_events().await
+    }
+}
@@ -0,0 +1,96 @@
+use tracing::instrument;
+use uuid::Uuid;
+
+use crate::{
+    models::{
+        privacy::{DataExport, ErasureSummary},
+        url::{UrlCsvRow, UrlModel},
+    },
+    store::{
+        CacheRepository, UrlRepository, user::UserRepository, utm::UtmRepository,
+        workspace::WorkspaceRepository,
+    },
+};
+
+/// Self-service data export and erasure
+#[derive(Clone, Debug)]
+pub struct PrivacyService {
+    users: UserRepository,
+    urls: UrlRepository,
+    workspaces: WorkspaceRepository,
+    utm: UtmRepository,
+    cache: CacheRepository,
+}
+
+impl PrivacyService {
+    pub fn new(
+        users: UserRepository,
+        urls: UrlRepository,
+        workspaces: WorkspaceRepository,
+        utm: UtmRepository,
+        cache: CacheRepository,
+    ) -> Self {
+        Self {
+            users,
+            urls,
+            workspaces,
+            utm,
+            cache,
+        }
+    }
+
+    /// Collect the profile, workspace memberships, links (with their click
+    /// counts) and UTM presets stored for a user
+    #[instrument(name = "PrivacyService: Export user data", skip(self))]
+    pub async fn export(&self, user_id: Uuid) -> anyhow::Result<DataExport> {
+        let user = self
+            .users
+            .find_by_id(user_id)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("user {} not found", user_id))?;
+
+        Ok(DataExport {
+            exported_at: chrono::Utc::now(),
+            profile: user.into(),
+            workspaces: self.workspaces.list_for_user(user_id).await?,
+            links: self.urls.list_by_creator(user_id).await?,
+            utm_presets: self.utm.list(user_id).await?,
+        })
+    }
+
+    /// Erase the user and their links, then drop everything cached for them.
+    /// Callers must confirm the removal with `AuthService` first.
+    #[instrument(name = "PrivacyService: Erase user data", skip(self))]
+    pub async fn erase(&self, user_id: Uuid) -> anyhow::Result<ErasureSummary> {
+        let summary = self.users.erase_account(user_id).await?;
+
+        for short_code in &summary.deleted_short_codes {
+            if let Err(e) = self.cache.delete(short_code).await {
+                tracing::warn!(%short_code, "Failed to evict erased link from cache: {:?}", e);
+            }
+        }
+        if let Err(e) = self.cache.delete_user_urls(user_id).await {
+            tracing::warn!("Failed to evict cached link list: {:?}", e);
+        }
+
+        tracing::info!(
+            links_deleted = summary.links_deleted,
+            links_anonymized = summary.links_anonymized,
+            "User data erased"
+        );
+        Ok(summary)
+    }
+}
+
+/// Render links as CSV, one row per link with its click count
+pub fn links_csv(links: &[UrlModel]) -> anyhow::Result<Vec<u8>> {
+    // The header is written by hand so an empty export still has one
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(Vec::new());
+    writer.write_record(UrlCsvRow::HEADER)?;
+    for link in links {
+        writer.serialize(UrlCsvRow::from(link))?;
+    }
+    Ok(writer.into_inner()?)
+}
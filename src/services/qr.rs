@@ -0,0 +1,223 @@
+use std::{fmt::Write, str::FromStr};
+
+use qrcode::{Color, EcLevel, QrCode};
+use serde::Deserialize;
+
+/// Smallest and largest side of a rendered code, in pixels
+pub const MIN_QR_SIZE: u32 = 64;
+pub const MAX_QR_SIZE: u32 = 2048;
+/// Widest blank border, in modules. Scanners want at least 4.
+pub const MAX_QR_MARGIN: u32 = 16;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QrFormat {
+    #[default]
+    Png,
+    Svg,
+}
+
+impl QrFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::Png => "image/png",
+            Self::Svg => "image/svg+xml",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Png => "png",
+            Self::Svg => "svg",
+        }
+    }
+}
+
+/// Share of the code that can be damaged and still scan: 7%, 15%, 25% or 30%
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum QrErrorCorrection {
+    #[serde(alias = "l")]
+    L,
+    #[default]
+    #[serde(alias = "m")]
+    M,
+    #[serde(alias = "q")]
+    Q,
+    #[serde(alias = "h")]
+    H,
+}
+
+impl From<QrErrorCorrection> for EcLevel {
+    fn from(level: QrErrorCorrection) -> Self {
+        match level {
+            QrErrorCorrection::L => EcLevel::L,
+            QrErrorCorrection::M => EcLevel::M,
+            QrErrorCorrection::Q => EcLevel::Q,
+            QrErrorCorrection::H => EcLevel::H,
+        }
+    }
+}
+
+/// A color written `rrggbb` or `rrggbbaa`, the `#` is optional
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rgba(pub [u8; 4]);
+
+impl Rgba {
+    pub const BLACK: Self = Self([0, 0, 0, 255]);
+    pub const WHITE: Self = Self([255, 255, 255, 255]);
+
+    fn hex(&self) -> String {
+        let [r, g, b, a] = self.0;
+        if a == 255 {
+            format!("#{:02x}{:02x}{:02x}", r, g, b)
+        } else {
+            format!("#{:02x}{:02x}{:02x}{:02x}", r, g, b, a)
+        }
+    }
+}
+
+impl FromStr for Rgba {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = s.trim().trim_start_matches('#');
+        let invalid =
+            || "Use a hex color such as 1a2b3c, or 1a2b3c80 with transparency".to_string();
+        if !matches!(hex.len(), 6 | 8) || !hex.is_ascii() {
+            return Err(invalid());
+        }
+        let mut rgba = [255u8; 4];
+        for (i, channel) in rgba.iter_mut().enumerate().take(hex.len() / 2) {
+            *channel = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).map_err(|_| invalid())?;
+        }
+        Ok(Self(rgba))
+    }
+}
+
+/// How a code is drawn
+#[derive(Debug, Clone)]
+pub struct QrStyle {
+    pub format: QrFormat,
+    /// Side of the image in pixels. PNGs round it down to a whole number of
+    /// pixels per module.
+    pub size: u32,
+    /// Blank border in modules
+    pub margin: u32,
+    pub error_correction: QrErrorCorrection,
+    pub dark: Rgba,
+    pub light: Rgba,
+}
+
+impl Default for QrStyle {
+    fn default() -> Self {
+        Self {
+            format: QrFormat::default(),
+            size: 256,
+            margin: 4,
+            error_correction: QrErrorCorrection::default(),
+            dark: Rgba::BLACK,
+            light: Rgba::WHITE,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct QrService {
+    public_url: String,
+}
+
+impl QrService {
+    pub fn new(public_url: String) -> Self {
+        Self { public_url }
+    }
+
+    /// What the code of a link encodes: its short URL, marked so scans can be
+    /// told apart from other clicks
+    pub fn link_for(&self, short_code: &str) -> String {
+        format!("{}/url/{}?src=qr", self.public_url, short_code)
+    }
+
+    pub fn render(&self, content: &str, style: &QrStyle) -> anyhow::Result<Vec<u8>> {
+        let code = QrCode::with_error_correction_level(content, style.error_correction.into())?;
+        let modules = Modules {
+            width: code.width(),
+            dark: code
+                .to_colors()
+                .into_iter()
+                .map(|c| c == Color::Dark)
+                .collect(),
+            margin: style.margin as usize,
+        };
+        match style.format {
+            QrFormat::Png => png(&modules, style),
+            QrFormat::Svg => Ok(svg(&modules, style).into_bytes()),
+        }
+    }
+}
+
+/// The modules of a code with the border around them
+struct Modules {
+    width: usize,
+    /// Row by row, `width` by `width`
+    dark: Vec<bool>,
+    margin: usize,
+}
+
+impl Modules {
+    /// Modules on a side, border included
+    fn side(&self) -> usize {
+        self.width + 2 * self.margin
+    }
+
+    fn is_dark(&self, x: usize, y: usize) -> bool {
+        let (Some(x), Some(y)) = (x.checked_sub(self.margin), y.checked_sub(self.margin)) else {
+            return false;
+        };
+        x < self.width && y < self.width && self.dark[y * self.width + x]
+    }
+}
+
+fn svg(modules: &Modules, style: &QrStyle) -> String {
+    let side = modules.side();
+    let mut path = String::new();
+    for y in 0..side {
+        for x in (0..side).filter(|&x| modules.is_dark(x, y)) {
+            let _ = write!(path, "M{} {}h1v1h-1z", x, y);
+        }
+    }
+    format!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{size}" height="{size}" viewBox="0 0 {side} {side}" shape-rendering="crispEdges"><rect width="{side}" height="{side}" fill="{light}"/><path fill="{dark}" d="{path}"/></svg>"#,
+        size = style.size,
+        side = side,
+        light = style.light.hex(),
+        dark = style.dark.hex(),
+        path = path,
+    )
+}
+
+fn png(modules: &Modules, style: &QrStyle) -> anyhow::Result<Vec<u8>> {
+    let side = modules.side();
+    let scale = (style.size as usize / side).max(1);
+    let pixels = side * scale;
+
+    let mut data = Vec::with_capacity(pixels * pixels * 4);
+    for y in 0..pixels {
+        for x in 0..pixels {
+            let color = if modules.is_dark(x / scale, y / scale) {
+                style.dark
+            } else {
+                style.light
+            };
+            data.extend_from_slice(&color.0);
+        }
+    }
+
+    let mut image = Vec::new();
+    let mut encoder = png::Encoder::new(&mut image, pixels as u32, pixels as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&data)?;
+    writer.finish()?;
+    Ok(image)
+}
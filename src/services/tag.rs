@@ -0,0 +1,231 @@
+use tracing::instrument;
+use uuid::Uuid;
+
+use crate::{
+    errors::{AuthError, FieldErrors},
+    models::{
+        tag::{FolderModel, TagModel},
+        workspace::WorkspaceMembership,
+    },
+    store::tag::TagRepository,
+};
+
+/// Longest tag name accepted
+const MAX_TAG_LEN: usize = 32;
+/// Most tags a single link can carry
+const MAX_TAGS_PER_LINK: usize = 10;
+/// Longest folder name accepted
+const MAX_FOLDER_LEN: usize = 64;
+
+#[derive(Clone, Debug)]
+pub struct TagService {
+    repo: TagRepository,
+}
+
+fn internal(e: anyhow::Error) -> AuthError {
+    tracing::error!("Tag storage error: {:?}", e);
+    AuthError::Internal
+}
+
+fn field_error(field: &'static str, message: &str) -> AuthError {
+    let mut errors = FieldErrors::new();
+    errors.insert(field, vec![message.to_string()]);
+    AuthError::Validation(errors)
+}
+
+/// Turn a rename that collides with an existing name into a field error
+fn name_taken(e: anyhow::Error, message: &str) -> AuthError {
+    let duplicate = e
+        .downcast_ref::<sqlx::Error>()
+        .and_then(|e| e.as_database_error())
+        .is_some_and(|e| e.is_unique_violation());
+    if duplicate {
+        return field_error("name", message);
+    }
+    internal(e)
+}
+
+impl TagService {
+    pub fn new(repo: TagRepository) -> Self {
+        Self { repo }
+    }
+
+    pub async fn list_tags(&self, workspace_id: Uuid) -> anyhow::Result<Vec<TagModel>> {
+        self.repo.list_tags(workspace_id).await
+    }
+
+    pub async fn list_folders(&self, workspace_id: Uuid) -> anyhow::Result<Vec<FolderModel>> {
+        self.repo.list_folders(workspace_id).await
+    }
+
+    #[instrument(name = "TagService: Create folder", skip(self, workspace))]
+    pub async fn create_folder(
+        &self,
+        workspace: &WorkspaceMembership,
+        name: &str,
+    ) -> Result<Uuid, AuthError> {
+        ensure_can_edit(workspace)?;
+        let name = folder_name(name)?;
+        self.repo
+            .create_folder(workspace.id, &name)
+            .await
+            .map_err(|e| name_taken(e, "A folder with this name already exists"))
+    }
+
+    #[instrument(name = "TagService: Rename folder", skip(self, workspace))]
+    pub async fn rename_folder(
+        &self,
+        workspace: &WorkspaceMembership,
+        id: Uuid,
+        name: &str,
+    ) -> Result<(), AuthError> {
+        ensure_can_edit(workspace)?;
+        let name = folder_name(name)?;
+        let renamed = self
+            .repo
+            .rename_folder(workspace.id, id, &name)
+            .await
+            .map_err(|e| name_taken(e, "A folder with this name already exists"))?;
+        found(renamed)
+    }
+
+    pub async fn delete_folder(
+        &self,
+        workspace: &WorkspaceMembership,
+        id: Uuid,
+    ) -> Result<(), AuthError> {
+        ensure_can_edit(workspace)?;
+        found(
+            self.repo
+                .delete_folder(workspace.id, id)
+                .await
+                .map_err(internal)?,
+        )
+    }
+
+    #[instrument(name = "TagService: Rename tag", skip(self, workspace))]
+    pub async fn rename_tag(
+        &self,
+        workspace: &WorkspaceMembership,
+        id: Uuid,
+        name: &str,
+    ) -> Result<(), AuthError> {
+        ensure_can_edit(workspace)?;
+        let name = tag_name(name).map_err(|message| field_error("name", &message))?;
+        let renamed = self
+            .repo
+            .rename_tag(workspace.id, id, &name)
+            .await
+            .map_err(|e| name_taken(e, "This tag already exists, merge the two instead"))?;
+        found(renamed)
+    }
+
+    #[instrument(name = "TagService: Merge tags", skip(self, workspace))]
+    pub async fn merge_tags(
+        &self,
+        workspace: &WorkspaceMembership,
+        source: Uuid,
+        target: Uuid,
+    ) -> Result<(), AuthError> {
+        ensure_can_edit(workspace)?;
+        if source == target {
+            return Err(field_error("into", "Pick a different tag to merge into"));
+        }
+        found(
+            self.repo
+                .merge_tags(workspace.id, source, target)
+                .await
+                .map_err(internal)?,
+        )
+    }
+
+    pub async fn delete_tag(
+        &self,
+        workspace: &WorkspaceMembership,
+        id: Uuid,
+    ) -> Result<(), AuthError> {
+        ensure_can_edit(workspace)?;
+        found(
+            self.repo
+                .delete_tag(workspace.id, id)
+                .await
+                .map_err(internal)?,
+        )
+    }
+
+    /// Set the folder and tags of a link in the workspace
+    #[instrument(name = "TagService: Label link", skip(self, workspace))]
+    pub async fn label_link(
+        &self,
+        workspace: &WorkspaceMembership,
+        short_code: &str,
+        folder_id: Option<Uuid>,
+        tags: &[String],
+    ) -> Result<(), AuthError> {
+        ensure_can_edit(workspace)?;
+        found(
+            self.repo
+                .label_link(workspace.id, short_code, folder_id, tags)
+                .await
+                .map_err(internal)?,
+        )
+    }
+}
+
+fn ensure_can_edit(workspace: &WorkspaceMembership) -> Result<(), AuthError> {
+    if workspace.role.can_edit() {
+        Ok(())
+    } else {
+        Err(AuthError::Forbidden)
+    }
+}
+
+fn found(found: bool) -> Result<(), AuthError> {
+    if found {
+        Ok(())
+    } else {
+        Err(AuthError::NotFound)
+    }
+}
+
+fn folder_name(name: &str) -> Result<String, AuthError> {
+    let name = name.split_whitespace().collect::<Vec<_>>().join(" ");
+    if name.is_empty() || name.chars().count() > MAX_FOLDER_LEN {
+        return Err(field_error(
+            "name",
+            &format!("Use between 1 and {} characters", MAX_FOLDER_LEN),
+        ));
+    }
+    Ok(name)
+}
+
+/// Tags are lowercase, inner whitespace becomes a dash
+fn tag_name(name: &str) -> Result<String, String> {
+    let name = name
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join("-")
+        .to_lowercase();
+    if name.is_empty() || name.chars().count() > MAX_TAG_LEN {
+        return Err(format!(
+            "Keep tags between 1 and {} characters",
+            MAX_TAG_LEN
+        ));
+    }
+    Ok(name)
+}
+
+/// Parse a comma separated list of tags, dropping blanks and duplicates
+pub fn parse_tags(input: &str) -> Result<Vec<String>, String> {
+    let mut tags: Vec<String> = Vec::new();
+    for raw in input.split(',').filter(|raw| !raw.trim().is_empty()) {
+        let tag = tag_name(raw)?;
+        if !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+    if tags.len() > MAX_TAGS_PER_LINK {
+        return Err(format!("Use at most {} tags", MAX_TAGS_PER_LINK));
+    }
+    Ok(tags)
+}
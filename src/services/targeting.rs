@@ -0,0 +1,471 @@
+use chrono::NaiveTime;
+use reqwest::Url;
+
+use crate::{
+    errors::FieldErrors,
+    models::targeting::{DeviceClass, TargetOs, TargetingRule},
+    services::url::is_web_url,
+};
+
+/// Most targeting rules a single link can have
+pub const MAX_RULES_PER_LINK: usize = 20;
+/// Longest language tag accepted in a rule
+const MAX_LANGUAGE_LEN: usize = 35;
+
+/// What the rules of a link are matched against, read once per visit
+#[derive(Debug)]
+pub struct Visitor {
+    pub os: Option<TargetOs>,
+    pub device: DeviceClass,
+    /// Lowercase tag of the language the visitor prefers most
+    pub language: Option<String>,
+    /// UTC time of the visit
+    pub time: NaiveTime,
+    /// Lowercase host of the referring page
+    pub referrer_host: Option<String>,
+}
+
+impl Visitor {
+    pub fn new(
+        user_agent: Option<&str>,
+        accept_language: Option<&str>,
+        referrer: Option<&str>,
+        time: NaiveTime,
+    ) -> Self {
+        let user_agent = user_agent.unwrap_or_default();
+        Self {
+            os: detect_os(user_agent),
+            device: detect_device(user_agent),
+            language: accept_language.and_then(preferred_language),
+            time,
+            referrer_host: referrer
+                .and_then(|referrer| Url::parse(referrer).ok())
+                .and_then(|url| url.host_str().map(str::to_lowercase)),
+        }
+    }
+}
+
+/// The first rule whose conditions the visitor all meets
+pub fn choose<'a>(rules: &'a [TargetingRule], visitor: &Visitor) -> Option<&'a TargetingRule> {
+    rules.iter().find(|rule| matches(rule, visitor))
+}
+
+fn matches(rule: &TargetingRule, visitor: &Visitor) -> bool {
+    if rule.os.is_some() && rule.os != visitor.os {
+        return false;
+    }
+    if rule.device.is_some_and(|device| device != visitor.device) {
+        return false;
+    }
+    if let Some(language) = &rule.language {
+        // `pt` covers `pt-br`, `pt-br` does not cover `pt`
+        let matched = visitor.language.as_deref().is_some_and(|preferred| {
+            preferred == language
+                || preferred
+                    .strip_prefix(language.as_str())
+                    .is_some_and(|rest| rest.starts_with('-'))
+        });
+        if !matched {
+            return false;
+        }
+    }
+    if rule.active_from.is_some() || rule.active_until.is_some() {
+        let from = rule.active_from.unwrap_or(NaiveTime::MIN);
+        let until = rule.active_until;
+        let within = match until {
+            Some(until) if from < until => visitor.time >= from && visitor.time < until,
+            Some(until) => visitor.time >= from || visitor.time < until,
+            None => visitor.time >= from,
+        };
+        if !within {
+            return false;
+        }
+    }
+    if let Some(host) = &rule.referrer_host {
+        let matched = visitor.referrer_host.as_deref().is_some_and(|referrer| {
+            referrer == host
+                || referrer
+                    .strip_suffix(host.as_str())
+                    .is_some_and(|rest| rest.ends_with('.'))
+        });
+        if !matched {
+            return false;
+        }
+    }
+    true
+}
+
+/// Checked in this order since Android and iOS agents also name Linux and
+/// Mac OS X. iPads on iPadOS 13 and later ask for desktop sites by default and
+/// send the user agent of Safari on a Mac, they count as macOS then.
+fn detect_os(user_agent: &str) -> Option<TargetOs> {
+    if user_agent.contains("Android") {
+        Some(TargetOs::Android)
+    } else if ["iPhone", "iPad", "iPod"]
+        .iter()
+        .any(|device| user_agent.contains(device))
+    {
+        Some(TargetOs::Ios)
+    } else if user_agent.contains("Windows") {
+        Some(TargetOs::Windows)
+    } else if user_agent.contains("Macintosh") || user_agent.contains("Mac OS X") {
+        Some(TargetOs::Macos)
+    } else if user_agent.contains("Linux") || user_agent.contains("X11") {
+        Some(TargetOs::Linux)
+    } else {
+        None
+    }
+}
+
+/// Android tablets leave `Mobile` out of their user agent. iPads sending a
+/// Mac user agent count as desktops, see `detect_os`.
+fn detect_device(user_agent: &str) -> DeviceClass {
+    if user_agent.contains("iPad")
+        || user_agent.contains("Tablet")
+        || (user_agent.contains("Android") && !user_agent.contains("Mobile"))
+    {
+        DeviceClass::Tablet
+    } else if user_agent.contains("Mobi")
+        || user_agent.contains("iPhone")
+        || user_agent.contains("iPod")
+    {
+        DeviceClass::Mobile
+    } else {
+        DeviceClass::Desktop
+    }
+}
+
+/// The language an `Accept-Language` header ranks highest, the first one
+/// listed among equals. Wildcards, refused languages and weights outside 0 to
+/// 1 are skipped.
+fn preferred_language(accept_language: &str) -> Option<String> {
+    let mut best: Option<(&str, f32)> = None;
+    for entry in accept_language.split(',') {
+        let mut parts = entry.split(';').map(str::trim);
+        let tag = parts.next().unwrap_or_default();
+        let quality = parts
+            .find_map(|param| param.strip_prefix("q="))
+            .map_or(Some(1.0), |q| q.parse::<f32>().ok())
+            .filter(|q| (0.0..=1.0).contains(q))
+            .unwrap_or(0.0);
+        if tag.is_empty() || tag == "*" || quality <= 0.0 {
+            continue;
+        }
+        if best.is_none_or(|(_, best_quality)| quality > best_quality) {
+            best = Some((tag, quality));
+        }
+    }
+    best.map(|(tag, _)| tag.to_lowercase())
+}
+
+/// Check a rule as entered and bring it to the form it is stored in
+pub fn normalize_rule(mut rule: TargetingRule) -> Result<TargetingRule, FieldErrors> {
+    let mut errors = FieldErrors::new();
+    rule.destination = rule.destination.trim().to_string();
+    if !is_web_url(&rule.destination) {
+        errors.insert(
+            "destination",
+            vec!["Enter a full http:// or https:// address".to_string()],
+        );
+    }
+    rule.language = rule
+        .language
+        .map(|language| language.trim().to_lowercase().replace('_', "-"))
+        .filter(|language| !language.is_empty());
+    if let Some(language) = &rule.language {
+        let valid = language.len() <= MAX_LANGUAGE_LEN
+            && language.split('-').all(|part| {
+                (1..=8).contains(&part.len()) && part.chars().all(|c| c.is_ascii_alphanumeric())
+            });
+        if !valid {
+            errors.insert(
+                "language",
+                vec!["Enter a language tag such as de or pt-BR".to_string()],
+            );
+        }
+    }
+    rule.referrer_host = rule
+        .referrer_host
+        .map(|host| host.trim().trim_end_matches('.').to_lowercase())
+        .filter(|host| !host.is_empty());
+    if let Some(host) = &rule.referrer_host
+        && Url::parse(&format!("http://{}/", host))
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_string))
+            .as_deref()
+            != Some(host.as_str())
+    {
+        errors.insert(
+            "referrer_host",
+            vec!["Enter a host name such as news.example.com".to_string()],
+        );
+    }
+    if rule.active_from.is_some() && rule.active_from == rule.active_until {
+        errors.insert(
+            "active_until",
+            vec!["End the time window at a different time".to_string()],
+        );
+    }
+    let unconditional = rule.os.is_none()
+        && rule.device.is_none()
+        && rule.language.is_none()
+        && rule.active_from.is_none()
+        && rule.active_until.is_none()
+        && rule.referrer_host.is_none();
+    if unconditional && errors.is_empty() {
+        errors.insert(
+            "destination",
+            vec!["Set at least one condition, the link's own url is the default".to_string()],
+        );
+    }
+    if errors.is_empty() {
+        Ok(rule)
+    } else {
+        Err(errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IPHONE: &str = "Mozilla/5.0 (iPhone; CPU iPhone OS 17_4 like Mac OS X) AppleWebKit/605.1.15 \
+        (KHTML, like Gecko) Version/17.4 Mobile/15E148 Safari/604.1";
+    const OLD_IPAD: &str = "Mozilla/5.0 (iPad; CPU OS 12_5 like Mac OS X) AppleWebKit/605.1.15 \
+        (KHTML, like Gecko) Version/12.1.2 Mobile/15E148 Safari/604.1";
+    /// Safari on iPadOS 13 and later, the same as Safari on a Mac
+    const IPAD: &str = "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/605.1.15 \
+        (KHTML, like Gecko) Version/17.4 Safari/605.1.15";
+    const ANDROID_PHONE: &str = "Mozilla/5.0 (Linux; Android 14; Pixel 8) AppleWebKit/537.36 \
+        (KHTML, like Gecko) Chrome/124.0 Mobile Safari/537.36";
+    const ANDROID_TABLET: &str = "Mozilla/5.0 (Linux; Android 14; SM-X710) AppleWebKit/537.36 \
+        (KHTML, like Gecko) Chrome/124.0 Safari/537.36";
+    const WINDOWS: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 \
+        (KHTML, like Gecko) Chrome/124.0 Safari/537.36";
+    const LINUX: &str = "Mozilla/5.0 (X11; Linux x86_64; rv:125.0) Gecko/20100101 Firefox/125.0";
+
+    fn rule() -> TargetingRule {
+        TargetingRule {
+            id: Default::default(),
+            url_id: Default::default(),
+            destination: "https://example.com/".to_string(),
+            os: None,
+            device: None,
+            language: None,
+            active_from: None,
+            active_until: None,
+            referrer_host: None,
+        }
+    }
+
+    fn visitor() -> Visitor {
+        Visitor::new(None, None, None, NaiveTime::MIN)
+    }
+
+    fn time(time: &str) -> NaiveTime {
+        NaiveTime::parse_from_str(time, "%H:%M").unwrap()
+    }
+
+    #[test]
+    fn detects_os_and_device() {
+        let cases = [
+            (IPHONE, Some(TargetOs::Ios), DeviceClass::Mobile),
+            (OLD_IPAD, Some(TargetOs::Ios), DeviceClass::Tablet),
+            (ANDROID_PHONE, Some(TargetOs::Android), DeviceClass::Mobile),
+            (ANDROID_TABLET, Some(TargetOs::Android), DeviceClass::Tablet),
+            (WINDOWS, Some(TargetOs::Windows), DeviceClass::Desktop),
+            (LINUX, Some(TargetOs::Linux), DeviceClass::Desktop),
+            ("curl/8.5.0", None, DeviceClass::Desktop),
+        ];
+        for (user_agent, os, device) in cases {
+            assert_eq!(detect_os(user_agent), os, "{}", user_agent);
+            assert_eq!(detect_device(user_agent), device, "{}", user_agent);
+        }
+    }
+
+    #[test]
+    fn recent_ipads_pass_for_macs() {
+        // Known limitation, nothing in the user agent tells them apart
+        assert_eq!(detect_os(IPAD), Some(TargetOs::Macos));
+        assert_eq!(detect_device(IPAD), DeviceClass::Desktop);
+    }
+
+    #[test]
+    fn prefers_the_highest_weight() {
+        assert_eq!(
+            preferred_language("en-US,en;q=0.9,de;q=0.8").as_deref(),
+            Some("en-us")
+        );
+        assert_eq!(
+            preferred_language("fr;q=0.5, de;q=0.9, en").as_deref(),
+            Some("en")
+        );
+        assert_eq!(
+            preferred_language("fr;q=0.5, DE-at;q=0.9").as_deref(),
+            Some("de-at")
+        );
+    }
+
+    #[test]
+    fn breaks_weight_ties_by_order() {
+        assert_eq!(
+            preferred_language("fr;q=0.8, de;q=0.8").as_deref(),
+            Some("fr")
+        );
+        assert_eq!(preferred_language("de, fr").as_deref(), Some("de"));
+    }
+
+    #[test]
+    fn skips_refused_wildcard_and_invalid_weights() {
+        assert_eq!(
+            preferred_language("de;q=0, fr;q=0.1").as_deref(),
+            Some("fr")
+        );
+        assert_eq!(preferred_language("*, fr;q=0.1").as_deref(), Some("fr"));
+        for invalid in ["abc", "NaN", "2", "-1", ""] {
+            let header = format!("de;q={}, fr;q=0.1", invalid);
+            assert_eq!(
+                preferred_language(&header).as_deref(),
+                Some("fr"),
+                "{}",
+                header
+            );
+        }
+        assert_eq!(preferred_language("*;q=0.5, de;q=0"), None);
+        assert_eq!(preferred_language(""), None);
+    }
+
+    #[test]
+    fn language_covers_its_regional_variants() {
+        let language = TargetingRule {
+            language: Some("pt".to_string()),
+            ..rule()
+        };
+        for (accept_language, matched) in
+            [("pt-BR", true), ("pt", true), ("pl", false), ("ptx", false)]
+        {
+            let visitor = Visitor::new(None, Some(accept_language), None, NaiveTime::MIN);
+            assert_eq!(matches(&language, &visitor), matched, "{}", accept_language);
+        }
+        let regional = TargetingRule {
+            language: Some("pt-br".to_string()),
+            ..rule()
+        };
+        let visitor = Visitor::new(None, Some("pt"), None, NaiveTime::MIN);
+        assert!(!matches(&regional, &visitor));
+    }
+
+    #[test]
+    fn time_window_within_a_day() {
+        let rule = TargetingRule {
+            active_from: Some(time("09:00")),
+            active_until: Some(time("17:00")),
+            ..rule()
+        };
+        for (at, within) in [
+            ("08:59", false),
+            ("09:00", true),
+            ("16:59", true),
+            ("17:00", false),
+        ] {
+            let visitor = Visitor {
+                time: time(at),
+                ..visitor()
+            };
+            assert_eq!(matches(&rule, &visitor), within, "{}", at);
+        }
+    }
+
+    #[test]
+    fn time_window_past_midnight() {
+        let rule = TargetingRule {
+            active_from: Some(time("22:00")),
+            active_until: Some(time("06:00")),
+            ..rule()
+        };
+        let cases = [
+            ("21:59", false),
+            ("22:00", true),
+            ("23:59", true),
+            ("00:00", true),
+            ("05:59", true),
+            ("06:00", false),
+            ("12:00", false),
+        ];
+        for (at, within) in cases {
+            let visitor = Visitor {
+                time: time(at),
+                ..visitor()
+            };
+            assert_eq!(matches(&rule, &visitor), within, "{}", at);
+        }
+    }
+
+    #[test]
+    fn time_window_open_on_one_side() {
+        let from = TargetingRule {
+            active_from: Some(time("20:00")),
+            ..rule()
+        };
+        let until = TargetingRule {
+            active_until: Some(time("04:00")),
+            ..rule()
+        };
+        for (at, after_from, before_until) in [
+            ("00:00", false, true),
+            ("03:59", false, true),
+            ("20:00", true, false),
+        ] {
+            let visitor = Visitor {
+                time: time(at),
+                ..visitor()
+            };
+            assert_eq!(matches(&from, &visitor), after_from, "{}", at);
+            assert_eq!(matches(&until, &visitor), before_until, "{}", at);
+        }
+    }
+
+    #[test]
+    fn referrer_covers_subdomains_only() {
+        let rule = TargetingRule {
+            referrer_host: Some("example.com".to_string()),
+            ..rule()
+        };
+        let cases = [
+            ("https://example.com/post", true),
+            ("https://news.EXAMPLE.com/a?b=c", true),
+            ("http://a.b.example.com:8080/", true),
+            ("https://badexample.com/", false),
+            ("https://example.com.evil.org/", false),
+            ("not a url", false),
+        ];
+        for (referrer, matched) in cases {
+            let visitor = Visitor::new(None, None, Some(referrer), NaiveTime::MIN);
+            assert_eq!(matches(&rule, &visitor), matched, "{}", referrer);
+        }
+        assert!(!matches(&rule, &visitor()));
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let rules = [
+            TargetingRule {
+                destination: "https://apps.apple.com/".to_string(),
+                os: Some(TargetOs::Ios),
+                ..rule()
+            },
+            TargetingRule {
+                destination: "https://m.example.com/".to_string(),
+                device: Some(DeviceClass::Mobile),
+                ..rule()
+            },
+        ];
+        let destination = |user_agent| {
+            let visitor = Visitor::new(Some(user_agent), None, None, NaiveTime::MIN);
+            choose(&rules, &visitor).map(|rule| rule.destination.as_str())
+        };
+        assert_eq!(destination(IPHONE), Some("https://apps.apple.com/"));
+        assert_eq!(destination(ANDROID_PHONE), Some("https://m.example.com/"));
+        assert_eq!(destination(WINDOWS), None);
+    }
+}
@@ -1,79 +1,1056 @@
+use std::collections::HashMap;
+
 use crate::{
-    models::url::UrlModel,
-    store::{CacheRepository, UrlRepository},
+    errors::{AuthError, FieldErrors},
+    models::{
+        import::{ImportMode, ImportReport, ImportResult, ImportStatus},
+        plan::PlanModel,
+        targeting::{RuleEdit, TargetingRule},
+        url::{
+            ClickSource, DailyClicks, ExportedLink, LimitedClick, LinkCursor, LinkFilter, LinkPage,
+            LinkTarget, NewUrl, QueryPassthrough, UrlModel,
+        },
+        utm::UtmParams,
+        workspace::WorkspaceMembership,
+    },
+    services::{
+        auth::{hash_password, verify_hash},
+        destination::{Forwarded, forward},
+        plan::PlanService,
+        targeting::{MAX_RULES_PER_LINK, Visitor, choose, normalize_rule},
+    },
+    store::{CacheRepository, UrlRepository, plan::LinkLimitReached},
 };
+use chrono::{DateTime, Duration, Utc};
+use futures_util::{Stream, stream};
 use nanoid::nanoid;
 use tracing::instrument;
 use uuid::Uuid;
 
+/// Longest custom alias accepted
+const MAX_ALIAS_LEN: usize = 32;
+/// Shortest link password accepted
+pub const MIN_LINK_PASSWORD_LEN: usize = 4;
+/// Longest link password accepted
+pub const MAX_LINK_PASSWORD_LEN: usize = 128;
+/// Links an export reads per database round trip
+const EXPORT_BATCH: i64 = 500;
+
 #[derive(Clone, Debug)]
 pub struct UrlService {
     repo: UrlRepository,
     cache: CacheRepository,
+    plans: PlanService,
+}
+
+/// Optional settings for a new link
+#[derive(Default)]
+pub struct LinkOptions {
+    /// Short code chosen by the user instead of a generated one
+    pub alias: Option<String>,
+    /// When the link goes live, right away when not given
+    pub starts_at: Option<DateTime<Utc>>,
+    /// Days until the link stops redirecting, counted from its start, the
+    /// plan's maximum when not given
+    pub expires_in_days: Option<u32>,
+    /// Where visitors go while the link is not live
+    pub fallback_url: Option<String>,
+    pub query_passthrough: QueryPassthrough,
+    /// Add whatever follows the short code in the path to the destination
+    pub path_passthrough: bool,
+    /// Added to the destination on redirect, kept apart from it
+    pub utm: UtmParams,
+    pub folder_id: Option<Uuid>,
+    /// Lowercase tag names, see [`crate::services::tag::parse_tags`]
+    pub tags: Vec<String>,
+    /// Visitors have to enter it before being redirected
+    pub password: Option<String>,
+    /// Clicks after which the link stops redirecting
+    pub max_clicks: Option<i32>,
+}
+
+impl std::fmt::Debug for LinkOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LinkOptions")
+            .field("alias", &self.alias)
+            .field("starts_at", &self.starts_at)
+            .field("expires_in_days", &self.expires_in_days)
+            .field("fallback_url", &self.fallback_url)
+            .field("query_passthrough", &self.query_passthrough)
+            .field("path_passthrough", &self.path_passthrough)
+            .field("utm", &self.utm)
+            .field("folder_id", &self.folder_id)
+            .field("tags", &self.tags)
+            .field("password", &self.password.as_ref().map(|_| "<redacted>"))
+            .field("max_clicks", &self.max_clicks)
+            .finish()
+    }
+}
+
+/// One row of a bulk import, checked for shape but not yet against the plan
+#[derive(Debug)]
+pub struct ImportRow {
+    /// Line of the uploaded file, for the report
+    pub line: usize,
+    pub long_url: String,
+    pub site_name: String,
+    pub options: Result<LinkOptions, FieldErrors>,
+}
+
+/// What the redirect path knows about the visitor
+#[derive(Debug, Default)]
+pub struct Visit {
+    pub source: ClickSource,
+    /// The visitor entered the link's password recently
+    pub unlocked: bool,
+    /// Raw query string of the request
+    pub query: Option<String>,
+    /// Path after the short code, for links that pass it through
+    pub path: Option<String>,
+    pub user_agent: Option<String>,
+    pub accept_language: Option<String>,
+    /// The `Referer` header
+    pub referrer: Option<String>,
+}
+
+/// Outcome of looking up a short code on the redirect path
+#[derive(Debug)]
+pub enum Resolution {
+    Found(String),
+    /// The link cannot be followed and has a fallback, its own or its owner's
+    Fallback(String),
+    NotFound,
+    /// The link is not live yet, it starts at the given time
+    Scheduled(DateTime<Utc>),
+    Expired,
+    /// The link has used up its clicks
+    Exhausted,
+    /// The link has a password the visitor has not entered yet
+    Locked,
+    /// The owner's plan has no redirects left this month
+    QuotaExceeded,
+    /// A limited link could not be counted, so it is not followed
+    Unavailable,
 }
 
 impl UrlService {
-    pub fn new(repo: UrlRepository, cache: CacheRepository) -> Self {
-        Self { repo, cache }
+    pub fn new(repo: UrlRepository, cache: CacheRepository, plans: PlanService) -> Self {
+        Self { repo, cache, plans }
     }
 
+    /// Create a link within the limits of the creator's plan
+    #[instrument(name = "Service: Shorten url", skip(self, options))]
     pub async fn shorten(
         &self,
         long_url: &str,
         site_name: &str,
         user_id: uuid::Uuid,
-    ) -> anyhow::Result<String> {
-        let short_code = nanoid!(8);
+        workspace_id: uuid::Uuid,
+        options: LinkOptions,
+    ) -> Result<String, AuthError> {
+        let plan = self.plans.plan_for(user_id).await.map_err(internal)?;
+        let expires_in_days = plan_options(&plan, &options).map_err(AuthError::Validation)?;
+
+        let starts_at = options.starts_at.unwrap_or_else(Utc::now);
+        let expires_at = expires_in_days.map(|days| starts_at + Duration::days(i64::from(days)));
+        let short_code = options.alias.clone().unwrap_or_else(|| nanoid!(8));
+        let password_hash = options
+            .password
+            .as_deref()
+            .map(hash_password)
+            .transpose()
+            .map_err(internal)?;
 
         // Save to DB first
-        self.repo
-            .store(&short_code, long_url, site_name, user_id)
-            .await?;
+        let new_url = NewUrl {
+            short_code: &short_code,
+            long_url,
+            site_name,
+            user_id,
+            workspace_id,
+            starts_at: options.starts_at,
+            expires_at,
+            fallback_url: options.fallback_url.as_deref(),
+            query_passthrough: options.query_passthrough,
+            path_passthrough: options.path_passthrough,
+            utm: options.utm.clone(),
+            folder_id: options.folder_id,
+            tags: &options.tags,
+            password_hash: password_hash.as_deref(),
+            max_clicks: options.max_clicks,
+        };
+        // The active link limit is checked while storing, under a lock
+        self.repo.store(&new_url).await.map_err(|e| {
+            if e.is::<LinkLimitReached>() {
+                return AuthError::PlanLimitReached;
+            }
+            if is_duplicate(&e) && options.alias.is_some() {
+                let mut errors = FieldErrors::new();
+                errors.insert("alias", vec!["This alias is already taken".to_string()]);
+                return AuthError::Validation(errors);
+            }
+            internal(e)
+        })?;
         // Optimistically cache it
-        let _ = self.cache.set(&short_code, long_url).await;
+        let target = LinkTarget {
+            long_url: long_url.to_string(),
+            user_id: Some(user_id),
+            starts_at: options.starts_at,
+            expires_at,
+            fallback_url: options.fallback_url.clone(),
+            query_passthrough: options.query_passthrough,
+            path_passthrough: options.path_passthrough,
+            utm: options.utm,
+            rules: Vec::new(),
+            protected: password_hash.is_some(),
+            max_clicks: options.max_clicks,
+        };
+        let _ = self.cache_target(&short_code, &target).await;
 
         Ok(short_code)
     }
-    #[instrument(name = "Service: Resolve url", skip(self))]
-    pub async fn resolve(&self, short_code: &str) -> Option<String> {
-        // 1. Try Cache
-        if let Some(url) = self.cache.get(short_code).await {
-            let s_code = short_code.to_string();
-            let repo = self.repo.clone();
-            let cache = self.cache.clone();
-
-            tokio::spawn(async move {
-                // We must fetch the owner to know which cache to delete
-                if let Ok(Some((_, Some(uid)))) = repo.fetch_with_owner(&s_code).await {
-                    let _ = cache.delete_user_urls(uid).await;
+
+    /// Create many links at once. Rows are checked like single links, then
+    /// stored together in `mode`. Failures are reported per row.
+    #[instrument(name = "Service: Import urls", skip(self, rows), fields(rows = rows.len()))]
+    pub async fn import(
+        &self,
+        user_id: Uuid,
+        workspace_id: Uuid,
+        rows: Vec<ImportRow>,
+        mode: ImportMode,
+    ) -> Result<ImportReport, AuthError> {
+        let plan = self.plans.plan_for(user_id).await.map_err(internal)?;
+        // Early estimate for the report, `store_many` enforces the limit
+        let mut room = match plan.max_active_links {
+            Some(max) => {
+                let active = self.plans.active_links(user_id).await.map_err(internal)?;
+                Some((i64::from(max) - active).max(0))
+            }
+            None => None,
+        };
+
+        // 1. Check every row before touching the database
+        let now = Utc::now();
+        let mut results = Vec::with_capacity(rows.len());
+        let mut accepted = Vec::new();
+        for row in &rows {
+            let checked = row.options.as_ref().map_err(describe).and_then(|options| {
+                let expires_in_days = plan_options(&plan, options).map_err(|e| describe(&e))?;
+                match room.as_mut() {
+                    Some(0) => return Err(format!("The {} plan link limit is reached", plan.name)),
+                    Some(left) => *left -= 1,
+                    None => {}
                 }
+                Ok((
+                    options.alias.clone().unwrap_or_else(|| nanoid!(8)),
+                    expires_in_days.map(|days| {
+                        options.starts_at.unwrap_or(now) + Duration::days(i64::from(days))
+                    }),
+                ))
             });
-            return Some(url);
+            let (status, short_code, error) = match checked {
+                Ok((short_code, expires_at)) => {
+                    accepted.push((results.len(), row, short_code.clone(), expires_at));
+                    (ImportStatus::Created, Some(short_code), None)
+                }
+                Err(error) => (ImportStatus::Failed, None, Some(error)),
+            };
+            results.push(ImportResult {
+                line: row.line,
+                status,
+                long_url: row.long_url.clone(),
+                short_code,
+                error,
+            });
+        }
+
+        // 2. Store what passed, in a single transaction
+        let atomic = mode == ImportMode::Atomic;
+        let outcomes = if atomic && accepted.len() < rows.len() {
+            Vec::new()
         } else {
-            tracing::warn!("Url not in cache!");
+            // Rows of a file often share a password, hash each one once
+            let mut hashes = HashMap::new();
+            for (_, row, _, _) in &accepted {
+                if let Ok(LinkOptions {
+                    password: Some(password),
+                    ..
+                }) = &row.options
+                    && !hashes.contains_key(password.as_str())
+                {
+                    hashes.insert(
+                        password.as_str(),
+                        hash_password(password).map_err(internal)?,
+                    );
+                }
+            }
+            let new_urls: Vec<NewUrl> = accepted
+                .iter()
+                .map(|(_, row, short_code, expires_at)| NewUrl {
+                    short_code,
+                    long_url: &row.long_url,
+                    site_name: &row.site_name,
+                    user_id,
+                    workspace_id,
+                    starts_at: row.options.as_ref().ok().and_then(|o| o.starts_at),
+                    expires_at: *expires_at,
+                    fallback_url: row
+                        .options
+                        .as_ref()
+                        .ok()
+                        .and_then(|o| o.fallback_url.as_deref()),
+                    query_passthrough: row
+                        .options
+                        .as_ref()
+                        .map(|o| o.query_passthrough)
+                        .unwrap_or_default(),
+                    path_passthrough: row.options.as_ref().is_ok_and(|o| o.path_passthrough),
+                    utm: row
+                        .options
+                        .as_ref()
+                        .map(|o| o.utm.clone())
+                        .unwrap_or_default(),
+                    folder_id: row.options.as_ref().ok().and_then(|o| o.folder_id),
+                    tags: row
+                        .options
+                        .as_ref()
+                        .map(|o| o.tags.as_slice())
+                        .unwrap_or_default(),
+                    password_hash: row
+                        .options
+                        .as_ref()
+                        .ok()
+                        .and_then(|o| o.password.as_deref())
+                        .and_then(|password| hashes.get(password))
+                        .map(String::as_str),
+                    max_clicks: row.options.as_ref().ok().and_then(|o| o.max_clicks),
+                })
+                .collect();
+            self.repo
+                .store_many(&new_urls, atomic)
+                .await
+                .map_err(internal)?
+        };
+
+        let mut committed = true;
+        for (i, (index, _, _, _)) in accepted.iter().enumerate() {
+            let result = &mut results[*index];
+            match outcomes.get(i) {
+                Some(Ok(())) => continue,
+                Some(Err(e)) => {
+                    committed = !atomic;
+                    result.status = ImportStatus::Failed;
+                    result.error = Some(if e.is::<LinkLimitReached>() {
+                        format!("The {} plan link limit is reached", plan.name)
+                    } else if is_duplicate(e) {
+                        "This alias is already taken".to_string()
+                    } else {
+                        tracing::error!("Failed to import row {}: {:?}", result.line, e);
+                        "The link could not be saved".to_string()
+                    });
+                }
+                None => {
+                    committed = false;
+                    result.status = ImportStatus::Skipped;
+                }
+            }
+            result.short_code = None;
+        }
+        if !committed {
+            // Rows stored before an atomic import failed were rolled back
+            for result in results
+                .iter_mut()
+                .filter(|r| r.status == ImportStatus::Created)
+            {
+                result.status = ImportStatus::Skipped;
+                result.short_code = None;
+            }
         }
 
-        // 2. Try DB (fetch_with_owner ALREADY increments clicks)
-        if let Ok(Some((url, user_id))) = self.repo.fetch_with_owner(short_code).await {
-            let cache = self.cache.clone();
+        tracing::info!(%user_id, %workspace_id, ?mode, rows = rows.len(), "Links imported");
+        Ok(ImportReport::new(mode, results))
+    }
 
-            // No need to call increment_clicks here! fetch_with_owner did it.
-            tokio::spawn(async move {
-                if let Some(uid) = user_id {
-                    let _ = cache.delete_user_urls(uid).await;
+    #[instrument(name = "Service: Resolve url", skip(self))]
+    pub async fn resolve(&self, short_code: &str, visit: &Visit) -> Resolution {
+        // 1. Try Cache, then DB
+        let target = match self.cached_target(short_code).await {
+            Some(target) => target,
+            None => {
+                tracing::warn!("Url not in cache!");
+                match self.repo.find_target(short_code).await {
+                    Ok(Some(target)) => {
+                        // Backfill individual link cache
+                        let _ = self.cache_target(short_code, &target).await;
+                        target
+                    }
+                    Ok(None) => {
+                        tracing::warn!("Url was not found");
+                        return Resolution::NotFound;
+                    }
+                    Err(e) => {
+                        tracing::error!("Failed to look up url: {:?}", e);
+                        return Resolution::NotFound;
+                    }
                 }
-            });
+            }
+        };
 
-            // Backfill individual link cache
-            let _ = self.cache.set(short_code, &url).await;
-            return Some(url);
+        if target.is_expired() {
+            return self
+                .fall_back(short_code, &target, visit, Resolution::Expired)
+                .await;
+        }
+        if let Some(starts_at) = target.pending_start() {
+            return self
+                .fall_back(short_code, &target, visit, Resolution::Scheduled(starts_at))
+                .await;
+        }
+        // Nothing is counted until the visitor gets through
+        if target.protected && !visit.unlocked {
+            return Resolution::Locked;
         }
-        tracing::warn!("Url was not found");
 
-        None
+        // 2. A limited link is only followed once its click is counted, the
+        // conditional update in the database is what keeps it to the limit.
+        // The click and the owner's redirect are charged together, a refused
+        // visit costs neither.
+        let limited = target.max_clicks.is_some();
+        if limited {
+            match self
+                .repo
+                .record_limited_click(short_code, visit.source, target.user_id)
+                .await
+            {
+                Ok(LimitedClick::Counted) => {}
+                Ok(LimitedClick::Exhausted) => {
+                    tracing::info!("Url has used up its clicks");
+                    return self
+                        .fall_back(short_code, &target, visit, Resolution::Exhausted)
+                        .await;
+                }
+                Ok(LimitedClick::QuotaExceeded) => {
+                    return self
+                        .fall_back(short_code, &target, visit, Resolution::QuotaExceeded)
+                        .await;
+                }
+                Err(e) => {
+                    tracing::error!("Failed to record click on a limited url: {:?}", e);
+                    return Resolution::Unavailable;
+                }
+            }
+        }
+
+        // 3. Count the redirect of any other link against the owner's plan
+        if !limited && let Some(owner) = target.user_id {
+            match self.plans.record_redirect(owner).await {
+                Ok(true) => {}
+                Ok(false) => {
+                    return self
+                        .fall_back(short_code, &target, visit, Resolution::QuotaExceeded)
+                        .await;
+                }
+                // Metering trouble should not take every link down with it
+                Err(e) => tracing::error!("Failed to record redirect: {:?}", e),
+            }
+        }
+
+        // 4. The first matching rule picks the destination, `long_url` otherwise
+        let visitor = Visitor::new(
+            visit.user_agent.as_deref(),
+            visit.accept_language.as_deref(),
+            visit.referrer.as_deref(),
+            Utc::now().time(),
+        );
+        let destination = choose(&target.rules, &visitor)
+            .map_or(target.long_url.as_str(), |rule| &rule.destination);
+        let forwarded = Forwarded {
+            query: visit.query.as_deref(),
+            path: visit.path.as_deref(),
+        };
+        let location = forward(destination, &target, &forwarded);
+
+        let s_code = short_code.to_string();
+        let source = visit.source;
+        let repo = self.repo.clone();
+        let cache = self.cache.clone();
+        tokio::spawn(async move {
+            if !limited && let Err(e) = repo.record_click(&s_code, source).await {
+                tracing::warn!("Failed to record click: {:?}", e);
+            }
+            if let Some(uid) = target.user_id {
+                let _ = cache.delete_user_urls(uid).await;
+            }
+        });
+
+        Resolution::Found(location)
+    }
+
+    /// Send the visitor of a link that cannot be followed to its fallback, the
+    /// link's own or else its owner's, and record that the fallback was served.
+    /// Without either the visitor gets `unavailable`.
+    async fn fall_back(
+        &self,
+        short_code: &str,
+        target: &LinkTarget,
+        visit: &Visit,
+        unavailable: Resolution,
+    ) -> Resolution {
+        let fallback_url = match (&target.fallback_url, target.user_id) {
+            (Some(url), _) => Some(url.clone()),
+            (None, Some(owner)) => self.repo.account_fallback(owner).await.unwrap_or_else(|e| {
+                tracing::error!("Failed to look up the account fallback: {:?}", e);
+                None
+            }),
+            (None, None) => None,
+        };
+        let Some(fallback_url) = fallback_url else {
+            return unavailable;
+        };
+
+        tracing::info!(reason = ?unavailable, "Serving the fallback url");
+        let s_code = short_code.to_string();
+        let source = visit.source;
+        let repo = self.repo.clone();
+        tokio::spawn(async move {
+            if let Err(e) = repo.record_fallback(&s_code, source).await {
+                tracing::warn!("Failed to record fallback: {:?}", e);
+            }
+        });
+        Resolution::Fallback(fallback_url)
+    }
+
+    /// Check a visitor's password for a protected link. False when it is wrong
+    /// or the link is missing or not protected.
+    #[instrument(name = "Service: Unlock url", skip(self, password))]
+    pub async fn unlock(&self, short_code: &str, password: &str) -> Result<bool, AuthError> {
+        let Some(password_hash) = self
+            .repo
+            .password_hash(short_code)
+            .await
+            .map_err(internal)?
+        else {
+            return Ok(false);
+        };
+        match verify_hash(&password_hash, password) {
+            Ok(()) => Ok(true),
+            Err(AuthError::WrongCredentials) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Protect a link of the workspace with a password, `None` removes it
+    #[instrument(name = "Service: Set url password", skip(self, workspace, password))]
+    pub async fn set_password(
+        &self,
+        workspace: &WorkspaceMembership,
+        short_code: &str,
+        password: Option<&str>,
+    ) -> Result<(), AuthError> {
+        if !workspace.role.can_edit() {
+            return Err(AuthError::Forbidden);
+        }
+        if let Some(problem) = password.and_then(link_password_problem) {
+            let mut errors = FieldErrors::new();
+            errors.insert("password", vec![problem]);
+            return Err(AuthError::Validation(errors));
+        }
+        let password_hash = password.map(hash_password).transpose().map_err(internal)?;
+        let updated = self
+            .repo
+            .set_password(workspace.id, short_code, password_hash.as_deref())
+            .await
+            .map_err(internal)?;
+        if !updated {
+            return Err(AuthError::NotFound);
+        }
+        // The cached target still says whether the link is protected
+        self.cache.delete(short_code).await.map_err(internal)?;
+        Ok(())
+    }
+
+    /// Set the fallback of a link of the workspace, `None` leaves it to the account's
+    #[instrument(name = "Service: Set url fallback", skip(self, workspace))]
+    pub async fn set_fallback_url(
+        &self,
+        workspace: &WorkspaceMembership,
+        short_code: &str,
+        fallback_url: Option<&str>,
+    ) -> Result<(), AuthError> {
+        if !workspace.role.can_edit() {
+            return Err(AuthError::Forbidden);
+        }
+        if fallback_url.is_some_and(|url| !is_web_url(url)) {
+            let mut errors = FieldErrors::new();
+            errors.insert(
+                "fallback_url",
+                vec!["Enter a full http:// or https:// address".to_string()],
+            );
+            return Err(AuthError::Validation(errors));
+        }
+        let updated = self
+            .repo
+            .set_fallback_url(workspace.id, short_code, fallback_url)
+            .await
+            .map_err(internal)?;
+        if !updated {
+            return Err(AuthError::NotFound);
+        }
+        self.cache.delete(short_code).await.map_err(internal)?;
+        Ok(())
     }
 
-    pub async fn get_user_urls(&self, user_id: Uuid) -> anyhow::Result<Vec<UrlModel>> {
-        self.repo.list_by_user(user_id).await
+    /// Set how the query string and extra path of a visit reach the destination
+    #[instrument(name = "Service: Set url passthrough", skip(self, workspace))]
+    pub async fn set_passthrough(
+        &self,
+        workspace: &WorkspaceMembership,
+        short_code: &str,
+        query_passthrough: QueryPassthrough,
+        path_passthrough: bool,
+    ) -> Result<(), AuthError> {
+        if !workspace.role.can_edit() {
+            return Err(AuthError::Forbidden);
+        }
+        let updated = self
+            .repo
+            .set_passthrough(
+                workspace.id,
+                short_code,
+                query_passthrough,
+                path_passthrough,
+            )
+            .await
+            .map_err(internal)?;
+        if !updated {
+            return Err(AuthError::NotFound);
+        }
+        self.cache.delete(short_code).await.map_err(internal)?;
+        Ok(())
     }
+
+    /// Replace the UTM parameters of a link, its destination is left as is
+    #[instrument(name = "Service: Set url UTM parameters", skip(self, workspace))]
+    pub async fn set_utm(
+        &self,
+        workspace: &WorkspaceMembership,
+        short_code: &str,
+        utm: &UtmParams,
+    ) -> Result<(), AuthError> {
+        if !workspace.role.can_edit() {
+            return Err(AuthError::Forbidden);
+        }
+        let updated = self
+            .repo
+            .set_utm(workspace.id, short_code, utm)
+            .await
+            .map_err(internal)?;
+        if !updated {
+            return Err(AuthError::NotFound);
+        }
+        self.cache.delete(short_code).await.map_err(internal)?;
+        Ok(())
+    }
+
+    /// Targeting rules of a link in the order they are tried
+    pub async fn rules(
+        &self,
+        workspace: &WorkspaceMembership,
+        short_code: &str,
+    ) -> Result<Vec<TargetingRule>, AuthError> {
+        self.repo
+            .rules(workspace.id, short_code)
+            .await
+            .map_err(internal)?
+            .ok_or(AuthError::NotFound)
+    }
+
+    /// Replace the targeting rules of a link, each one is checked first
+    #[instrument(name = "Service: Set url rules", skip(self, workspace, rules), fields(count = rules.len()))]
+    pub async fn set_rules(
+        &self,
+        workspace: &WorkspaceMembership,
+        short_code: &str,
+        rules: Vec<TargetingRule>,
+    ) -> Result<(), AuthError> {
+        if !workspace.role.can_edit() {
+            return Err(AuthError::Forbidden);
+        }
+        if rules.len() > MAX_RULES_PER_LINK {
+            let mut errors = FieldErrors::new();
+            errors.insert(
+                "rules",
+                vec![format!("Use at most {} rules", MAX_RULES_PER_LINK)],
+            );
+            return Err(AuthError::Validation(errors));
+        }
+        let rules = rules
+            .into_iter()
+            .enumerate()
+            .map(|(i, rule)| {
+                // Tell apart which rule of a list was rejected
+                normalize_rule(rule).map_err(|mut errors| {
+                    for messages in errors.values_mut() {
+                        for message in messages.iter_mut() {
+                            *message = format!("Rule {}: {}", i + 1, message);
+                        }
+                    }
+                    AuthError::Validation(errors)
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let updated = self
+            .repo
+            .set_rules(workspace.id, short_code, &rules)
+            .await
+            .map_err(internal)?;
+        if !updated {
+            return Err(AuthError::NotFound);
+        }
+        self.cache.delete(short_code).await.map_err(internal)?;
+        Ok(())
+    }
+
+    /// Add a targeting rule after the existing ones
+    #[instrument(name = "Service: Add url rule", skip(self, workspace, rule))]
+    pub async fn add_rule(
+        &self,
+        workspace: &WorkspaceMembership,
+        short_code: &str,
+        rule: TargetingRule,
+    ) -> Result<(), AuthError> {
+        if !workspace.role.can_edit() {
+            return Err(AuthError::Forbidden);
+        }
+        let rule = normalize_rule(rule).map_err(AuthError::Validation)?;
+        let edit = self
+            .repo
+            .add_rule(workspace.id, short_code, &rule, MAX_RULES_PER_LINK)
+            .await
+            .map_err(internal)?;
+        self.rule_edited(short_code, edit).await
+    }
+
+    /// Remove a targeting rule of a link
+    #[instrument(name = "Service: Remove url rule", skip(self, workspace))]
+    pub async fn remove_rule(
+        &self,
+        workspace: &WorkspaceMembership,
+        short_code: &str,
+        rule_id: Uuid,
+    ) -> Result<(), AuthError> {
+        if !workspace.role.can_edit() {
+            return Err(AuthError::Forbidden);
+        }
+        let edit = self
+            .repo
+            .delete_rule(workspace.id, short_code, rule_id)
+            .await
+            .map_err(internal)?;
+        self.rule_edited(short_code, edit).await
+    }
+
+    /// Swap a targeting rule with the one tried before it, or after it when
+    /// `earlier` is false
+    #[instrument(name = "Service: Move url rule", skip(self, workspace))]
+    pub async fn move_rule(
+        &self,
+        workspace: &WorkspaceMembership,
+        short_code: &str,
+        rule_id: Uuid,
+        earlier: bool,
+    ) -> Result<(), AuthError> {
+        if !workspace.role.can_edit() {
+            return Err(AuthError::Forbidden);
+        }
+        let edit = self
+            .repo
+            .move_rule(workspace.id, short_code, rule_id, earlier)
+            .await
+            .map_err(internal)?;
+        self.rule_edited(short_code, edit).await
+    }
+
+    async fn rule_edited(&self, short_code: &str, edit: RuleEdit) -> Result<(), AuthError> {
+        match edit {
+            RuleEdit::Applied => {
+                self.cache.delete(short_code).await.map_err(internal)?;
+                Ok(())
+            }
+            RuleEdit::NotFound => Err(AuthError::NotFound),
+            RuleEdit::Full => {
+                let mut errors = FieldErrors::new();
+                errors.insert(
+                    "rules",
+                    vec![format!("Use at most {} rules", MAX_RULES_PER_LINK)],
+                );
+                Err(AuthError::Validation(errors))
+            }
+        }
+    }
+
+    /// Whether a link with this short code exists and is not taken down
+    pub async fn exists(&self, short_code: &str) -> anyhow::Result<bool> {
+        Ok(self.repo.find_target(short_code).await?.is_some())
+    }
+
+    /// Entries written before the cache held JSON are treated as misses
+    async fn cached_target(&self, short_code: &str) -> Option<LinkTarget> {
+        let cached = self.cache.get(short_code).await?;
+        serde_json::from_str(&cached).ok()
+    }
+
+    async fn cache_target(&self, short_code: &str, target: &LinkTarget) -> anyhow::Result<()> {
+        self.cache
+            .set(short_code, &serde_json::to_string(target)?)
+            .await
+    }
+
+    pub async fn search_urls(&self, query: &str) -> anyhow::Result<Vec<UrlModel>> {
+        self.repo.search(query, 100).await
+    }
+
+    /// Take a link down (or restore it) and make sure the cache stops serving it
+    #[instrument(name = "Service: Set url taken down", skip(self))]
+    pub async fn set_taken_down(&self, short_code: &str, taken_down: bool) -> anyhow::Result<bool> {
+        let Some(owner) = self.repo.set_taken_down(short_code, taken_down).await? else {
+            return Ok(false);
+        };
+
+        self.cache.delete(short_code).await?;
+        if let Some(uid) = owner {
+            let _ = self.cache.delete_user_urls(uid).await;
+        }
+        Ok(true)
+    }
+
+    /// Drop cached redirects for links that no longer exist
+    #[instrument(name = "Service: Forget deleted urls", skip(self, short_codes))]
+    pub async fn forget_deleted(
+        &self,
+        user_id: Uuid,
+        short_codes: &[String],
+    ) -> anyhow::Result<()> {
+        for short_code in short_codes {
+            self.cache.delete(short_code).await?;
+        }
+        self.cache.delete_user_urls(user_id).await
+    }
+
+    /// One page of the links of a workspace that match the filter
+    #[instrument(name = "Service: List workspace urls", skip(self))]
+    pub async fn get_workspace_urls(
+        &self,
+        workspace_id: Uuid,
+        filter: &LinkFilter,
+    ) -> Result<LinkPage, AuthError> {
+        let mut errors = FieldErrors::new();
+        let cursor = match &filter.after {
+            Some(after) => {
+                let cursor = LinkCursor::decode(filter.sort, after);
+                if cursor.is_none() {
+                    errors.insert(
+                        "after",
+                        vec!["This page link is no longer valid".to_string()],
+                    );
+                }
+                cursor
+            }
+            None => None,
+        };
+        if let (Some(from), Some(to)) = (filter.from, filter.to)
+            && from > to
+        {
+            errors.insert(
+                "to",
+                vec!["Pick an end date after the start date".to_string()],
+            );
+        }
+        if !errors.is_empty() {
+            return Err(AuthError::Validation(errors));
+        }
+
+        // One extra row tells whether another page follows
+        let size = filter.page_size() as usize;
+        let mut links = self
+            .repo
+            .list_by_workspace(workspace_id, filter, cursor.as_ref(), size as i64 + 1)
+            .await
+            .map_err(internal)?;
+        let next = if links.len() > size {
+            links.truncate(size);
+            links
+                .last()
+                .map(|last| LinkCursor::after(filter.sort, last).encode())
+        } else {
+            None
+        };
+        Ok(LinkPage { links, next })
+    }
+
+    /// Every link of a workspace that matches the filter, in the filter's
+    /// order. Links are read in batches, so an export never holds the whole
+    /// workspace in memory.
+    pub fn export_links(
+        &self,
+        workspace_id: Uuid,
+        filter: LinkFilter,
+        daily: bool,
+    ) -> impl Stream<Item = anyhow::Result<Vec<ExportedLink>>> + Send + 'static {
+        let repo = self.repo.clone();
+        let filter = LinkFilter {
+            after: None,
+            limit: None,
+            ..filter
+        };
+        // `None` once the last batch is out, `Some(None)` before the first one
+        stream::try_unfold(Some(None::<LinkCursor>), move |state| {
+            let repo = repo.clone();
+            let filter = filter.clone();
+            async move {
+                let Some(cursor) = state else {
+                    return Ok(None);
+                };
+                let links = repo
+                    .list_by_workspace(workspace_id, &filter, cursor.as_ref(), EXPORT_BATCH)
+                    .await?;
+                if links.is_empty() {
+                    return Ok(None);
+                }
+                let next = if links.len() as i64 == EXPORT_BATCH {
+                    links
+                        .last()
+                        .map(|last| Some(LinkCursor::after(filter.sort, last)))
+                } else {
+                    None
+                };
+
+                let mut daily_clicks: HashMap<Uuid, Vec<DailyClicks>> = HashMap::new();
+                if daily {
+                    let ids: Vec<Uuid> = links.iter().map(|link| link.id).collect();
+                    for day in repo.daily_clicks(&ids).await? {
+                        daily_clicks.entry(day.url_id).or_default().push(day);
+                    }
+                }
+                let batch = links
+                    .into_iter()
+                    .map(|link| ExportedLink {
+                        daily_clicks: daily
+                            .then(|| daily_clicks.remove(&link.id).unwrap_or_default()),
+                        link,
+                    })
+                    .collect();
+                Ok(Some((batch, next)))
+            }
+        })
+    }
+
+    /// Number of links and clicks across a whole workspace
+    pub async fn workspace_totals(&self, workspace_id: Uuid) -> anyhow::Result<(i64, i64)> {
+        self.repo.workspace_totals(workspace_id).await
+    }
+}
+
+/// Render an import report as CSV, one row per uploaded line
+pub fn import_report_csv(results: &[ImportResult]) -> anyhow::Result<Vec<u8>> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    // `serialize` only writes the header together with the first row
+    if results.is_empty() {
+        writer.write_record(["line", "status", "long_url", "short_code", "error"])?;
+    }
+    for result in results {
+        writer.serialize(result)?;
+    }
+    Ok(writer.into_inner()?)
+}
+
+/// Check link options against the plan, returning the lifetime in days to apply
+fn plan_options(plan: &PlanModel, options: &LinkOptions) -> Result<Option<u32>, FieldErrors> {
+    let mut errors = FieldErrors::new();
+    if let Some(alias) = &options.alias {
+        if !plan.custom_aliases {
+            errors.insert(
+                "alias",
+                vec![format!(
+                    "Custom aliases are not included in the {} plan",
+                    plan.name
+                )],
+            );
+        } else if let Some(problem) = alias_problem(alias) {
+            errors.insert("alias", vec![problem]);
+        }
+    }
+    let max_days = plan.max_link_ttl_days.map(|days| days.max(0) as u32);
+    let expires_in_days = match (options.expires_in_days, max_days) {
+        (Some(requested), Some(max)) if requested > max => {
+            errors.insert(
+                "expires_in_days",
+                vec![format!(
+                    "The {} plan keeps links active for at most {} days",
+                    plan.name, max
+                )],
+            );
+            None
+        }
+        (requested, max) => requested.or(max),
+    };
+    if errors.is_empty() {
+        Ok(expires_in_days)
+    } else {
+        Err(errors)
+    }
+}
+
+fn is_duplicate(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<sqlx::Error>()
+        .and_then(|e| e.as_database_error())
+        .is_some_and(|e| e.is_unique_violation())
+}
+
+/// Field errors as one line for a report, e.g. `alias: Use only letters`
+fn describe(errors: &FieldErrors) -> String {
+    errors
+        .iter()
+        .map(|(field, messages)| format!("{}: {}", field, messages.join(", ")))
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// Only absolute http(s) addresses are followed
+pub fn is_web_url(value: &str) -> bool {
+    reqwest::Url::parse(value)
+        .is_ok_and(|url| matches!(url.scheme(), "http" | "https") && url.has_host())
+}
+
+/// Why a link password cannot be used, if it cannot
+pub fn link_password_problem(password: &str) -> Option<String> {
+    let len = password.chars().count();
+    if !(MIN_LINK_PASSWORD_LEN..=MAX_LINK_PASSWORD_LEN).contains(&len) {
+        return Some(format!(
+            "Use between {} and {} characters",
+            MIN_LINK_PASSWORD_LEN, MAX_LINK_PASSWORD_LEN
+        ));
+    }
+    None
+}
+
+/// Why an alias cannot be used as a short code, if it cannot
+fn alias_problem(alias: &str) -> Option<String> {
+    if alias.len() < 3 || alias.len() > MAX_ALIAS_LEN {
+        return Some(format!("Use between 3 and {} characters", MAX_ALIAS_LEN));
+    }
+    if !alias
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Some("Use only letters, numbers, dashes and underscores".to_string());
+    }
+    None
+}
+
+fn internal(e: anyhow::Error) -> AuthError {
+    tracing::error!("Url service error: {:?}", e);
+    AuthError::Internal
 }
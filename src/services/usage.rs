@@ -0,0 +1,61 @@
+use chrono::{DurationRound, NaiveDate, TimeDelta, Utc};
+use tracing::instrument;
+
+use crate::{
+    models::usage::{UsageExport, UsageRecord},
+    store::usage::UsageRepository,
+};
+
+/// How long after an hour ends it is still open. Usage is bucketed by the
+/// start of the transaction recording it, so one that began just before the
+/// hour turned can commit into it a little later.
+const COMPLETION_GRACE: TimeDelta = TimeDelta::minutes(5);
+
+#[derive(Clone, Debug)]
+pub struct UsageService {
+    repo: UsageRepository,
+}
+
+impl UsageService {
+    pub fn new(repo: UsageRepository) -> Self {
+        Self { repo }
+    }
+
+    /// Usage from the start of `from` up to the start of `to`, both read as UTC dates
+    #[instrument(name = "Service: Export usage", skip(self))]
+    pub async fn export(&self, from: NaiveDate, to: NaiveDate) -> anyhow::Result<UsageExport> {
+        if from >= to {
+            anyhow::bail!("the billing period must end after it starts");
+        }
+        let period_start = from.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc();
+        let period_end = to.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc();
+
+        let generated_at = Utc::now();
+        // The current hour is still counting, it is left for a later export
+        let complete_through = (generated_at - COMPLETION_GRACE)
+            .duration_trunc(TimeDelta::hours(1))?
+            .clamp(period_start, period_end);
+        let records = self.repo.list(period_start, complete_through).await?;
+
+        Ok(UsageExport {
+            period_start,
+            period_end,
+            complete_through,
+            generated_at,
+            records,
+        })
+    }
+}
+
+/// Render usage as CSV, one row per account, hour and metric
+pub fn usage_csv(records: &[UsageRecord]) -> anyhow::Result<Vec<u8>> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    // `serialize` only writes the header together with the first row
+    if records.is_empty() {
+        writer.write_record(["id", "user_id", "hour", "metric", "quantity"])?;
+    }
+    for record in records {
+        writer.serialize(record)?;
+    }
+    Ok(writer.into_inner()?)
+}
@@ -0,0 +1,134 @@
+use tracing::instrument;
+use uuid::Uuid;
+
+use crate::{
+    errors::{AuthError, FieldErrors},
+    models::utm::{UtmParams, UtmPreset},
+    store::utm::UtmRepository,
+};
+
+/// Longest value accepted for a single UTM parameter
+const MAX_UTM_LEN: usize = 200;
+/// Longest preset name accepted
+const MAX_PRESET_NAME_LEN: usize = 64;
+
+#[derive(Clone, Debug)]
+pub struct UtmService {
+    repo: UtmRepository,
+}
+
+fn internal(e: anyhow::Error) -> AuthError {
+    tracing::error!("UTM preset storage error: {:?}", e);
+    AuthError::Internal
+}
+
+fn field_error(field: &'static str, message: &str) -> AuthError {
+    let mut errors = FieldErrors::new();
+    errors.insert(field, vec![message.to_string()]);
+    AuthError::Validation(errors)
+}
+
+impl UtmService {
+    pub fn new(repo: UtmRepository) -> Self {
+        Self { repo }
+    }
+
+    pub async fn list_presets(&self, user_id: Uuid) -> anyhow::Result<Vec<UtmPreset>> {
+        self.repo.list(user_id).await
+    }
+
+    #[instrument(name = "UtmService: Create preset", skip(self))]
+    pub async fn create_preset(
+        &self,
+        user_id: Uuid,
+        name: &str,
+        params: &UtmParams,
+    ) -> Result<Uuid, AuthError> {
+        let name = name.split_whitespace().collect::<Vec<_>>().join(" ");
+        if name.is_empty() || name.chars().count() > MAX_PRESET_NAME_LEN {
+            return Err(field_error(
+                "name",
+                &format!("Use between 1 and {} characters", MAX_PRESET_NAME_LEN),
+            ));
+        }
+        if params.is_empty() {
+            return Err(field_error("utm_source", "Set at least one parameter"));
+        }
+        self.repo.create(user_id, &name, params).await.map_err(|e| {
+            let duplicate = e
+                .downcast_ref::<sqlx::Error>()
+                .and_then(|e| e.as_database_error())
+                .is_some_and(|e| e.is_unique_violation());
+            if duplicate {
+                return field_error("name", "A preset with this name already exists");
+            }
+            internal(e)
+        })
+    }
+
+    pub async fn delete_preset(&self, user_id: Uuid, id: Uuid) -> Result<(), AuthError> {
+        if self.repo.delete(user_id, id).await.map_err(internal)? {
+            Ok(())
+        } else {
+            Err(AuthError::NotFound)
+        }
+    }
+
+    /// Fill the parameters `params` leaves unset from the user's preset
+    /// called `preset`, when one is named
+    pub async fn with_preset(
+        &self,
+        user_id: Uuid,
+        preset: Option<&str>,
+        params: UtmParams,
+    ) -> Result<UtmParams, AuthError> {
+        let Some(name) = preset.map(str::trim).filter(|name| !name.is_empty()) else {
+            return Ok(params);
+        };
+        match self
+            .repo
+            .find_by_name(user_id, name)
+            .await
+            .map_err(internal)?
+        {
+            Some(preset) => Ok(params.or(&preset.params)),
+            None => Err(field_error(
+                "utm_preset",
+                "There is no preset with this name",
+            )),
+        }
+    }
+}
+
+/// Read the five UTM parameters as typed, blank ones are left unset
+pub fn parse_utm(
+    source: &str,
+    medium: &str,
+    campaign: &str,
+    term: &str,
+    content: &str,
+) -> Result<UtmParams, FieldErrors> {
+    let mut errors = FieldErrors::new();
+    let mut value = |field: &'static str, value: &str| {
+        let value = value.trim();
+        if value.chars().count() > MAX_UTM_LEN {
+            errors.insert(
+                field,
+                vec![format!("Use at most {} characters", MAX_UTM_LEN)],
+            );
+        }
+        (!value.is_empty()).then(|| value.to_string())
+    };
+    let params = UtmParams {
+        utm_source: value("utm_source", source),
+        utm_medium: value("utm_medium", medium),
+        utm_campaign: value("utm_campaign", campaign),
+        utm_term: value("utm_term", term),
+        utm_content: value("utm_content", content),
+    };
+    if errors.is_empty() {
+        Ok(params)
+    } else {
+        Err(errors)
+    }
+}
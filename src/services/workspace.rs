@@ -0,0 +1,206 @@
+use nanoid::nanoid;
+use tracing::instrument;
+use uuid::Uuid;
+
+use crate::{
+    errors::AuthError,
+    models::workspace::{InvitationModel, MemberModel, WorkspaceMembership, WorkspaceRole},
+    store::workspace::WorkspaceRepository,
+};
+
+#[derive(Clone, Debug)]
+pub struct WorkspaceService {
+    repo: WorkspaceRepository,
+}
+
+fn internal(e: anyhow::Error) -> AuthError {
+    tracing::error!("Workspace storage error: {:?}", e);
+    AuthError::Internal
+}
+
+impl WorkspaceService {
+    pub fn new(repo: WorkspaceRepository) -> Self {
+        Self { repo }
+    }
+
+    pub async fn list_for_user(&self, user_id: Uuid) -> anyhow::Result<Vec<WorkspaceMembership>> {
+        self.repo.list_for_user(user_id).await
+    }
+
+    /// Resolve the workspace a request acts on: the requested one if the user
+    /// belongs to it, otherwise the user's personal workspace.
+    #[instrument(name = "WorkspaceService: Resolve workspace", skip(self))]
+    pub async fn resolve(
+        &self,
+        user_id: Uuid,
+        requested: Option<Uuid>,
+    ) -> Result<WorkspaceMembership, AuthError> {
+        if let Some(workspace_id) = requested {
+            if let Some(membership) = self
+                .repo
+                .membership(workspace_id, user_id)
+                .await
+                .map_err(internal)?
+            {
+                return Ok(membership);
+            }
+            tracing::warn!(%workspace_id, "Requested workspace is not accessible, using personal");
+        }
+
+        self.repo
+            .personal_for_user(user_id)
+            .await
+            .map_err(internal)?
+            .ok_or_else(|| {
+                tracing::error!("User has no personal workspace");
+                AuthError::Internal
+            })
+    }
+
+    /// The user's membership in a specific workspace, or `Forbidden` if they are not a member
+    pub async fn membership(
+        &self,
+        workspace_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<WorkspaceMembership, AuthError> {
+        self.repo
+            .membership(workspace_id, user_id)
+            .await
+            .map_err(internal)?
+            .ok_or(AuthError::Forbidden)
+    }
+
+    pub async fn create(&self, name: &str, owner_id: Uuid) -> anyhow::Result<Uuid> {
+        self.repo.create(name, owner_id).await
+    }
+
+    pub async fn members(&self, workspace_id: Uuid) -> anyhow::Result<Vec<MemberModel>> {
+        self.repo.members(workspace_id).await
+    }
+
+    pub async fn pending_invitations(
+        &self,
+        workspace_id: Uuid,
+    ) -> anyhow::Result<Vec<InvitationModel>> {
+        self.repo.pending_invitations(workspace_id).await
+    }
+
+    /// Invite `email` into the workspace and return the invitation token
+    #[instrument(name = "WorkspaceService: Invite", skip(self, workspace))]
+    pub async fn invite(
+        &self,
+        workspace: &WorkspaceMembership,
+        invited_by: Uuid,
+        email: &str,
+        role: WorkspaceRole,
+    ) -> Result<String, AuthError> {
+        if !workspace.role.can_manage() || workspace.personal {
+            return Err(AuthError::Forbidden);
+        }
+        let token = nanoid!(32);
+        self.repo
+            .create_invitation(workspace.id, email, role, &token, invited_by)
+            .await
+            .map_err(internal)?;
+        Ok(token)
+    }
+
+    pub async fn find_invitation(&self, token: &str) -> Result<InvitationModel, AuthError> {
+        self.repo
+            .find_invitation(token)
+            .await
+            .map_err(internal)?
+            .filter(|i| i.accepted_at.is_none())
+            .ok_or(AuthError::NotFound)
+    }
+
+    /// Accept an invitation on behalf of the user whose email it was sent to
+    #[instrument(name = "WorkspaceService: Accept invitation", skip(self, token))]
+    pub async fn accept(
+        &self,
+        token: &str,
+        user_id: Uuid,
+        user_email: &str,
+    ) -> Result<Uuid, AuthError> {
+        let invitation = self.find_invitation(token).await?;
+        if !invitation.email.eq_ignore_ascii_case(user_email) {
+            tracing::warn!("Invitation was addressed to a different email");
+            return Err(AuthError::Forbidden);
+        }
+        self.repo
+            .accept_invitation(invitation.id, user_id)
+            .await
+            .map_err(internal)?;
+        Ok(invitation.workspace_id)
+    }
+
+    #[instrument(name = "WorkspaceService: Change member role", skip(self, workspace))]
+    pub async fn set_member_role(
+        &self,
+        workspace: &WorkspaceMembership,
+        user_id: Uuid,
+        role: WorkspaceRole,
+    ) -> Result<(), AuthError> {
+        if !workspace.role.can_manage() {
+            return Err(AuthError::Forbidden);
+        }
+        if role != WorkspaceRole::Owner {
+            self.ensure_not_last_owner(workspace.id, user_id).await?;
+        }
+        if !self
+            .repo
+            .set_member_role(workspace.id, user_id, role)
+            .await
+            .map_err(internal)?
+        {
+            return Err(AuthError::NotFound);
+        }
+        Ok(())
+    }
+
+    #[instrument(name = "WorkspaceService: Remove member", skip(self, workspace))]
+    pub async fn remove_member(
+        &self,
+        workspace: &WorkspaceMembership,
+        user_id: Uuid,
+    ) -> Result<(), AuthError> {
+        if !workspace.role.can_manage() || workspace.personal {
+            return Err(AuthError::Forbidden);
+        }
+        self.ensure_not_last_owner(workspace.id, user_id).await?;
+        if !self
+            .repo
+            .remove_member(workspace.id, user_id)
+            .await
+            .map_err(internal)?
+        {
+            return Err(AuthError::NotFound);
+        }
+        Ok(())
+    }
+
+    /// A workspace must always keep at least one owner
+    async fn ensure_not_last_owner(
+        &self,
+        workspace_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<(), AuthError> {
+        let is_owner = self
+            .repo
+            .membership(workspace_id, user_id)
+            .await
+            .map_err(internal)?
+            .is_some_and(|m| m.role == WorkspaceRole::Owner);
+        if is_owner
+            && self
+                .repo
+                .count_owners(workspace_id)
+                .await
+                .map_err(internal)?
+                <= 1
+        {
+            return Err(AuthError::Forbidden);
+        }
+        Ok(())
+    }
+}
@@ -1,36 +1,101 @@
 pub use crate::configuration;
+use crate::middleware::csrf::csrf_protect;
+use crate::middleware::rate_limit::{RateLimiter, rate_limit};
+use crate::routes::admin::{
+    admin_handler, disable_user_handler, enable_user_handler, restore_url_handler,
+    takedown_url_handler, usage_export_handler, user_plan_handler, user_role_handler,
+};
 use crate::routes::auth::login_page;
 use crate::routes::auth::login_post;
 use crate::routes::auth::logout_handler;
 use crate::routes::auth::signup_page;
 use crate::routes::auth::signup_post;
 use crate::routes::dashboard::dashboard_handler;
-use crate::routes::url::shorten_form_handler;
+use crate::routes::import::import_handler;
+use crate::routes::oidc::{oidc_callback, oidc_login};
+use crate::routes::profile::{
+    change_email_handler, change_password_handler, confirm_email_handler, delete_account_handler,
+    erase_account_handler, export_handler, fallback_url_handler, profile_page,
+    update_profile_handler,
+};
+use crate::routes::qr::qr_handler;
+use crate::routes::tag::{
+    create_folder_handler, delete_folder_handler, delete_tag_handler, label_link_handler,
+    list_folders, list_tags, merge_tag_handler, rename_folder_handler, rename_tag_handler,
+    tags_page,
+};
+use crate::routes::url::{
+    add_link_rule_handler, delete_link_rule_handler, export_urls, link_fallback_handler,
+    link_passthrough_handler, link_password_handler, link_utm_handler, list_link_rules, list_urls,
+    move_link_rule_handler, replace_link_rules, shorten_form_handler, unlock_handler,
+};
+use crate::routes::utm::{create_utm_preset_handler, delete_utm_preset_handler, list_utm_presets};
+use crate::routes::workspace::{
+    accept_invitation_handler, create_workspace_handler, invitation_page, invite_handler,
+    member_role_handler, remove_member_handler, select_workspace_handler, workspaces_page,
+};
 use crate::services::auth::AuthService;
+use crate::services::jwt::JwtService;
+use crate::services::mailer;
+use crate::services::oidc::OidcService;
+use crate::services::password::PasswordPolicy;
+use crate::services::plan::PlanService;
+use crate::services::privacy::PrivacyService;
+use crate::services::qr::QrService;
+use crate::services::tag::TagService;
 use crate::services::url::UrlService;
+use crate::services::usage::UsageService;
+use crate::services::utm::UtmService;
+use crate::services::workspace::WorkspaceService;
 use crate::store::CacheRepository;
 use crate::store::UrlRepository;
+use crate::store::plan::PlanRepository;
+use crate::store::tag::TagRepository;
+use crate::store::usage::UsageRepository;
 use crate::store::user::UserRepository;
+use crate::store::utm::UtmRepository;
+use crate::store::workspace::WorkspaceRepository;
+use std::net::SocketAddr;
 use tower_http::services::ServeDir;
 
 use axum::{
     Router,
+    extract::FromRef,
+    middleware,
     routing::{get, post},
 };
+use axum_extra::extract::cookie::Key;
 use redis::AsyncCommands;
 use redis::Client;
+use secrecy::ExposeSecret;
 use sqlx::postgres::PgPoolOptions;
 
-use crate::routes::auth::{authorize_handler, register_handler};
+use crate::routes::auth::{authorize_handler, jwks_handler, register_handler};
 use crate::{
     configuration::get_configuration,
-    routes::url::{redirect, shorten},
+    routes::url::{redirect, redirect_path, shorten},
 };
 
 #[derive(Clone, Debug)]
 pub struct AppState {
     pub url_service: UrlService,
     pub auth_service: AuthService,
+    pub workspace_service: WorkspaceService,
+    pub privacy_service: PrivacyService,
+    pub plan_service: PlanService,
+    pub usage_service: UsageService,
+    pub tag_service: TagService,
+    pub utm_service: UtmService,
+    pub qr_service: QrService,
+    pub jwt_service: JwtService,
+    pub oidc_service: Option<OidcService>,
+    pub cookie_key: Key,
+}
+
+impl FromRef<AppState> for Key {
+    fn from_ref(state: &AppState) -> Self {
+        state.cookie_key.clone()
+    }
 }
 
 pub async fn run() {
@@ -54,25 +119,193 @@ pub async fn run() {
         .connect_lazy_with(cfg.database.with_db());
 
     let repo = UrlRepository::new(pg_pool.clone());
-    let cache = CacheRepository::new(redis_pool);
-    let url_service = UrlService::new(repo, cache);
+    let cache = CacheRepository::new(redis_pool.clone());
+    let plan_service = PlanService::new(PlanRepository::new(pg_pool.clone()));
+    let usage_service = UsageService::new(UsageRepository::new(pg_pool.clone()));
+    let tag_service = TagService::new(TagRepository::new(pg_pool.clone()));
+    let utm_repo = UtmRepository::new(pg_pool.clone());
+    let utm_service = UtmService::new(utm_repo.clone());
+    let url_service = UrlService::new(repo.clone(), cache.clone(), plan_service.clone());
 
     let user_repo = UserRepository::new(pg_pool.clone());
-    let auth_service = AuthService::new(user_repo);
+    let workspace_repo = WorkspaceRepository::new(pg_pool.clone());
+    let password_policy = PasswordPolicy::from_settings(&cfg.auth.password_policy)
+        .expect("could not load the password policy");
+    let auth_service = AuthService::new(
+        user_repo.clone(),
+        cfg.auth.password_login_enabled,
+        password_policy,
+        cfg.mailer.as_ref().map(mailer::from_settings),
+        cfg.application.public_url(),
+    );
+    let workspace_service = WorkspaceService::new(workspace_repo.clone());
+    let privacy_service = PrivacyService::new(user_repo, repo, workspace_repo, utm_repo, cache);
+    let jwt_service = JwtService::from_settings(&cfg.auth).expect("could not load JWT keys");
+    let limiter = RateLimiter::new(&cfg.rate_limit, redis_pool, jwt_service.clone());
+    let throttle =
+        |name, policy| middleware::from_fn_with_state(limiter.policy(name, policy), rate_limit);
+    let oidc_service = cfg.oidc.map(OidcService::new);
+    if !cfg.auth.password_login_enabled && oidc_service.is_none() {
+        panic!("password login is disabled but no OIDC provider is configured");
+    }
+
+    let cookie_secret = cfg.application.cookie_secret.expose_secret().as_bytes();
+    assert!(
+        cookie_secret.len() >= 64,
+        "application.cookie_secret must be at least 64 bytes"
+    );
+    let cookie_key = Key::from(cookie_secret);
+
     let app_state = AppState {
         url_service,
         auth_service,
+        workspace_service,
+        privacy_service,
+        plan_service: plan_service.clone(),
+        usage_service,
+        tag_service,
+        utm_service,
+        qr_service: QrService::new(cfg.application.public_url()),
+        jwt_service,
+        oidc_service,
+        cookie_key,
     };
+    tokio::spawn(prune_analytics(plan_service));
+
     let app = Router::new()
         .route("/dashboard", get(dashboard_handler))
-        .route("/url/shorten", get(shorten))
-        .route("/url/{key}", get(redirect))
-        .route("/register", post(register_handler))
-        .route("/login", get(login_page).post(login_post))
-        .route("/signup", get(signup_page).post(signup_post))
-        .route("/logout", get(logout_handler))
-        .route("/authorize", post(authorize_handler))
-        .route("/shorten", post(shorten_form_handler))
+        .route(
+            "/url/shorten",
+            get(shorten).layer(throttle("shorten", &cfg.rate_limit.shorten)),
+        )
+        .route("/api/urls", get(list_urls))
+        .route(
+            "/api/urls/{key}/rules",
+            get(list_link_rules).put(replace_link_rules),
+        )
+        .route("/urls/export", get(export_urls))
+        .route("/api/tags", get(list_tags))
+        .route("/api/folders", get(list_folders))
+        .route("/api/utm-presets", get(list_utm_presets))
+        .route(
+            "/url/{key}",
+            get(redirect).layer(throttle("redirect", &cfg.rate_limit.redirect)),
+        )
+        .route(
+            "/url/{key}/unlock",
+            post(unlock_handler).layer(throttle("auth", &cfg.rate_limit.auth)),
+        )
+        // Static routes such as `/url/{key}/qr` win over the wildcard
+        .route(
+            "/url/{key}/{*rest}",
+            get(redirect_path).layer(throttle("redirect", &cfg.rate_limit.redirect)),
+        )
+        .route(
+            "/url/{key}/qr",
+            get(qr_handler).layer(throttle("redirect", &cfg.rate_limit.redirect)),
+        )
+        .route(
+            "/register",
+            post(register_handler).layer(throttle("auth", &cfg.rate_limit.auth)),
+        )
+        .route(
+            "/login",
+            get(login_page).merge(post(login_post).layer(throttle("auth", &cfg.rate_limit.auth))),
+        )
+        .route(
+            "/signup",
+            get(signup_page).merge(post(signup_post).layer(throttle("auth", &cfg.rate_limit.auth))),
+        )
+        .route("/logout", post(logout_handler))
+        .route(
+            "/authorize",
+            post(authorize_handler).layer(throttle("auth", &cfg.rate_limit.auth)),
+        )
+        .route("/.well-known/jwks.json", get(jwks_handler))
+        .route("/auth/oidc/login", get(oidc_login))
+        .route("/auth/oidc/callback", get(oidc_callback))
+        .route(
+            "/shorten",
+            post(shorten_form_handler).layer(throttle("shorten", &cfg.rate_limit.shorten)),
+        )
+        .route(
+            "/urls/import",
+            post(import_handler).layer(throttle("shorten", &cfg.rate_limit.shorten)),
+        )
+        .route("/urls/{key}/labels", post(label_link_handler))
+        .route("/urls/{key}/password", post(link_password_handler))
+        .route("/urls/{key}/fallback", post(link_fallback_handler))
+        .route("/urls/{key}/passthrough", post(link_passthrough_handler))
+        .route("/urls/{key}/utm", post(link_utm_handler))
+        .route("/urls/{key}/rules", post(add_link_rule_handler))
+        .route(
+            "/urls/{key}/rules/{rule_id}/delete",
+            post(delete_link_rule_handler),
+        )
+        .route(
+            "/urls/{key}/rules/{rule_id}/move",
+            post(move_link_rule_handler),
+        )
+        .route("/tags", get(tags_page))
+        .route("/tags/{id}/rename", post(rename_tag_handler))
+        .route("/tags/{id}/merge", post(merge_tag_handler))
+        .route("/tags/{id}/delete", post(delete_tag_handler))
+        .route("/folders", post(create_folder_handler))
+        .route("/folders/{id}/rename", post(rename_folder_handler))
+        .route("/folders/{id}/delete", post(delete_folder_handler))
+        .route("/profile", get(profile_page))
+        .route("/update-profile", post(update_profile_handler))
+        .route(
+            "/profile/email",
+            post(change_email_handler).layer(throttle("auth", &cfg.rate_limit.auth)),
+        )
+        .route("/profile/email/confirm", get(confirm_email_handler))
+        .route(
+            "/profile/password",
+            post(change_password_handler).layer(throttle("auth", &cfg.rate_limit.auth)),
+        )
+        .route("/profile/fallback", post(fallback_url_handler))
+        .route("/profile/utm-presets", post(create_utm_preset_handler))
+        .route(
+            "/profile/utm-presets/{id}/delete",
+            post(delete_utm_preset_handler),
+        )
+        .route(
+            "/profile/delete",
+            post(delete_account_handler).layer(throttle("auth", &cfg.rate_limit.auth)),
+        )
+        .route("/profile/export", get(export_handler))
+        .route(
+            "/profile/erase",
+            post(erase_account_handler).layer(throttle("auth", &cfg.rate_limit.auth)),
+        )
+        .route(
+            "/workspaces",
+            get(workspaces_page).post(create_workspace_handler),
+        )
+        .route("/workspaces/{id}/select", post(select_workspace_handler))
+        .route("/workspaces/{id}/invitations", post(invite_handler))
+        .route(
+            "/workspaces/{id}/members/{user_id}/role",
+            post(member_role_handler),
+        )
+        .route(
+            "/workspaces/{id}/members/{user_id}/remove",
+            post(remove_member_handler),
+        )
+        .route("/invitations/{token}", get(invitation_page))
+        .route(
+            "/invitations/{token}/accept",
+            post(accept_invitation_handler),
+        )
+        .route("/admin", get(admin_handler))
+        .route("/admin/users/{id}/disable", post(disable_user_handler))
+        .route("/admin/users/{id}/enable", post(enable_user_handler))
+        .route("/admin/users/{id}/role", post(user_role_handler))
+        .route("/admin/users/{id}/plan", post(user_plan_handler))
+        .route("/admin/usage/export", get(usage_export_handler))
+        .route("/admin/urls/{key}/takedown", post(takedown_url_handler))
+        .route("/admin/urls/{key}/restore", post(restore_url_handler))
         .nest_service(
             "/assets",
             ServeDir::new(format!(
@@ -80,13 +313,33 @@ pub async fn run() {
                 std::env::current_dir().unwrap().to_str().unwrap()
             )),
         )
+        .layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            csrf_protect,
+        ))
         .with_state(app_state);
 
     let listener = tokio::net::TcpListener::bind("127.0.0.1:4001")
         .await
         .unwrap();
-    axum::serve(listener, app)
-        .await
-        .expect("could not start server");
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .expect("could not start server");
+}
+/// Drop click events that are older than their owner's plan keeps analytics for
+async fn prune_analytics(plans: PlanService) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(3600));
+    loop {
+        interval.tick().await;
+        match plans.prune_analytics().await {
+            Ok(0) => {}
+            Ok(pruned) => tracing::info!(pruned, "Pruned expired click events"),
+            Err(e) => tracing::warn!("Failed to prune click events: {:?}", e),
+        }
+    }
 }
+
 pub type ConnectionPool = bb8::Pool<Client>;
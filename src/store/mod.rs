@@ -1,3 +1,8 @@
+pub mod plan;
+pub mod tag;
 pub mod url;
+pub mod usage;
 pub mod user;
+pub mod utm;
+pub mod workspace;
 pub use url::{CacheRepository, UrlRepository};
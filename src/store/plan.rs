@@ -0,0 +1,178 @@
+use sqlx::{Pool, Postgres, Transaction};
+use tracing::instrument;
+use uuid::Uuid;
+
+use crate::{
+    models::{plan::PlanModel, usage::UsageMetric},
+    store::usage,
+};
+
+#[derive(Clone, Debug)]
+pub struct PlanRepository {
+    pg_pool: Pool<Postgres>,
+}
+
+impl PlanRepository {
+    pub fn new(pg_pool: Pool<Postgres>) -> Self {
+        Self { pg_pool }
+    }
+
+    pub async fn list(&self) -> anyhow::Result<Vec<PlanModel>> {
+        let plans = sqlx::query_as::<_, PlanModel>(
+            r#"SELECT id, name, max_active_links, monthly_redirects, max_link_ttl_days, custom_aliases, analytics_retention_days
+            FROM plans
+            ORDER BY position"#,
+        )
+        .fetch_all(&self.pg_pool)
+        .await?;
+        Ok(plans)
+    }
+
+    pub async fn find_for_user(&self, user_id: Uuid) -> anyhow::Result<Option<PlanModel>> {
+        let plan = sqlx::query_as::<_, PlanModel>(
+            r#"SELECT p.id, p.name, p.max_active_links, p.monthly_redirects, p.max_link_ttl_days, p.custom_aliases, p.analytics_retention_days
+            FROM plans p
+            JOIN users u ON u.plan_id = p.id
+            WHERE u.id = $1"#,
+        )
+        .bind(user_id)
+        .fetch_optional(&self.pg_pool)
+        .await?;
+        Ok(plan)
+    }
+
+    /// Returns false when the user or the plan does not exist
+    pub async fn assign(&self, user_id: Uuid, plan_id: &str) -> anyhow::Result<bool> {
+        let result = sqlx::query!(
+            "UPDATE users SET plan_id = p.id FROM plans p WHERE users.id = $1 AND p.id = $2",
+            user_id,
+            plan_id
+        )
+        .execute(&self.pg_pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Links created by the user that still redirect
+    pub async fn active_links(&self, user_id: Uuid) -> anyhow::Result<i64> {
+        let count = sqlx::query_scalar!(
+            r#"SELECT COUNT(*) AS "count!" FROM urls
+            WHERE user_id = $1 AND NOT taken_down AND (expires_at IS NULL OR expires_at > NOW())
+                AND (max_clicks IS NULL OR clicks < max_clicks)"#,
+            user_id
+        )
+        .fetch_one(&self.pg_pool)
+        .await?;
+        Ok(count)
+    }
+
+    pub async fn redirects_this_month(&self, user_id: Uuid) -> anyhow::Result<i64> {
+        let count = sqlx::query_scalar!(
+            r#"SELECT redirects FROM redirect_usage
+            WHERE user_id = $1 AND month = date_trunc('month', NOW())::date"#,
+            user_id
+        )
+        .fetch_optional(&self.pg_pool)
+        .await?;
+        Ok(count.unwrap_or(0))
+    }
+
+    /// Count one redirect against the user's monthly quota and meter it for
+    /// billing. Returns false, without counting, once the quota is used up.
+    #[instrument(name = "Record redirect", skip(self))]
+    pub async fn record_redirect(&self, user_id: Uuid) -> anyhow::Result<bool> {
+        let mut tx = self.pg_pool.begin().await?;
+        if !charge_redirect(&mut tx, user_id).await? {
+            return Ok(false);
+        }
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    /// Drop click events older than the link owner's plan keeps analytics for
+    #[instrument(name = "Prune click events", skip(self))]
+    pub async fn prune_click_events(&self) -> anyhow::Result<u64> {
+        let result = sqlx::query!(
+            r#"DELETE FROM click_events e
+            USING urls u, users usr, plans p
+            WHERE e.url_id = u.id
+              AND u.user_id = usr.id
+              AND usr.plan_id = p.id
+              AND p.analytics_retention_days IS NOT NULL
+              AND e.clicked_at < NOW() - make_interval(days => p.analytics_retention_days)"#
+        )
+        .execute(&self.pg_pool)
+        .await?;
+        Ok(result.rows_affected())
+    }
+}
+
+/// Storing a link would take its owner past the plan's active link limit
+#[derive(Debug, thiserror::Error)]
+#[error("active link limit reached")]
+pub struct LinkLimitReached;
+
+/// How many more active links the user may create, None without a limit.
+///
+/// Locks the user's row until `tx` ends, so concurrent link creation for the
+/// same user waits and counts the links stored in the meantime.
+pub async fn link_room(
+    tx: &mut Transaction<'_, Postgres>,
+    user_id: Uuid,
+) -> anyhow::Result<Option<i64>> {
+    let max = sqlx::query_scalar!(
+        r#"SELECT p.max_active_links FROM users u
+        JOIN plans p ON p.id = u.plan_id
+        WHERE u.id = $1
+        FOR UPDATE OF u"#,
+        user_id
+    )
+    .fetch_one(&mut **tx)
+    .await?;
+    let Some(max) = max else {
+        return Ok(None);
+    };
+
+    let active = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "count!" FROM urls
+        WHERE user_id = $1 AND NOT taken_down AND (expires_at IS NULL OR expires_at > NOW())
+            AND (max_clicks IS NULL OR clicks < max_clicks)"#,
+        user_id
+    )
+    .fetch_one(&mut **tx)
+    .await?;
+    Ok(Some((i64::from(max) - active).max(0)))
+}
+
+/// `PlanRepository::record_redirect` as part of a larger transaction, nothing
+/// is counted if `tx` is rolled back
+pub async fn charge_redirect(
+    tx: &mut Transaction<'_, Postgres>,
+    user_id: Uuid,
+) -> anyhow::Result<bool> {
+    // The limit check and the increment happen in one statement so
+    // concurrent redirects cannot overshoot the quota
+    let row = sqlx::query_scalar!(
+        r#"WITH quota AS (
+            SELECT p.monthly_redirects AS monthly_limit
+            FROM users u JOIN plans p ON p.id = u.plan_id
+            WHERE u.id = $1
+        )
+        INSERT INTO redirect_usage AS r (user_id, month, redirects)
+        SELECT $1, date_trunc('month', NOW())::date, 1
+        FROM quota WHERE quota.monthly_limit IS NULL OR quota.monthly_limit > 0
+        ON CONFLICT (user_id, month) DO UPDATE SET redirects = r.redirects + 1
+        WHERE (SELECT monthly_limit FROM quota) IS NULL
+           OR r.redirects < (SELECT monthly_limit FROM quota)
+        RETURNING r.redirects"#,
+        user_id
+    )
+    .fetch_optional(&mut **tx)
+    .await?;
+    if row.is_none() {
+        return Ok(false);
+    }
+
+    usage::record(tx, user_id, UsageMetric::Redirect, 1).await?;
+    Ok(true)
+}
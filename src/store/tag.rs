@@ -0,0 +1,219 @@
+use sqlx::{Pool, Postgres, Transaction};
+use tracing::instrument;
+use uuid::Uuid;
+
+use crate::models::tag::{FolderModel, TagModel};
+
+/// Tags and folders of a workspace. Every query is scoped to the workspace,
+/// ids from another workspace behave like ids that do not exist.
+#[derive(Clone, Debug)]
+pub struct TagRepository {
+    pg_pool: Pool<Postgres>,
+}
+
+impl TagRepository {
+    pub fn new(pg_pool: Pool<Postgres>) -> Self {
+        Self { pg_pool }
+    }
+
+    pub async fn list_tags(&self, workspace_id: Uuid) -> anyhow::Result<Vec<TagModel>> {
+        let tags = sqlx::query_as::<_, TagModel>(
+            r#"SELECT t.id, t.name, COUNT(ut.url_id) AS links
+            FROM tags t
+            LEFT JOIN url_tags ut ON ut.tag_id = t.id
+            WHERE t.workspace_id = $1
+            GROUP BY t.id
+            ORDER BY t.name"#,
+        )
+        .bind(workspace_id)
+        .fetch_all(&self.pg_pool)
+        .await?;
+        Ok(tags)
+    }
+
+    pub async fn list_folders(&self, workspace_id: Uuid) -> anyhow::Result<Vec<FolderModel>> {
+        let folders = sqlx::query_as::<_, FolderModel>(
+            r#"SELECT f.id, f.name, COUNT(u.id) AS links
+            FROM folders f
+            LEFT JOIN urls u ON u.folder_id = f.id
+            WHERE f.workspace_id = $1
+            GROUP BY f.id
+            ORDER BY f.name"#,
+        )
+        .bind(workspace_id)
+        .fetch_all(&self.pg_pool)
+        .await?;
+        Ok(folders)
+    }
+
+    pub async fn create_folder(&self, workspace_id: Uuid, name: &str) -> anyhow::Result<Uuid> {
+        let id = sqlx::query_scalar!(
+            "INSERT INTO folders (workspace_id, name) VALUES ($1, $2) RETURNING id",
+            workspace_id,
+            name
+        )
+        .fetch_one(&self.pg_pool)
+        .await?;
+        Ok(id)
+    }
+
+    pub async fn rename_folder(
+        &self,
+        workspace_id: Uuid,
+        id: Uuid,
+        name: &str,
+    ) -> anyhow::Result<bool> {
+        let result = sqlx::query!(
+            "UPDATE folders SET name = $3 WHERE id = $2 AND workspace_id = $1",
+            workspace_id,
+            id,
+            name
+        )
+        .execute(&self.pg_pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Links in the folder are kept, outside of any folder
+    pub async fn delete_folder(&self, workspace_id: Uuid, id: Uuid) -> anyhow::Result<bool> {
+        let result = sqlx::query!(
+            "DELETE FROM folders WHERE id = $2 AND workspace_id = $1",
+            workspace_id,
+            id
+        )
+        .execute(&self.pg_pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn rename_tag(
+        &self,
+        workspace_id: Uuid,
+        id: Uuid,
+        name: &str,
+    ) -> anyhow::Result<bool> {
+        let result = sqlx::query!(
+            "UPDATE tags SET name = $3 WHERE id = $2 AND workspace_id = $1",
+            workspace_id,
+            id,
+            name
+        )
+        .execute(&self.pg_pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Move every link tagged `source` over to `target` and drop `source`
+    #[instrument(name = "Merge tags", skip(self))]
+    pub async fn merge_tags(
+        &self,
+        workspace_id: Uuid,
+        source: Uuid,
+        target: Uuid,
+    ) -> anyhow::Result<bool> {
+        let mut tx = self.pg_pool.begin().await?;
+        let found = sqlx::query_scalar!(
+            r#"SELECT COUNT(*) AS "count!" FROM tags WHERE workspace_id = $1 AND id = ANY($2)"#,
+            workspace_id,
+            &[source, target][..]
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        if source == target || found != 2 {
+            return Ok(false);
+        }
+
+        sqlx::query!(
+            r#"INSERT INTO url_tags (url_id, tag_id)
+            SELECT url_id, $2 FROM url_tags WHERE tag_id = $1
+            ON CONFLICT DO NOTHING"#,
+            source,
+            target
+        )
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query!("DELETE FROM tags WHERE id = $1", source)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    /// The tag is removed from every link that carries it
+    pub async fn delete_tag(&self, workspace_id: Uuid, id: Uuid) -> anyhow::Result<bool> {
+        let result = sqlx::query!(
+            "DELETE FROM tags WHERE id = $2 AND workspace_id = $1",
+            workspace_id,
+            id
+        )
+        .execute(&self.pg_pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Replace the folder and tags of a link. Returns false when the link is
+    /// not in the workspace.
+    #[instrument(name = "Label link", skip(self))]
+    pub async fn label_link(
+        &self,
+        workspace_id: Uuid,
+        short_code: &str,
+        folder_id: Option<Uuid>,
+        tags: &[String],
+    ) -> anyhow::Result<bool> {
+        let mut tx = self.pg_pool.begin().await?;
+        let url_id = sqlx::query_scalar!(
+            r#"UPDATE urls
+            SET folder_id = (SELECT id FROM folders WHERE id = $3 AND workspace_id = $1)
+            WHERE short_code = $2 AND workspace_id = $1
+            RETURNING id"#,
+            workspace_id,
+            short_code,
+            folder_id as Option<Uuid>
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(url_id) = url_id else {
+            return Ok(false);
+        };
+
+        set_link_tags(&mut tx, url_id, workspace_id, tags).await?;
+        tx.commit().await?;
+        Ok(true)
+    }
+}
+
+/// Make `tags` the exact set of tags on a link, creating missing ones
+pub async fn set_link_tags(
+    tx: &mut Transaction<'_, Postgres>,
+    url_id: Uuid,
+    workspace_id: Uuid,
+    tags: &[String],
+) -> anyhow::Result<()> {
+    sqlx::query!("DELETE FROM url_tags WHERE url_id = $1", url_id)
+        .execute(&mut **tx)
+        .await?;
+    if tags.is_empty() {
+        return Ok(());
+    }
+
+    sqlx::query!(
+        r#"INSERT INTO tags (workspace_id, name)
+        SELECT $1, name FROM UNNEST($2::text[]) AS name
+        ON CONFLICT (workspace_id, name) DO NOTHING"#,
+        workspace_id,
+        tags
+    )
+    .execute(&mut **tx)
+    .await?;
+    sqlx::query!(
+        r#"INSERT INTO url_tags (url_id, tag_id)
+        SELECT $1, id FROM tags WHERE workspace_id = $2 AND name = ANY($3)"#,
+        url_id,
+        workspace_id,
+        tags
+    )
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
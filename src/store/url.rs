@@ -1,9 +1,36 @@
+use std::collections::HashMap;
+
 use redis::AsyncCommands;
-use sqlx::{Pool, Postgres};
+use sqlx::{Connection, Pool, Postgres, QueryBuilder, Transaction};
 use tracing::instrument;
 use uuid::Uuid;
 
-use crate::models::url::UrlModel;
+use crate::{
+    models::{
+        targeting::{RuleEdit, TargetingRule},
+        url::{
+            ClickSource, DailyClicks, LimitedClick, LinkCursor, LinkFilter, LinkSort, LinkStatus,
+            LinkTarget, NewUrl, QueryPassthrough, SortOrder, UrlModel,
+        },
+        usage::UsageMetric,
+        utm::UtmParams,
+    },
+    store::{plan, tag, usage},
+};
+
+/// Select list for `UrlModel`, the links table is aliased `u`
+const URL_SELECT: &str = r#"SELECT u.id, u.short_code, u.site_name, u.long_url, u.user_id, u.workspace_id, u.clicks, u.qr_scans, u.fallback_hits, u.max_clicks,
+    u.taken_down, u.created_at, u.starts_at, u.expires_at, u.fallback_url, u.query_passthrough,
+    u.path_passthrough, u.utm_source, u.utm_medium, u.utm_campaign, u.utm_term, u.utm_content, u.last_clicked_at, u.password_hash IS NOT NULL AS protected,
+    u.folder_id, f.name AS folder,
+    ARRAY(SELECT t.name FROM url_tags ut JOIN tags t ON t.id = ut.tag_id WHERE ut.url_id = u.id ORDER BY t.name) AS tags
+FROM urls u
+LEFT JOIN folders f ON f.id = u.folder_id"#;
+
+/// Select list for `TargetingRule`, the rules table is aliased `r`
+const RULE_SELECT: &str = r#"SELECT r.id, r.url_id, r.destination, r.os, r.device, r.language, r.active_from, r.active_until,
+    r.referrer_host
+FROM link_rules r"#;
 
 #[derive(Clone, Debug)]
 pub struct UrlRepository {
@@ -15,28 +42,76 @@ impl UrlRepository {
         Self { pg_pool }
     }
 
-    pub async fn store(
-        &self,
-        short_code: &str,
-        long_url: &str,
-        site_name: &str,
-        user_id: uuid::Uuid,
-    ) -> anyhow::Result<()> {
-        sqlx::query!(
-            "INSERT INTO urls (short_code, long_url,site_name, user_id) VALUES ($1, $2, $3, $4)",
-            short_code,
-            long_url,
-            site_name,
-            user_id, /* Uuid */
-        )
-        .execute(&self.pg_pool)
-        .await?;
+    /// Fails with [`plan::LinkLimitReached`] when the owner has no room left
+    pub async fn store(&self, url: &NewUrl<'_>) -> anyhow::Result<()> {
+        let mut tx = self.pg_pool.begin().await?;
+        if plan::link_room(&mut tx, url.user_id).await? == Some(0) {
+            return Err(plan::LinkLimitReached.into());
+        }
+        insert(&mut tx, url).await?;
+        tx.commit().await?;
         Ok(())
     }
 
+    /// Store a batch of links in one transaction, returning one outcome per link.
+    ///
+    /// When `atomic`, the first failure rolls the whole batch back and ends it,
+    /// links after it are not attempted. Otherwise every link gets a savepoint
+    /// and only the failed ones are left out. Links past the owner's active link
+    /// limit fail with [`plan::LinkLimitReached`]. All links belong to one owner.
+    #[instrument(name = "Store url batch", skip(self, urls), fields(count = urls.len()))]
+    pub async fn store_many(
+        &self,
+        urls: &[NewUrl<'_>],
+        atomic: bool,
+    ) -> anyhow::Result<Vec<anyhow::Result<()>>> {
+        let mut tx = self.pg_pool.begin().await?;
+        let mut room = match urls.first() {
+            Some(url) => plan::link_room(&mut tx, url.user_id).await?,
+            None => None,
+        };
+        let mut outcomes = Vec::with_capacity(urls.len());
+        for url in urls {
+            if room == Some(0) {
+                outcomes.push(Err(plan::LinkLimitReached.into()));
+                if atomic {
+                    tx.rollback().await?;
+                    return Ok(outcomes);
+                }
+                continue;
+            }
+
+            if atomic {
+                if let Err(e) = insert(&mut tx, url).await {
+                    outcomes.push(Err(e));
+                    tx.rollback().await?;
+                    return Ok(outcomes);
+                }
+                outcomes.push(Ok(()));
+                room = room.map(|left| left - 1);
+                continue;
+            }
+
+            let mut savepoint = (*tx).begin().await?;
+            match insert(&mut savepoint, url).await {
+                Ok(_) => {
+                    savepoint.commit().await?;
+                    outcomes.push(Ok(()));
+                    room = room.map(|left| left - 1);
+                }
+                Err(e) => {
+                    savepoint.rollback().await?;
+                    outcomes.push(Err(e));
+                }
+            }
+        }
+        tx.commit().await?;
+        Ok(outcomes)
+    }
+
     pub async fn fetch(&self, short_code: &str) -> anyhow::Result<Option<String>> {
         let row = sqlx::query!(
-            "SELECT long_url FROM urls WHERE short_code = $1",
+            "SELECT long_url FROM urls WHERE short_code = $1 AND NOT taken_down",
             short_code
         )
         .fetch_optional(&self.pg_pool)
@@ -55,36 +130,669 @@ impl UrlRepository {
         Ok(())
     }
 
-    /// Fetch all URLs belonging to a specific user
-    pub async fn list_by_user(&self, user_id: uuid::Uuid) -> anyhow::Result<Vec<UrlModel>> {
-        let rows = sqlx::query_as::<_, UrlModel>(
-            r#"SELECT short_code,site_name, long_url, user_id, clicks, created_at
-            FROM urls
-            WHERE user_id = $1 
-            ORDER BY created_at DESC
-            "#,
+    /// Fetch up to `limit` URLs of a workspace that match the filter, in the
+    /// filter's order, starting after `cursor`
+    pub async fn list_by_workspace(
+        &self,
+        workspace_id: uuid::Uuid,
+        filter: &LinkFilter,
+        cursor: Option<&LinkCursor>,
+        limit: i64,
+    ) -> anyhow::Result<Vec<UrlModel>> {
+        let mut query = QueryBuilder::<Postgres>::new(URL_SELECT);
+        query
+            .push(" WHERE u.workspace_id = ")
+            .push_bind(workspace_id);
+        if let Some(q) = &filter.q {
+            // Matches the trigram index on the same expression
+            query
+                .push(" AND (u.short_code || ' ' || COALESCE(u.site_name, '') || ' ' || u.long_url) ILIKE ")
+                .push_bind(format!("%{}%", escape_like(q)));
+        }
+        if let Some(tag) = &filter.tag {
+            query
+                .push(" AND EXISTS (SELECT 1 FROM url_tags ut JOIN tags t ON t.id = ut.tag_id WHERE ut.url_id = u.id AND t.name = ")
+                .push_bind(tag.trim().to_lowercase())
+                .push(")");
+        }
+        if let Some(folder) = filter.folder {
+            query.push(" AND u.folder_id = ").push_bind(folder);
+        }
+        match filter.status {
+            Some(LinkStatus::Scheduled) => {
+                query.push(" AND NOT u.taken_down AND u.starts_at > NOW()");
+            }
+            Some(LinkStatus::Active) => {
+                query.push(
+                    " AND NOT u.taken_down AND (u.starts_at IS NULL OR u.starts_at <= NOW()) \
+                    AND (u.expires_at IS NULL OR u.expires_at > NOW()) \
+                    AND (u.max_clicks IS NULL OR u.clicks < u.max_clicks)",
+                );
+            }
+            Some(LinkStatus::Expired) => {
+                // Used up links are over just like expired ones
+                query.push(
+                    " AND NOT u.taken_down AND (u.expires_at <= NOW() OR u.clicks >= u.max_clicks)",
+                );
+            }
+            Some(LinkStatus::TakenDown) => {
+                query.push(" AND u.taken_down");
+            }
+            None => {}
+        }
+        if let Some(from) = filter.from {
+            query
+                .push(" AND u.created_at >= ")
+                .push_bind(from.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc());
+        }
+        if let Some(to) = filter.to.and_then(|to| to.succ_opt()) {
+            query
+                .push(" AND u.created_at < ")
+                .push_bind(to.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc());
+        }
+
+        let (key, cast) = match filter.sort {
+            LinkSort::Created => ("u.created_at", "::timestamptz"),
+            LinkSort::Clicks => ("u.clicks", "::int"),
+            LinkSort::LastClick => (
+                "COALESCE(u.last_clicked_at, 'epoch'::timestamptz)",
+                "::timestamptz",
+            ),
+        };
+        let (direction, after) = match filter.order {
+            SortOrder::Desc => ("DESC", "<"),
+            SortOrder::Asc => ("ASC", ">"),
+        };
+        if let Some(cursor) = cursor {
+            query
+                .push(format_args!(" AND ({}, u.id) {} (", key, after))
+                .push_bind(cursor.key.clone())
+                .push(format_args!("{}, ", cast))
+                .push_bind(cursor.id)
+                .push(")");
+        }
+        query
+            .push(format_args!(
+                " ORDER BY {key} {direction}, u.id {direction} LIMIT "
+            ))
+            .push_bind(limit);
+
+        let mut rows = query
+            .build_query_as::<UrlModel>()
+            .fetch_all(&self.pg_pool)
+            .await?;
+        self.attach_rules(&mut rows).await?;
+        Ok(rows)
+    }
+
+    /// Clicks per UTC day of the given links, oldest day first
+    pub async fn daily_clicks(&self, url_ids: &[Uuid]) -> anyhow::Result<Vec<DailyClicks>> {
+        let rows = sqlx::query_as::<_, DailyClicks>(
+            r#"SELECT url_id, (clicked_at AT TIME ZONE 'UTC')::date AS day,
+                COUNT(*) FILTER (WHERE NOT fallback) AS clicks,
+                COUNT(*) FILTER (WHERE source = 'qr' AND NOT fallback) AS qr_scans,
+                COUNT(*) FILTER (WHERE fallback) AS fallbacks
+            FROM click_events
+            WHERE url_id = ANY($1)
+            GROUP BY url_id, day
+            ORDER BY url_id, day"#,
+        )
+        .bind(url_ids)
+        .fetch_all(&self.pg_pool)
+        .await?;
+        Ok(rows)
+    }
+
+    /// Number of links and clicks across a whole workspace
+    pub async fn workspace_totals(&self, workspace_id: Uuid) -> anyhow::Result<(i64, i64)> {
+        let row = sqlx::query!(
+            r#"SELECT COUNT(*) AS "links!", COALESCE(SUM(clicks), 0)::bigint AS "clicks!"
+            FROM urls WHERE workspace_id = $1"#,
+            workspace_id
         )
+        .fetch_one(&self.pg_pool)
+        .await?;
+        Ok((row.links, row.clicks))
+    }
+
+    /// Fetch all URLs created by a user, across every workspace
+    pub async fn list_by_creator(&self, user_id: Uuid) -> anyhow::Result<Vec<UrlModel>> {
+        let mut rows = sqlx::query_as::<_, UrlModel>(&format!(
+            "{} WHERE u.user_id = $1 ORDER BY u.created_at DESC",
+            URL_SELECT
+        ))
         .bind(user_id)
         .fetch_all(&self.pg_pool)
         .await?;
+        self.attach_rules(&mut rows).await?;
         Ok(rows)
     }
-    pub async fn fetch_with_owner(
+
+    /// Fill in the targeting rules of listed links with a single query
+    async fn attach_rules(&self, urls: &mut [UrlModel]) -> anyhow::Result<()> {
+        if urls.is_empty() {
+            return Ok(());
+        }
+        let url_ids: Vec<Uuid> = urls.iter().map(|url| url.id).collect();
+        let rules = sqlx::query_as::<_, TargetingRule>(&format!(
+            "{} WHERE r.url_id = ANY($1) ORDER BY r.url_id, r.position",
+            RULE_SELECT
+        ))
+        .bind(&url_ids)
+        .fetch_all(&self.pg_pool)
+        .await?;
+        let mut by_url: HashMap<Uuid, Vec<TargetingRule>> = HashMap::new();
+        for rule in rules {
+            by_url.entry(rule.url_id).or_default().push(rule);
+        }
+        for url in urls {
+            url.rules = by_url.remove(&url.id).unwrap_or_default();
+        }
+        Ok(())
+    }
+
+    /// Targeting rules of a link of the workspace in the order they are
+    /// tried, none when the link is not in the workspace
+    pub async fn rules(
         &self,
+        workspace_id: Uuid,
         short_code: &str,
-    ) -> anyhow::Result<Option<(String, Option<Uuid>)>> {
-        let row = sqlx::query!(
-            r#"UPDATE urls 
-            SET clicks = clicks + 1
-            WHERE short_code = $1
-            RETURNING long_url, user_id;"#,
+    ) -> anyhow::Result<Option<Vec<TargetingRule>>> {
+        let url_id = sqlx::query_scalar!(
+            "SELECT id FROM urls WHERE short_code = $2 AND workspace_id = $1",
+            workspace_id,
             short_code
         )
         .fetch_optional(&self.pg_pool)
         .await?;
+        let Some(url_id) = url_id else {
+            return Ok(None);
+        };
+        let rules = sqlx::query_as::<_, TargetingRule>(&format!(
+            "{} WHERE r.url_id = $1 ORDER BY r.position",
+            RULE_SELECT
+        ))
+        .bind(url_id)
+        .fetch_all(&self.pg_pool)
+        .await?;
+        Ok(Some(rules))
+    }
+
+    /// Replace the targeting rules of a link of the workspace, keeping their
+    /// order. Returns false when the link is not in the workspace.
+    #[instrument(name = "Set link rules", skip(self, rules), fields(count = rules.len()))]
+    pub async fn set_rules(
+        &self,
+        workspace_id: Uuid,
+        short_code: &str,
+        rules: &[TargetingRule],
+    ) -> anyhow::Result<bool> {
+        let mut tx = self.pg_pool.begin().await?;
+        let Some(url_id) = lock_for_rules(&mut tx, workspace_id, short_code).await? else {
+            return Ok(false);
+        };
+
+        sqlx::query!("DELETE FROM link_rules WHERE url_id = $1", url_id)
+            .execute(&mut *tx)
+            .await?;
+        for (position, rule) in rules.iter().enumerate() {
+            insert_rule(&mut tx, url_id, position as i32, rule).await?;
+        }
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    /// Add a targeting rule after the existing ones of a link of the
+    /// workspace, unless it already has `limit` rules
+    #[instrument(name = "Add link rule", skip(self, rule))]
+    pub async fn add_rule(
+        &self,
+        workspace_id: Uuid,
+        short_code: &str,
+        rule: &TargetingRule,
+        limit: usize,
+    ) -> anyhow::Result<RuleEdit> {
+        let mut tx = self.pg_pool.begin().await?;
+        let Some(url_id) = lock_for_rules(&mut tx, workspace_id, short_code).await? else {
+            return Ok(RuleEdit::NotFound);
+        };
+
+        let (count, next): (i64, i32) = sqlx::query_as(
+            "SELECT COUNT(*), COALESCE(MAX(position) + 1, 0) FROM link_rules WHERE url_id = $1",
+        )
+        .bind(url_id)
+        .fetch_one(&mut *tx)
+        .await?;
+        if count as usize >= limit {
+            return Ok(RuleEdit::Full);
+        }
+        insert_rule(&mut tx, url_id, next, rule).await?;
+        tx.commit().await?;
+        Ok(RuleEdit::Applied)
+    }
+
+    /// Remove a targeting rule from a link of the workspace. The others keep
+    /// their positions, only their order matters.
+    #[instrument(name = "Delete link rule", skip(self))]
+    pub async fn delete_rule(
+        &self,
+        workspace_id: Uuid,
+        short_code: &str,
+        rule_id: Uuid,
+    ) -> anyhow::Result<RuleEdit> {
+        let mut tx = self.pg_pool.begin().await?;
+        let Some(url_id) = lock_for_rules(&mut tx, workspace_id, short_code).await? else {
+            return Ok(RuleEdit::NotFound);
+        };
+
+        let result = sqlx::query!(
+            "DELETE FROM link_rules WHERE id = $1 AND url_id = $2",
+            rule_id,
+            url_id
+        )
+        .execute(&mut *tx)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Ok(RuleEdit::NotFound);
+        }
+        tx.commit().await?;
+        Ok(RuleEdit::Applied)
+    }
+
+    /// Swap a targeting rule of a link of the workspace with the one tried
+    /// before it, or after it when `earlier` is false. Moving the first rule
+    /// up or the last one down changes nothing.
+    #[instrument(name = "Move link rule", skip(self))]
+    pub async fn move_rule(
+        &self,
+        workspace_id: Uuid,
+        short_code: &str,
+        rule_id: Uuid,
+        earlier: bool,
+    ) -> anyhow::Result<RuleEdit> {
+        let mut tx = self.pg_pool.begin().await?;
+        let Some(url_id) = lock_for_rules(&mut tx, workspace_id, short_code).await? else {
+            return Ok(RuleEdit::NotFound);
+        };
+
+        let position = sqlx::query_scalar!(
+            "SELECT position FROM link_rules WHERE id = $1 AND url_id = $2",
+            rule_id,
+            url_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(position) = position else {
+            return Ok(RuleEdit::NotFound);
+        };
+        let neighbour: Option<(Uuid, i32)> = sqlx::query_as(if earlier {
+            "SELECT id, position FROM link_rules WHERE url_id = $1 AND position < $2 ORDER BY position DESC LIMIT 1"
+        } else {
+            "SELECT id, position FROM link_rules WHERE url_id = $1 AND position > $2 ORDER BY position LIMIT 1"
+        })
+        .bind(url_id)
+        .bind(position)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some((neighbour_id, neighbour_position)) = neighbour else {
+            return Ok(RuleEdit::Applied);
+        };
+
+        // Positions are unique per link, the rule steps aside while its
+        // neighbour takes its place
+        for (id, position) in [
+            (rule_id, -1),
+            (neighbour_id, position),
+            (rule_id, neighbour_position),
+        ] {
+            sqlx::query!(
+                "UPDATE link_rules SET position = $2 WHERE id = $1",
+                id,
+                position
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(RuleEdit::Applied)
+    }
+
+    /// Look up where a link points, without counting a click
+    pub async fn find_target(&self, short_code: &str) -> anyhow::Result<Option<LinkTarget>> {
+        let target = sqlx::query_as::<_, LinkTarget>(
+            r#"SELECT long_url, user_id, starts_at, expires_at, fallback_url, query_passthrough, path_passthrough,
+                utm_source, utm_medium, utm_campaign, utm_term, utm_content, password_hash IS NOT NULL AS protected,
+                max_clicks
+            FROM urls WHERE short_code = $1 AND NOT taken_down"#,
+        )
+        .bind(short_code)
+        .fetch_optional(&self.pg_pool)
+        .await?;
+        let Some(mut target) = target else {
+            return Ok(None);
+        };
+        target.rules = sqlx::query_as::<_, TargetingRule>(&format!(
+            "{} JOIN urls u ON u.id = r.url_id WHERE u.short_code = $1 ORDER BY r.position",
+            RULE_SELECT
+        ))
+        .bind(short_code)
+        .fetch_all(&self.pg_pool)
+        .await?;
+        Ok(Some(target))
+    }
 
-        Ok(row.map(|r| (r.long_url, r.user_id)))
+    /// Password hash of a protected link, none when the link is missing,
+    /// taken down or not protected
+    pub async fn password_hash(&self, short_code: &str) -> anyhow::Result<Option<String>> {
+        let hash = sqlx::query_scalar!(
+            "SELECT password_hash FROM urls WHERE short_code = $1 AND NOT taken_down",
+            short_code
+        )
+        .fetch_optional(&self.pg_pool)
+        .await?;
+        Ok(hash.flatten())
     }
+
+    /// Protect a link of the workspace with a password, or lift the protection
+    /// with `None`. Returns false when the link is not in the workspace.
+    pub async fn set_password(
+        &self,
+        workspace_id: Uuid,
+        short_code: &str,
+        password_hash: Option<&str>,
+    ) -> anyhow::Result<bool> {
+        let result = sqlx::query!(
+            "UPDATE urls SET password_hash = $3 WHERE short_code = $2 AND workspace_id = $1",
+            workspace_id,
+            short_code,
+            password_hash
+        )
+        .execute(&self.pg_pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Set or clear the fallback of a link of the workspace. Returns false
+    /// when the link is not in the workspace.
+    pub async fn set_fallback_url(
+        &self,
+        workspace_id: Uuid,
+        short_code: &str,
+        fallback_url: Option<&str>,
+    ) -> anyhow::Result<bool> {
+        let result = sqlx::query!(
+            "UPDATE urls SET fallback_url = $3 WHERE short_code = $2 AND workspace_id = $1",
+            workspace_id,
+            short_code,
+            fallback_url
+        )
+        .execute(&self.pg_pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Set how visits are forwarded to a link of the workspace. Returns false
+    /// when the link is not in the workspace.
+    pub async fn set_passthrough(
+        &self,
+        workspace_id: Uuid,
+        short_code: &str,
+        query_passthrough: QueryPassthrough,
+        path_passthrough: bool,
+    ) -> anyhow::Result<bool> {
+        let result = sqlx::query(
+            r#"UPDATE urls SET query_passthrough = $3, path_passthrough = $4
+            WHERE short_code = $2 AND workspace_id = $1"#,
+        )
+        .bind(workspace_id)
+        .bind(short_code)
+        .bind(query_passthrough)
+        .bind(path_passthrough)
+        .execute(&self.pg_pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Replace the UTM parameters of a link of the workspace. Returns false
+    /// when the link is not in the workspace.
+    pub async fn set_utm(
+        &self,
+        workspace_id: Uuid,
+        short_code: &str,
+        utm: &UtmParams,
+    ) -> anyhow::Result<bool> {
+        let result = sqlx::query!(
+            r#"UPDATE urls SET utm_source = $3, utm_medium = $4, utm_campaign = $5, utm_term = $6, utm_content = $7
+            WHERE short_code = $2 AND workspace_id = $1"#,
+            workspace_id,
+            short_code,
+            utm.utm_source,
+            utm.utm_medium,
+            utm.utm_campaign,
+            utm.utm_term,
+            utm.utm_content
+        )
+        .execute(&self.pg_pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Count a click on a link without a click limit and keep it as an event
+    /// for analytics, limited links go through `record_limited_click`. The
+    /// `max_clicks` guard is only a safety net against counting past a limit.
+    /// Returns false when nothing was counted, e.g. the link is gone.
+    #[instrument(name = "Record click", skip(self))]
+    pub async fn record_click(
+        &self,
+        short_code: &str,
+        source: ClickSource,
+    ) -> anyhow::Result<bool> {
+        let result = sqlx::query(
+            r#"WITH clicked AS (
+                UPDATE urls
+                SET clicks = clicks + 1,
+                    qr_scans = qr_scans + CASE WHEN $2 = 'qr'::click_source THEN 1 ELSE 0 END,
+                    last_clicked_at = NOW()
+                WHERE short_code = $1 AND (max_clicks IS NULL OR clicks < max_clicks)
+                RETURNING id
+            )
+            INSERT INTO click_events (url_id, source) SELECT id, $2 FROM clicked"#,
+        )
+        .bind(short_code)
+        .bind(source)
+        .execute(&self.pg_pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Claim a click on a link with a click limit and charge it to `owner`'s
+    /// quota in one transaction. Nothing is counted unless both go through.
+    pub async fn record_limited_click(
+        &self,
+        short_code: &str,
+        source: ClickSource,
+        owner: Option<Uuid>,
+    ) -> anyhow::Result<LimitedClick> {
+        let mut tx = self.pg_pool.begin().await?;
+        // The row stays locked until the commit, so the limit holds under
+        // concurrent visits
+        let url_id: Option<Uuid> = sqlx::query_scalar(
+            r#"UPDATE urls
+            SET clicks = clicks + 1,
+                qr_scans = qr_scans + CASE WHEN $2 = 'qr'::click_source THEN 1 ELSE 0 END,
+                last_clicked_at = NOW()
+            WHERE short_code = $1 AND (max_clicks IS NULL OR clicks < max_clicks)
+            RETURNING id"#,
+        )
+        .bind(short_code)
+        .bind(source)
+        .fetch_optional(&mut *tx)
+        .await?;
+        let Some(url_id) = url_id else {
+            return Ok(LimitedClick::Exhausted);
+        };
+        if let Some(owner) = owner
+            && !plan::charge_redirect(&mut tx, owner).await?
+        {
+            return Ok(LimitedClick::QuotaExceeded);
+        }
+        sqlx::query("INSERT INTO click_events (url_id, source) VALUES ($1, $2)")
+            .bind(url_id)
+            .bind(source)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(LimitedClick::Counted)
+    }
+
+    /// Count a visit that was sent to the fallback url instead
+    pub async fn record_fallback(
+        &self,
+        short_code: &str,
+        source: ClickSource,
+    ) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"WITH served AS (
+                UPDATE urls SET fallback_hits = fallback_hits + 1
+                WHERE short_code = $1
+                RETURNING id
+            )
+            INSERT INTO click_events (url_id, source, fallback) SELECT id, $2, true FROM served"#,
+        )
+        .bind(short_code)
+        .bind(source)
+        .execute(&self.pg_pool)
+        .await?;
+        Ok(())
+    }
+
+    /// The owner's fallback for links without one of their own
+    pub async fn account_fallback(&self, user_id: Uuid) -> anyhow::Result<Option<String>> {
+        let fallback_url =
+            sqlx::query_scalar!("SELECT fallback_url FROM users WHERE id = $1", user_id)
+                .fetch_optional(&self.pg_pool)
+                .await?;
+        Ok(fallback_url.flatten())
+    }
+
+    /// Search every URL in the system by short code, site name or destination (admin only)
+    pub async fn search(&self, query: &str, limit: i64) -> anyhow::Result<Vec<UrlModel>> {
+        let pattern = format!("%{}%", query);
+        let mut rows = sqlx::query_as::<_, UrlModel>(&format!(
+            r#"{}
+            WHERE u.short_code ILIKE $1 OR u.site_name ILIKE $1 OR u.long_url ILIKE $1
+            ORDER BY u.created_at DESC
+            LIMIT $2"#,
+            URL_SELECT
+        ))
+        .bind(pattern)
+        .bind(limit)
+        .fetch_all(&self.pg_pool)
+        .await?;
+        self.attach_rules(&mut rows).await?;
+        Ok(rows)
+    }
+
+    #[instrument(name = "Set url taken down flag", skip(self))]
+    pub async fn set_taken_down(
+        &self,
+        short_code: &str,
+        taken_down: bool,
+    ) -> anyhow::Result<Option<Option<Uuid>>> {
+        let row = sqlx::query!(
+            "UPDATE urls SET taken_down = $2 WHERE short_code = $1 RETURNING user_id",
+            short_code,
+            taken_down
+        )
+        .fetch_optional(&self.pg_pool)
+        .await?;
+        Ok(row.map(|r| r.user_id))
+    }
+}
+
+/// Insert a link with its tags and meter its creation
+async fn insert(tx: &mut Transaction<'_, Postgres>, url: &NewUrl<'_>) -> anyhow::Result<Uuid> {
+    let url_id: Uuid = sqlx::query_scalar(
+        r#"INSERT INTO urls (short_code, long_url,site_name, user_id, workspace_id, expires_at, folder_id, password_hash,
+            max_clicks, starts_at, fallback_url, query_passthrough, path_passthrough, utm_source, utm_medium, utm_campaign,
+            utm_term, utm_content)
+        VALUES ($1, $2, $3, $4, $5, $6, (SELECT id FROM folders WHERE id = $7 AND workspace_id = $5), $8, $9, $10, $11,
+            $12, $13, $14, $15, $16, $17, $18)
+        RETURNING id"#,
+    )
+    .bind(url.short_code)
+    .bind(url.long_url)
+    .bind(url.site_name)
+    .bind(url.user_id)
+    .bind(url.workspace_id)
+    .bind(url.expires_at)
+    .bind(url.folder_id)
+    .bind(url.password_hash)
+    .bind(url.max_clicks)
+    .bind(url.starts_at)
+    .bind(url.fallback_url)
+    .bind(url.query_passthrough)
+    .bind(url.path_passthrough)
+    .bind(&url.utm.utm_source)
+    .bind(&url.utm.utm_medium)
+    .bind(&url.utm.utm_campaign)
+    .bind(&url.utm.utm_term)
+    .bind(&url.utm.utm_content)
+    .fetch_one(&mut **tx)
+    .await?;
+    tag::set_link_tags(tx, url_id, url.workspace_id, url.tags).await?;
+    usage::record(tx, url.user_id, UsageMetric::LinkCreated, 1).await?;
+    Ok(url_id)
+}
+
+/// Escape the wildcards of a `LIKE` pattern so user input matches literally
+pub(crate) fn escape_like(input: &str) -> String {
+    input
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+/// Lock a link of the workspace for changes to its rules, which keeps
+/// concurrent edits apart. None when the link is not in the workspace.
+async fn lock_for_rules(
+    tx: &mut Transaction<'_, Postgres>,
+    workspace_id: Uuid,
+    short_code: &str,
+) -> anyhow::Result<Option<Uuid>> {
+    let url_id = sqlx::query_scalar!(
+        "SELECT id FROM urls WHERE short_code = $2 AND workspace_id = $1 FOR UPDATE",
+        workspace_id,
+        short_code
+    )
+    .fetch_optional(&mut **tx)
+    .await?;
+    Ok(url_id)
+}
+
+async fn insert_rule(
+    tx: &mut Transaction<'_, Postgres>,
+    url_id: Uuid,
+    position: i32,
+    rule: &TargetingRule,
+) -> anyhow::Result<()> {
+    sqlx::query(
+        r#"INSERT INTO link_rules (url_id, position, destination, os, device, language, active_from,
+            active_until, referrer_host)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)"#,
+    )
+    .bind(url_id)
+    .bind(position)
+    .bind(&rule.destination)
+    .bind(rule.os)
+    .bind(rule.device)
+    .bind(&rule.language)
+    .bind(rule.active_from)
+    .bind(rule.active_until)
+    .bind(&rule.referrer_host)
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
 }
 
 #[derive(Clone, Debug)]
@@ -102,6 +810,12 @@ impl CacheRepository {
         conn.get(key).await.ok()
     }
 
+    pub async fn delete(&self, key: &str) -> anyhow::Result<()> {
+        let mut conn = self.redis_pool.get().await?;
+        let _: () = conn.del(key).await?;
+        Ok(())
+    }
+
     pub async fn set(&self, key: &str, value: &str) -> anyhow::Result<()> {
         let mut conn = self.redis_pool.get().await?;
         conn.set_ex::<&str, &str, u64>(key, value, 3600).await?; // 1 hour TTL
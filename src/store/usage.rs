@@ -0,0 +1,58 @@
+use chrono::{DateTime, Utc};
+use sqlx::{Pool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::models::usage::{UsageMetric, UsageRecord};
+
+#[derive(Clone, Debug)]
+pub struct UsageRepository {
+    pg_pool: Pool<Postgres>,
+}
+
+impl UsageRepository {
+    pub fn new(pg_pool: Pool<Postgres>) -> Self {
+        Self { pg_pool }
+    }
+
+    /// Records for the hours starting in `[from, to)`
+    pub async fn list(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<UsageRecord>> {
+        let records = sqlx::query_as::<_, UsageRecord>(
+            r#"SELECT
+                user_id::text || ':' || to_char(hour AT TIME ZONE 'UTC', 'YYYY-MM-DD"T"HH24') || ':' || metric::text AS id,
+                user_id, hour, metric, quantity
+            FROM usage_hourly
+            WHERE hour >= $1 AND hour < $2
+            ORDER BY hour, user_id, metric"#,
+        )
+        .bind(from)
+        .bind(to)
+        .fetch_all(&self.pg_pool)
+        .await?;
+        Ok(records)
+    }
+}
+
+/// Meter usage inside the transaction that performs it, so usage is recorded
+/// exactly when the work it bills for is committed
+pub async fn record(
+    tx: &mut Transaction<'_, Postgres>,
+    user_id: Uuid,
+    metric: UsageMetric,
+    quantity: i64,
+) -> anyhow::Result<()> {
+    sqlx::query(
+        r#"INSERT INTO usage_hourly (user_id, hour, metric, quantity)
+        VALUES ($1, date_trunc('hour', NOW()), $2, $3)
+        ON CONFLICT (user_id, hour, metric) DO UPDATE SET quantity = usage_hourly.quantity + EXCLUDED.quantity"#,
+    )
+    .bind(user_id)
+    .bind(metric)
+    .bind(quantity)
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
@@ -1,5 +1,11 @@
-use crate::models::user::UserModel;
-use sqlx::{Pool, Postgres};
+use crate::{
+    models::{
+        privacy::ErasureSummary,
+        user::{Role, UserModel},
+    },
+    store::url::escape_like,
+};
+use sqlx::{Pool, Postgres, Transaction};
 use tracing::instrument;
 use uuid::Uuid;
 
@@ -12,26 +18,94 @@ impl UserRepository {
     pub fn new(pool: Pool<Postgres>) -> Self {
         Self { pool }
     }
+    /// Insert a user together with the personal workspace that owns their links
     #[instrument(name = "Saving new user to database", skip(self, password_hash))]
     pub async fn create_user(&self, email: &str, password_hash: &str) -> anyhow::Result<Uuid> {
+        let mut tx = self.pool.begin().await?;
         let rec = sqlx::query!(
             "INSERT INTO users (email, password_hash) VALUES ($1, $2) RETURNING id",
             email,
             password_hash
         )
-        .fetch_one(&self.pool)
+        .fetch_one(&mut *tx)
         .await
         .map_err(|e| {
             tracing::error!("Failed to execute query: {:?}", e);
             e
         })?;
+
+        create_personal_workspace(&mut tx, rec.id).await?;
+        tx.commit().await?;
+        Ok(rec.id)
+    }
+
+    /// Provision a password-less user that signs in through an OIDC provider
+    #[instrument(name = "Saving new OIDC user to database", skip(self))]
+    pub async fn create_oidc_user(
+        &self,
+        email: &str,
+        issuer: &str,
+        subject: &str,
+    ) -> anyhow::Result<Uuid> {
+        let mut tx = self.pool.begin().await?;
+        let rec = sqlx::query!(
+            "INSERT INTO users (email, oidc_issuer, oidc_subject) VALUES ($1, $2, $3) RETURNING id",
+            email,
+            issuer,
+            subject
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        create_personal_workspace(&mut tx, rec.id).await?;
+        tx.commit().await?;
         Ok(rec.id)
     }
 
+    #[instrument(name = "Fetching user by OIDC identity from database", skip(self))]
+    pub async fn find_by_oidc_subject(
+        &self,
+        issuer: &str,
+        subject: &str,
+    ) -> anyhow::Result<Option<UserModel>> {
+        let user = sqlx::query_as::<_, UserModel>(
+            r#"SELECT id, email, pending_email, display_name, password_hash, role, disabled, session_version, plan_id, fallback_url, created_at
+            FROM users
+            WHERE oidc_issuer = $1 AND oidc_subject = $2"#,
+        )
+        .bind(issuer)
+        .bind(subject)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(user)
+    }
+
+    /// Link an OIDC identity to a user. Returns false, changing nothing, when
+    /// the user is already linked to a different one.
+    #[instrument(name = "Linking OIDC identity to user", skip(self))]
+    pub async fn link_oidc_subject(
+        &self,
+        id: Uuid,
+        issuer: &str,
+        subject: &str,
+    ) -> anyhow::Result<bool> {
+        let result = sqlx::query!(
+            r#"UPDATE users SET oidc_issuer = $2, oidc_subject = $3
+            WHERE id = $1
+              AND (oidc_subject IS NULL OR (oidc_issuer = $2 AND oidc_subject = $3))"#,
+            id,
+            issuer,
+            subject
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
     #[instrument(name = "Fetching user by email from database", skip(self))]
     pub async fn find_by_email(&self, email: &str) -> anyhow::Result<Option<UserModel>> {
         let user = sqlx::query_as::<_, UserModel>(
-            r#"SELECT id, email, password_hash, created_at FROM users WHERE email = $1"#,
+            r#"SELECT id, email, pending_email, display_name, password_hash, role, disabled, session_version, plan_id, fallback_url, created_at FROM users WHERE email = $1"#,
         )
         .bind(email)
         .fetch_optional(&self.pool)
@@ -43,6 +117,225 @@ impl UserRepository {
         Ok(user)
     }
 
+    #[instrument(name = "Fetching user by id from database", skip(self))]
+    pub async fn find_by_id(&self, id: Uuid) -> anyhow::Result<Option<UserModel>> {
+        let user = sqlx::query_as::<_, UserModel>(
+            r#"SELECT id, email, pending_email, display_name, password_hash, role, disabled, session_version, plan_id, fallback_url, created_at FROM users WHERE id = $1"#,
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(user)
+    }
+
+    /// List users whose email contains `query`, newest first
+    #[instrument(name = "Searching users by email", skip(self))]
+    pub async fn search(&self, query: &str, limit: i64) -> anyhow::Result<Vec<UserModel>> {
+        let users = sqlx::query_as::<_, UserModel>(
+            r#"SELECT id, email, pending_email, display_name, password_hash, role, disabled, session_version, plan_id, fallback_url, created_at
+            FROM users
+            WHERE email ILIKE $1
+            ORDER BY created_at DESC
+            LIMIT $2"#,
+        )
+        .bind(format!("%{}%", escape_like(query)))
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(users)
+    }
+
+    #[instrument(name = "Updating user disabled flag", skip(self))]
+    pub async fn set_disabled(&self, id: Uuid, disabled: bool) -> anyhow::Result<bool> {
+        let result = sqlx::query!("UPDATE users SET disabled = $2 WHERE id = $1", id, disabled)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    #[instrument(name = "Updating user role", skip(self))]
+    pub async fn set_role(&self, id: Uuid, role: Role) -> anyhow::Result<bool> {
+        let result = sqlx::query!("UPDATE users SET role = $2 WHERE id = $1", id, role as Role)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    #[instrument(name = "Updating user display name", skip(self))]
+    pub async fn set_display_name(
+        &self,
+        id: Uuid,
+        display_name: Option<&str>,
+    ) -> anyhow::Result<()> {
+        sqlx::query!(
+            "UPDATE users SET display_name = $2 WHERE id = $1",
+            id,
+            display_name
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    #[instrument(name = "Updating user fallback url", skip(self))]
+    pub async fn set_fallback_url(
+        &self,
+        id: Uuid,
+        fallback_url: Option<&str>,
+    ) -> anyhow::Result<()> {
+        sqlx::query!(
+            "UPDATE users SET fallback_url = $2 WHERE id = $1",
+            id,
+            fallback_url
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    #[instrument(name = "Storing pending email change", skip(self, token))]
+    pub async fn set_pending_email(
+        &self,
+        id: Uuid,
+        email: &str,
+        token: &str,
+        expires_at: chrono::DateTime<chrono::Utc>,
+    ) -> anyhow::Result<()> {
+        sqlx::query(
+            r#"UPDATE users
+            SET pending_email = $2, email_change_token = $3, email_change_expires_at = $4
+            WHERE id = $1"#,
+        )
+        .bind(id)
+        .bind(email)
+        .bind(token)
+        .bind(expires_at)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Swap in the pending email if `token` matches and has not expired.
+    /// Returns the new address, or `None` when the token is not valid.
+    #[instrument(name = "Confirming email change", skip(self, token))]
+    pub async fn confirm_email_change(
+        &self,
+        id: Uuid,
+        token: &str,
+    ) -> anyhow::Result<Option<String>> {
+        let rec = sqlx::query!(
+            r#"UPDATE users
+            SET email = pending_email,
+                pending_email = NULL,
+                email_change_token = NULL,
+                email_change_expires_at = NULL
+            WHERE id = $1
+              AND pending_email IS NOT NULL
+              AND email_change_token = $2
+              AND email_change_expires_at > now()
+            RETURNING email"#,
+            id,
+            token
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(rec.map(|r| r.email))
+    }
+
+    /// Store a new password hash and bump the session version so every
+    /// token issued before the change stops working
+    #[instrument(name = "Updating user password", skip(self, password_hash))]
+    pub async fn update_password(&self, id: Uuid, password_hash: &str) -> anyhow::Result<i32> {
+        let rec = sqlx::query!(
+            r#"UPDATE users
+            SET password_hash = $2, session_version = session_version + 1
+            WHERE id = $1
+            RETURNING session_version"#,
+            id,
+            password_hash
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(rec.session_version)
+    }
+
+    /// Names of shared workspaces that would be left without an owner
+    /// if this user went away
+    pub async fn solely_owned_shared_workspaces(&self, id: Uuid) -> anyhow::Result<Vec<String>> {
+        let rows = sqlx::query!(
+            r#"SELECT w.name
+            FROM workspaces w
+            JOIN workspace_members m ON m.workspace_id = w.id
+            WHERE m.user_id = $1 AND m.role = 'owner'
+              AND EXISTS (
+                SELECT 1 FROM workspace_members o
+                WHERE o.workspace_id = w.id AND o.user_id <> $1
+              )
+              AND NOT EXISTS (
+                SELECT 1 FROM workspace_members o
+                WHERE o.workspace_id = w.id AND o.user_id <> $1 AND o.role = 'owner'
+              )
+            ORDER BY w.name"#,
+            id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.into_iter().map(|r| r.name).collect())
+    }
+
+    /// Delete a user and every workspace where they are the only member.
+    ///
+    /// Links in those workspaces are deleted when `delete_links` is set,
+    /// otherwise they are orphaned and keep redirecting. Links the user created
+    /// in shared workspaces stay with the team. Returns the deleted short codes.
+    #[instrument(name = "Deleting user account", skip(self))]
+    pub async fn delete_account(
+        &self,
+        id: Uuid,
+        delete_links: bool,
+    ) -> anyhow::Result<Vec<String>> {
+        let mut tx = self.pool.begin().await?;
+        let removed = remove_account(&mut tx, id, delete_links).await?;
+        tx.commit().await?;
+        Ok(removed.deleted)
+    }
+
+    /// Erase a user for a privacy request: delete their links, anonymize the
+    /// shared links they created, drop invitations addressed to them and
+    /// record an audit entry without any personal data.
+    #[instrument(name = "Erasing user account", skip(self))]
+    pub async fn erase_account(&self, id: Uuid) -> anyhow::Result<ErasureSummary> {
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query!(
+            r#"DELETE FROM workspace_invitations
+            WHERE email = (SELECT email FROM users WHERE id = $1)"#,
+            id
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        let removed = remove_account(&mut tx, id, true).await?;
+        let summary = ErasureSummary {
+            links_deleted: removed.deleted.len() as i32,
+            links_anonymized: removed.anonymized as i32,
+            deleted_short_codes: removed.deleted,
+        };
+
+        sqlx::query!(
+            r#"INSERT INTO account_erasures (user_id, links_deleted, links_anonymized)
+            VALUES ($1, $2, $3)"#,
+            id,
+            summary.links_deleted,
+            summary.links_anonymized
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(summary)
+    }
+
     pub async fn create_user_old(&self, email: &str, password_hash: &str) -> anyhow::Result<Uuid> {
         let rec = sqlx::query!(
             "INSERT INTO users (email, password_hash) VALUES ($1, $2) RETURNING id",
@@ -64,3 +357,91 @@ impl UserRepository {
         Ok(user)
     }
 }
+
+async fn create_personal_workspace(
+    tx: &mut Transaction<'_, Postgres>,
+    user_id: Uuid,
+) -> anyhow::Result<()> {
+    let workspace = sqlx::query!(
+        "INSERT INTO workspaces (name, personal, created_by) VALUES ('Personal', TRUE, $1) RETURNING id",
+        user_id
+    )
+    .fetch_one(&mut **tx)
+    .await?;
+    sqlx::query!(
+        "INSERT INTO workspace_members (workspace_id, user_id, role) VALUES ($1, $2, 'owner')",
+        workspace.id,
+        user_id
+    )
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+struct RemovedAccount {
+    deleted: Vec<String>,
+    anonymized: u64,
+}
+
+/// Remove the user row together with the workspaces only they belong to
+async fn remove_account(
+    tx: &mut Transaction<'_, Postgres>,
+    id: Uuid,
+    delete_links: bool,
+) -> anyhow::Result<RemovedAccount> {
+    let workspace_ids: Vec<Uuid> = sqlx::query!(
+        r#"SELECT m.workspace_id
+        FROM workspace_members m
+        WHERE m.user_id = $1
+          AND NOT EXISTS (
+            SELECT 1 FROM workspace_members o
+            WHERE o.workspace_id = m.workspace_id AND o.user_id <> $1
+          )"#,
+        id
+    )
+    .fetch_all(&mut **tx)
+    .await?
+    .into_iter()
+    .map(|r| r.workspace_id)
+    .collect();
+
+    let deleted = if delete_links {
+        sqlx::query!(
+            r#"DELETE FROM urls
+            WHERE workspace_id = ANY($2) OR (workspace_id IS NULL AND user_id = $1)
+            RETURNING short_code"#,
+            id,
+            &workspace_ids
+        )
+        .fetch_all(&mut **tx)
+        .await?
+        .into_iter()
+        .map(|r| r.short_code)
+        .collect()
+    } else {
+        sqlx::query!(
+            "UPDATE urls SET workspace_id = NULL WHERE workspace_id = ANY($1)",
+            &workspace_ids
+        )
+        .execute(&mut **tx)
+        .await?;
+        Vec::new()
+    };
+
+    // Whatever is left belongs to someone else now
+    let anonymized = sqlx::query!("UPDATE urls SET user_id = NULL WHERE user_id = $1", id)
+        .execute(&mut **tx)
+        .await?
+        .rows_affected();
+    sqlx::query!("DELETE FROM workspaces WHERE id = ANY($1)", &workspace_ids)
+        .execute(&mut **tx)
+        .await?;
+    sqlx::query!("DELETE FROM users WHERE id = $1", id)
+        .execute(&mut **tx)
+        .await?;
+
+    Ok(RemovedAccount {
+        deleted,
+        anonymized,
+    })
+}
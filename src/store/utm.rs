@@ -0,0 +1,81 @@
+use sqlx::{Pool, Postgres};
+use uuid::Uuid;
+
+use crate::models::utm::{UtmParams, UtmPreset};
+
+/// UTM presets of a user. Every query is scoped to the user, ids of another
+/// user's presets behave like ids that do not exist.
+#[derive(Clone, Debug)]
+pub struct UtmRepository {
+    pg_pool: Pool<Postgres>,
+}
+
+impl UtmRepository {
+    pub fn new(pg_pool: Pool<Postgres>) -> Self {
+        Self { pg_pool }
+    }
+
+    pub async fn list(&self, user_id: Uuid) -> anyhow::Result<Vec<UtmPreset>> {
+        let presets = sqlx::query_as::<_, UtmPreset>(
+            r#"SELECT id, name, utm_source, utm_medium, utm_campaign, utm_term, utm_content, created_at
+            FROM utm_presets
+            WHERE user_id = $1
+            ORDER BY name"#,
+        )
+        .bind(user_id)
+        .fetch_all(&self.pg_pool)
+        .await?;
+        Ok(presets)
+    }
+
+    pub async fn find_by_name(
+        &self,
+        user_id: Uuid,
+        name: &str,
+    ) -> anyhow::Result<Option<UtmPreset>> {
+        let preset = sqlx::query_as::<_, UtmPreset>(
+            r#"SELECT id, name, utm_source, utm_medium, utm_campaign, utm_term, utm_content, created_at
+            FROM utm_presets
+            WHERE user_id = $1 AND name = $2"#,
+        )
+        .bind(user_id)
+        .bind(name)
+        .fetch_optional(&self.pg_pool)
+        .await?;
+        Ok(preset)
+    }
+
+    pub async fn create(
+        &self,
+        user_id: Uuid,
+        name: &str,
+        params: &UtmParams,
+    ) -> anyhow::Result<Uuid> {
+        let id = sqlx::query_scalar!(
+            r#"INSERT INTO utm_presets (user_id, name, utm_source, utm_medium, utm_campaign, utm_term, utm_content)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            RETURNING id"#,
+            user_id,
+            name,
+            params.utm_source,
+            params.utm_medium,
+            params.utm_campaign,
+            params.utm_term,
+            params.utm_content
+        )
+        .fetch_one(&self.pg_pool)
+        .await?;
+        Ok(id)
+    }
+
+    pub async fn delete(&self, user_id: Uuid, id: Uuid) -> anyhow::Result<bool> {
+        let result = sqlx::query!(
+            "DELETE FROM utm_presets WHERE id = $2 AND user_id = $1",
+            user_id,
+            id
+        )
+        .execute(&self.pg_pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
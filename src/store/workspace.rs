@@ -0,0 +1,228 @@
+use sqlx::{Pool, Postgres};
+use tracing::instrument;
+use uuid::Uuid;
+
+use crate::models::workspace::{InvitationModel, MemberModel, WorkspaceMembership, WorkspaceRole};
+
+#[derive(Clone, Debug)]
+pub struct WorkspaceRepository {
+    pool: Pool<Postgres>,
+}
+
+impl WorkspaceRepository {
+    pub fn new(pool: Pool<Postgres>) -> Self {
+        Self { pool }
+    }
+
+    /// Create a shared workspace with `owner_id` as its first owner
+    #[instrument(name = "Creating workspace", skip(self))]
+    pub async fn create(&self, name: &str, owner_id: Uuid) -> anyhow::Result<Uuid> {
+        let mut tx = self.pool.begin().await?;
+        let rec = sqlx::query!(
+            "INSERT INTO workspaces (name, created_by) VALUES ($1, $2) RETURNING id",
+            name,
+            owner_id
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        sqlx::query!(
+            "INSERT INTO workspace_members (workspace_id, user_id, role) VALUES ($1, $2, 'owner')",
+            rec.id,
+            owner_id
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(rec.id)
+    }
+
+    pub async fn list_for_user(&self, user_id: Uuid) -> anyhow::Result<Vec<WorkspaceMembership>> {
+        let rows = sqlx::query_as::<_, WorkspaceMembership>(
+            r#"SELECT w.id, w.name, w.personal, m.role
+            FROM workspaces w
+            JOIN workspace_members m ON m.workspace_id = w.id
+            WHERE m.user_id = $1
+            ORDER BY w.personal DESC, w.name"#,
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows)
+    }
+
+    pub async fn membership(
+        &self,
+        workspace_id: Uuid,
+        user_id: Uuid,
+    ) -> anyhow::Result<Option<WorkspaceMembership>> {
+        let row = sqlx::query_as::<_, WorkspaceMembership>(
+            r#"SELECT w.id, w.name, w.personal, m.role
+            FROM workspaces w
+            JOIN workspace_members m ON m.workspace_id = w.id
+            WHERE w.id = $1 AND m.user_id = $2"#,
+        )
+        .bind(workspace_id)
+        .bind(user_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row)
+    }
+
+    pub async fn personal_for_user(
+        &self,
+        user_id: Uuid,
+    ) -> anyhow::Result<Option<WorkspaceMembership>> {
+        let row = sqlx::query_as::<_, WorkspaceMembership>(
+            r#"SELECT w.id, w.name, w.personal, m.role
+            FROM workspaces w
+            JOIN workspace_members m ON m.workspace_id = w.id
+            WHERE w.personal AND w.created_by = $1 AND m.user_id = $1"#,
+        )
+        .bind(user_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row)
+    }
+
+    pub async fn members(&self, workspace_id: Uuid) -> anyhow::Result<Vec<MemberModel>> {
+        let rows = sqlx::query_as::<_, MemberModel>(
+            r#"SELECT m.user_id, u.email, m.role, m.joined_at
+            FROM workspace_members m
+            JOIN users u ON u.id = m.user_id
+            WHERE m.workspace_id = $1
+            ORDER BY m.joined_at"#,
+        )
+        .bind(workspace_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows)
+    }
+
+    pub async fn count_owners(&self, workspace_id: Uuid) -> anyhow::Result<i64> {
+        let rec = sqlx::query!(
+            r#"SELECT COUNT(*) AS "count!" FROM workspace_members WHERE workspace_id = $1 AND role = 'owner'"#,
+            workspace_id
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(rec.count)
+    }
+
+    #[instrument(name = "Updating workspace member role", skip(self))]
+    pub async fn set_member_role(
+        &self,
+        workspace_id: Uuid,
+        user_id: Uuid,
+        role: WorkspaceRole,
+    ) -> anyhow::Result<bool> {
+        let result = sqlx::query!(
+            "UPDATE workspace_members SET role = $3 WHERE workspace_id = $1 AND user_id = $2",
+            workspace_id,
+            user_id,
+            role as WorkspaceRole
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    #[instrument(name = "Removing workspace member", skip(self))]
+    pub async fn remove_member(&self, workspace_id: Uuid, user_id: Uuid) -> anyhow::Result<bool> {
+        let result = sqlx::query!(
+            "DELETE FROM workspace_members WHERE workspace_id = $1 AND user_id = $2",
+            workspace_id,
+            user_id
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    #[instrument(name = "Creating workspace invitation", skip(self, token))]
+    pub async fn create_invitation(
+        &self,
+        workspace_id: Uuid,
+        email: &str,
+        role: WorkspaceRole,
+        token: &str,
+        invited_by: Uuid,
+    ) -> anyhow::Result<()> {
+        sqlx::query!(
+            r#"INSERT INTO workspace_invitations (workspace_id, email, role, token, invited_by)
+            VALUES ($1, $2, $3, $4, $5)"#,
+            workspace_id,
+            email,
+            role as WorkspaceRole,
+            token,
+            invited_by
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn find_invitation(&self, token: &str) -> anyhow::Result<Option<InvitationModel>> {
+        let row = sqlx::query_as::<_, InvitationModel>(
+            r#"SELECT i.id, i.workspace_id, w.name AS workspace_name, i.email, i.role, i.token,
+                i.created_at, i.accepted_at
+            FROM workspace_invitations i
+            JOIN workspaces w ON w.id = i.workspace_id
+            WHERE i.token = $1"#,
+        )
+        .bind(token)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row)
+    }
+
+    pub async fn pending_invitations(
+        &self,
+        workspace_id: Uuid,
+    ) -> anyhow::Result<Vec<InvitationModel>> {
+        let rows = sqlx::query_as::<_, InvitationModel>(
+            r#"SELECT i.id, i.workspace_id, w.name AS workspace_name, i.email, i.role, i.token,
+                i.created_at, i.accepted_at
+            FROM workspace_invitations i
+            JOIN workspaces w ON w.id = i.workspace_id
+            WHERE i.workspace_id = $1 AND i.accepted_at IS NULL
+            ORDER BY i.created_at DESC"#,
+        )
+        .bind(workspace_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows)
+    }
+
+    /// Mark the invitation as used and add the user to the workspace in one go
+    #[instrument(name = "Accepting workspace invitation", skip(self))]
+    pub async fn accept_invitation(
+        &self,
+        invitation_id: Uuid,
+        user_id: Uuid,
+    ) -> anyhow::Result<()> {
+        let mut tx = self.pool.begin().await?;
+        let invitation = sqlx::query!(
+            r#"UPDATE workspace_invitations
+            SET accepted_at = NOW()
+            WHERE id = $1 AND accepted_at IS NULL
+            RETURNING workspace_id, role AS "role: WorkspaceRole""#,
+            invitation_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("invitation already used"))?;
+
+        sqlx::query!(
+            r#"INSERT INTO workspace_members (workspace_id, user_id, role)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (workspace_id, user_id) DO NOTHING"#,
+            invitation.workspace_id,
+            user_id,
+            invitation.role as WorkspaceRole
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+}